@@ -0,0 +1,76 @@
+//! Microbenchmarks of the building blocks of chunking, to catch performance regressions.
+//!
+//! Run with `cargo bench --bench bench --features testing`. `add_file` chunks 100 MB of random
+//! bytes into a fresh directory under `target` on every iteration, stored without compression.
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use file_chunk::test_util::xorshift_bytes;
+use file_chunk::{mod_pow, multiply_mod, Chunker, Compression, RabinFingerprint};
+use std::hint::black_box;
+
+const LEN: usize = 100 * 1024 * 1024;
+const SEED: u64 = 0x2545_F491_4F6C_DD1D;
+/// The prime `RabinFingerprint::new` uses.
+const PRIME: u64 = 1_099_511_627_791;
+const OUTPUT: &str = "./target/bench/add_file";
+
+fn bench_push_byte(c: &mut Criterion) {
+    let bytes = xorshift_bytes(SEED, LEN);
+    let mut group = c.benchmark_group("push_byte");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(LEN as u64));
+    group.bench_function("100MB", |b| {
+        b.iter(|| {
+            let mut fingerprint = RabinFingerprint::new();
+            for &byte in bytes.iter() {
+                fingerprint.push_byte(black_box(byte));
+            }
+            fingerprint.value()
+        })
+    });
+    group.finish();
+}
+
+fn bench_add_file(c: &mut Criterion) {
+    let bytes = xorshift_bytes(SEED, LEN);
+    let mut group = c.benchmark_group("add_file");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(LEN as u64));
+    group.bench_function("100MB", |b| {
+        b.iter_batched(
+            || {
+                // A manifest left by the last iteration would mark the file as unchanged
+                let _ = std::fs::remove_dir_all(OUTPUT);
+                std::fs::create_dir_all(OUTPUT).unwrap();
+                Chunker::builder().per_file_compression(vec![("*".to_string(), Compression::None)]).build()
+            },
+            |chunker| chunker.add_bytes("random.bin", black_box(&bytes), OUTPUT).unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+fn bench_mod_pow(c: &mut Criterion) {
+    c.bench_function("mod_pow x10000", |b| {
+        b.iter(|| {
+            for _ in 0..10_000 {
+                black_box(mod_pow(black_box(256), black_box(64), black_box(PRIME)));
+            }
+        })
+    });
+}
+
+fn bench_multiply_mod(c: &mut Criterion) {
+    c.bench_function("multiply_mod x1000000", |b| {
+        b.iter(|| {
+            let mut value = 1;
+            for i in 0..1_000_000u64 {
+                value = multiply_mod(black_box(value), black_box(i | 1), PRIME);
+            }
+            value
+        })
+    });
+}
+
+criterion_group!(benches, bench_push_byte, bench_add_file, bench_mod_pow, bench_multiply_mod);
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+//! Compares chunking throughput of the Rabin fingerprint and FastCDC on a 100 MB file.
+//!
+//! Run with `cargo bench --bench cdc_throughput`. Chunks are estimated without compression, so
+//! the numbers are reading, boundary detection and hashing only.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use file_chunk::{CdcAlgorithm, Chunker, Compression};
+
+const FILE: &str = "./tests/data/B100MB.bin";
+
+fn cdc_throughput(c: &mut Criterion) {
+    let bytes = std::fs::metadata(FILE).unwrap().len();
+    let mut group = c.benchmark_group("cdc_throughput");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(bytes));
+    let algorithms = [
+        ("rabin", CdcAlgorithm::Rabin),
+        ("fastcdc", CdcAlgorithm::FastCdc { min: 2 * 1024 * 1024, avg: 8 * 1024 * 1024, max: 32 * 1024 * 1024 }),
+    ];
+    for (name, algorithm) in algorithms {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &algorithm, |b, &algorithm| {
+            let chunker = Chunker::builder()
+                .cdc_algorithm(algorithm)
+                .per_file_compression(vec![("*".to_string(), Compression::None)])
+                .build();
+            b.iter(|| chunker.estimate_chunks(&[FILE.to_string()]).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, cdc_throughput);
+criterion_main!(benches);
@@ -0,0 +1,52 @@
+//! Compares reading and mapping a 512 MiB file with a cold page cache.
+//!
+//! Run with `cargo bench --bench mmap_read_ahead`. The file is backed up once, every iteration
+//! then evicts it from the page cache with `POSIX_FADV_DONTNEED` and only reads and hashes it to
+//! find it unchanged. Eviction needs no privileges, unlike dropping all caches, but only works on
+//! Linux and other Unix systems with `posix_fadvise`; elsewhere the cache stays warm.
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use file_chunk::Chunker;
+use std::fs;
+
+const FILE_BYTES: usize = 512 * 1024 * 1024;
+
+#[cfg(target_os = "linux")]
+fn evict_from_page_cache(path: &str) {
+    use std::os::fd::AsRawFd;
+    let file = fs::File::open(path).unwrap();
+    file.sync_all().unwrap();
+    assert_eq!(unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) }, 0);
+}
+
+#[cfg(not(target_os = "linux"))]
+fn evict_from_page_cache(_path: &str) {}
+
+fn mmap_read_ahead(c: &mut Criterion) {
+    let dir = "./tests/tmp/bench_mmap_read_ahead";
+    let path = format!("{}/512MB.bin", dir);
+    let output = format!("{}/chunks", dir);
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(&output).unwrap();
+    let data = fs::read("./tests/data/B100MB.bin").unwrap();
+    fs::write(&path, data.iter().cycle().take(FILE_BYTES).copied().collect::<Vec<u8>>()).unwrap();
+    drop(data);
+    Chunker::new().add_files(vec![path.clone()], &output).unwrap();
+
+    let mut group = c.benchmark_group("mmap_read_ahead");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(FILE_BYTES as u64));
+    for (name, use_mmap) in [("read", false), ("mmap", true)] {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &use_mmap, |b, &use_mmap| {
+            b.iter_batched(
+                || evict_from_page_cache(&path),
+                |_| Chunker::builder().use_mmap(use_mmap).build().add_files(vec![path.clone()], &output).unwrap(),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+    let _ = fs::remove_dir_all(dir);
+}
+
+criterion_group!(benches, mmap_read_ahead);
+criterion_main!(benches);
@@ -0,0 +1,36 @@
+//! Compares `parallel_compression_threads` settings on `tests/data/B100MB.bin`.
+//!
+//! Run with `cargo bench --bench parallel_compression`. Expect a speedup on machines with at
+//! least as many cores as threads; on an 8-core machine 8 threads leave little for reading.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use file_chunk::{Chunker, Compression};
+use std::fs;
+
+fn parallel_compression(c: &mut Criterion) {
+    let path = "./tests/data/B100MB.bin".to_string();
+    let output = "./tests/tmp/bench_parallel_compression";
+    let mut group = c.benchmark_group("parallel_compression");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(fs::metadata(&path).unwrap().len()));
+    for codec in [Compression::Snappy, Compression::Zstd] {
+        for threads in [1, 4, 8] {
+            group.bench_with_input(BenchmarkId::new(format!("{:?}", codec), threads), &threads, |b, &threads| {
+                b.iter(|| {
+                    // A fresh output directory, so no chunk is reused from the previous iteration
+                    let _ = fs::remove_dir_all(output);
+                    fs::create_dir_all(output).unwrap();
+                    Chunker::builder()
+                        .per_file_compression(vec![("*".to_string(), codec)])
+                        .parallel_compression_threads(threads)
+                        .build()
+                        .add_files(vec![path.clone()], output)
+                        .unwrap()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parallel_compression);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+//! Compares serial and parallel BLAKE3 file hashing on a 1 GiB file.
+//!
+//! Run with `cargo bench --bench parallel_hashing`. The file is backed up once, every iteration
+//! then only reads and hashes it to find it unchanged, so chunking is left out of the numbers.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use file_chunk::Chunker;
+use std::fs;
+
+const FILE_BYTES: usize = 1024 * 1024 * 1024;
+
+fn parallel_hashing(c: &mut Criterion) {
+    let dir = "./tests/tmp/bench_parallel_hashing";
+    let path = format!("{}/1GB.bin", dir);
+    let output = format!("{}/chunks", dir);
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(&output).unwrap();
+    let mut data = fs::read("./tests/data/B100MB.bin").unwrap();
+    data = data.iter().cycle().take(FILE_BYTES).copied().collect();
+    fs::write(&path, &data).unwrap();
+    drop(data);
+    Chunker::new().add_files(vec![path.clone()], &output).unwrap();
+
+    let mut group = c.benchmark_group("parallel_hashing");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(FILE_BYTES as u64));
+    for (name, threshold) in [("serial", u64::MAX), ("parallel", 64 * 1024 * 1024)] {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &threshold, |b, &threshold| {
+            b.iter(|| {
+                Chunker::builder()
+                    .parallel_hash_threshold_bytes(threshold)
+                    .build()
+                    .add_files(vec![path.clone()], &output)
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+    let _ = fs::remove_dir_all(dir);
+}
+
+criterion_group!(benches, parallel_hashing);
+criterion_main!(benches);
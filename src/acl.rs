@@ -0,0 +1,33 @@
+use posix_acl::PosixACL;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Returns the access ACL of `path` in its long text form, or `None` if it holds nothing the mode
+/// bits don't.
+pub(crate) fn read_acl(path: &str) -> io::Result<Option<String>> {
+    let acl = PosixACL::read_acl(path).map_err(io::Error::other)?;
+    // Owner, group and other entries are the mode bits, named entries come with a mask
+    if acl.entries().len() <= 3 {
+        return Ok(None);
+    }
+    Ok(Some(acl.as_text()))
+}
+
+/// Replaces the access ACL of `path` with the one in `text`, as returned by [`read_acl`].
+pub(crate) fn write_acl(path: &str, text: &str) -> io::Result<()> {
+    let c_text = CString::new(text).map_err(io::Error::other)?;
+    let c_path = CString::new(Path::new(path).as_os_str().as_bytes()).map_err(io::Error::other)?;
+    let acl = unsafe { acl_sys::acl_from_text(c_text.as_ptr()) };
+    if acl.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let result = unsafe { acl_sys::acl_set_file(c_path.as_ptr(), acl_sys::ACL_TYPE_ACCESS, acl) };
+    let error = io::Error::last_os_error();
+    unsafe { acl_sys::acl_free(acl) };
+    if result != 0 {
+        return Err(error);
+    }
+    Ok(())
+}
@@ -0,0 +1,360 @@
+use crate::chunkstream::{
+    chunk_file_path, chunk_range, read_source, restoring, restoring_chunk, xor_delta, Chunk, ChunkHeader,
+    Chunker, RestoreInformation, SavedChunk,
+};
+use crate::compression::Compression;
+use crate::error::{ChunkError, Result, ResultExt};
+use crate::paths::normalize_path;
+use crate::rate_limit::RateLimiter;
+use crate::stats::ChunkStats;
+use std::future::Future;
+use std::io::{self, Write};
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Async front-end for [`Chunker`], for use inside a tokio runtime.
+///
+/// File and chunk I/O goes through `tokio::fs`, while fingerprinting and compression run on
+/// the blocking thread pool.
+pub struct AsyncChunker {
+    inner: Chunker,
+}
+
+impl From<Chunker> for AsyncChunker {
+    fn from(inner: Chunker) -> Self {
+        AsyncChunker { inner }
+    }
+}
+
+impl AsyncChunker {
+    pub fn new() -> AsyncChunker {
+        AsyncChunker::from(Chunker::new())
+    }
+
+    pub async fn add_files(self, paths: Vec<String>, output_path: &str) -> Result<ChunkStats> {
+        let chunk_dir = output_path.to_string();
+        let prefix = self.inner.options.chunk_name_prefix.clone();
+        self.add_files_to_sink(paths, output_path, move |chunk_name| {
+            let path = chunk_file_path(&chunk_dir, prefix.as_deref(), &chunk_name);
+            async move {
+                tracing::info!(chunk_name = %chunk_name, path = %path, "Saving chunk");
+                // Check if file exists
+                if tokio::fs::try_exists(&path).await? {
+                    return Ok(None);
+                }
+                Ok(Some(tokio::fs::File::create(path).await?))
+            }
+        })
+        .await
+    }
+
+    /// Like [`AsyncChunker::add_files`], but chunk data is written to the sinks returned by
+    /// `open_sink`, which is called with the name of every completed chunk.
+    ///
+    /// Returning `None` from `open_sink` marks the chunk as already stored. The manifest is
+    /// still written to `output_path`.
+    pub async fn add_files_to_sink<F, Fut, W>(
+        self,
+        mut paths: Vec<String>,
+        output_path: &str,
+        mut open_sink: F,
+    ) -> Result<ChunkStats>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = io::Result<Option<W>>>,
+        W: AsyncWrite + Unpin,
+    {
+        let start = std::time::Instant::now();
+        self.inner.check_free_space(output_path)?;
+        paths.sort_unstable();
+        let output = output_path.to_string();
+        let mut chunker = self.inner;
+        chunker.begin_pending(output_path)?;
+        let (mut chunker, sources) = tokio::task::spawn_blocking(move || {
+            let sources = chunker.record_symlinks(paths).and_then(|paths| {
+                let unchanged = chunker.resume_from_manifest(&paths, &output, &mut read_source)?;
+                Ok((paths, unchanged))
+            });
+            (chunker, sources)
+        })
+        .await
+        .map_err(io::Error::other)?;
+        let (paths, unchanged) = sources?;
+        let hash_seed = chunker.options.hash_seed;
+
+        let mut chunk = chunker.first_chunk()?;
+        let mut rate_limiter = chunker.options.rate_limit_bytes_per_sec.map(RateLimiter::new);
+        for path in paths.iter() {
+            let path = normalize_path(path);
+            if unchanged.contains(&path) {
+                tracing::info!(path = %path, "Already chunked");
+                continue;
+            }
+            let mut bytes = vec![];
+            tokio::fs::File::open(&path)
+                .await?
+                .read_to_end(&mut bytes)
+                .await?;
+
+            let (returned_chunker, returned_chunk, completed) = tokio::task::spawn_blocking(move || {
+                let completed = chunker.split_file(&mut chunk, &path, &bytes);
+                (chunker, chunk, completed)
+            })
+            .await
+            .map_err(io::Error::other)?;
+            chunker = returned_chunker;
+            chunk = returned_chunk;
+
+            for full_chunk in completed {
+                chunker.journal_chunk(&full_chunk.name())?;
+                let codec = chunker.chunk_compression(&full_chunk);
+                let saved = save_to_sink(&full_chunk, codec, hash_seed, &mut open_sink).await?;
+                chunker.record_saved_chunk(&full_chunk, &saved);
+                if let Some(limiter) = rate_limiter.as_mut() {
+                    tokio::time::sleep(limiter.consume(saved.compressed_len)).await;
+                }
+            }
+        }
+
+        if let Some(last_chunk) = chunker.seal_last_chunk(chunk) {
+            chunker.journal_chunk(&last_chunk.name())?;
+            let codec = chunker.chunk_compression(&last_chunk);
+            let saved = save_to_sink(&last_chunk, codec, hash_seed, &mut open_sink).await?;
+            chunker.record_saved_chunk(&last_chunk, &saved);
+        }
+
+        let output = output_path.to_string();
+        let stats = tokio::task::spawn_blocking(move || {
+            chunker.dump_restore_info(&output)?;
+            Ok::<_, ChunkError>(chunker.stats)
+        })
+        .await
+        .map_err(io::Error::other)??;
+        Ok(stats.finish(start.elapsed()))
+    }
+
+    pub async fn restore_file(&self, filename: &str, data_path: &str, output_path: &str) -> Result<()> {
+        // Normalize filename to unix path
+        let filename = normalize_path(filename);
+        let (path, compression_ext) = self.inner.restore_target(&filename, output_path);
+        if let Some(parent) = std::path::Path::new(&path).parent() {
+            tokio::fs::create_dir_all(parent).await.with_context(|| restoring(&filename))?;
+        }
+
+        let manifest_path = data_path.to_string();
+        let restore_info = tokio::task::spawn_blocking(move || RestoreInformation::load(&manifest_path))
+            .await
+            .map_err(io::Error::other)
+            .with_context(|| restoring(&filename))?
+            .with_context(|| restoring(&filename))?;
+        self.inner.check_format_version(&restore_info).with_context(|| restoring(&filename))?;
+        self.inner.check_hash_seed(&restore_info).with_context(|| restoring(&filename))?;
+        let file_ranges = restore_info
+            .file_ranges(&filename)
+            .ok_or_else(|| ChunkError::UnknownFile(filename.clone()))
+            .with_context(|| restoring(&filename))?;
+
+        let mut file = tokio::fs::File::create(&path).await.with_context(|| restoring(&filename))?;
+        let mut decoder = OutputDecoder::new(compression_ext);
+        let mut written = 0;
+        for (chunk_name, start_end) in file_ranges.into_iter() {
+            let context = || restoring_chunk(&filename, chunk_name, written);
+            let mut chunk_bytes = vec![];
+            for name in restore_info.delta_chain(chunk_name) {
+                let stored = tokio::fs::read(restore_info.stored_chunk_path(data_path, name))
+                    .await
+                    .with_context(context)?;
+                let stored = ChunkHeader::split(&stored).with_context(context)?.1;
+                let stored = restore_info.chunk_codec(name).decompress(stored).with_context(context)?;
+                chunk_bytes = if restore_info.is_delta(name) { xor_delta(&chunk_bytes, &stored) } else { stored };
+            }
+            let slice = chunk_range(&chunk_bytes, start_end).with_context(context)?;
+            let decoded = decoder.decode(slice).with_context(context)?;
+            file.write_all(&decoded).await.with_context(context)?;
+            written += slice.len() as u64;
+        }
+        let decoded = decoder.finish().with_context(|| restoring(&filename))?;
+        file.write_all(&decoded).await.with_context(|| restoring(&filename))?;
+        file.flush().await.with_context(|| restoring(&filename))?;
+        Ok(())
+    }
+}
+
+impl Default for AsyncChunker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Chunk {
+    /// Writes the compressed chunk to `writer` and returns the BLAKE3 hash and size of what was written.
+    pub(crate) async fn save_to_async_writer<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+        codec: Compression,
+        hash_seed: Option<u64>,
+    ) -> io::Result<SavedChunk> {
+        let (compressed, saved) = self.compress(codec, hash_seed).map_err(io::Error::other)?;
+        writer.write_all(&compressed).await?;
+        writer.shutdown().await?;
+        Ok(saved)
+    }
+}
+
+async fn save_to_sink<F, Fut, W>(
+    chunk: &Chunk,
+    codec: Compression,
+    hash_seed: Option<u64>,
+    open_sink: &mut F,
+) -> io::Result<SavedChunk>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = io::Result<Option<W>>>,
+    W: AsyncWrite + Unpin,
+{
+    match open_sink(chunk.name()).await? {
+        Some(mut writer) => chunk.save_to_async_writer(&mut writer, codec, hash_seed).await,
+        None => {
+            let (_, mut saved) = chunk.compress(codec, hash_seed).map_err(io::Error::other)?;
+            saved.reused = true;
+            Ok(saved)
+        }
+    }
+}
+
+/// In-memory counterpart of the write-side decoders used by the blocking restore path.
+enum OutputDecoder {
+    Plain,
+    Gz(flate2::write::GzDecoder<Vec<u8>>),
+    Bz(bzip2::write::BzDecoder<Vec<u8>>),
+}
+
+impl OutputDecoder {
+    fn new(compression_ext: Option<&str>) -> OutputDecoder {
+        match compression_ext {
+            Some(".gz") => OutputDecoder::Gz(flate2::write::GzDecoder::new(vec![])),
+            Some(".bz2") => OutputDecoder::Bz(bzip2::write::BzDecoder::new(vec![])),
+            _ => OutputDecoder::Plain,
+        }
+    }
+
+    /// Feeds `data` to the decoder and returns whatever output is ready.
+    fn decode(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            OutputDecoder::Plain => Ok(data.to_vec()),
+            OutputDecoder::Gz(decoder) => {
+                decoder.write_all(data)?;
+                Ok(std::mem::take(decoder.get_mut()))
+            }
+            OutputDecoder::Bz(decoder) => {
+                decoder.write_all(data)?;
+                Ok(std::mem::take(decoder.get_mut()))
+            }
+        }
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            OutputDecoder::Plain => Ok(vec![]),
+            OutputDecoder::Gz(decoder) => decoder.finish(),
+            OutputDecoder::Bz(mut decoder) => decoder.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rolling_hash::RabinFingerprint;
+    use std::fs;
+
+    fn fresh_dir(path: &str) -> String {
+        let _ = fs::remove_dir_all(path);
+        fs::create_dir_all(path).unwrap();
+        path.to_string()
+    }
+
+    #[tokio::test]
+    async fn test_async_chunking() {
+        let output = fresh_dir("./tests/tmp/async/chunks");
+        let restored = fresh_dir("./tests/tmp/async/restored");
+        let paths = vec![
+            "./tests/data/A.txt".to_string(),
+            "./tests/data/A2.txt".to_string(),
+            "./tests/data/CSmall.txt".to_string(),
+        ];
+
+        AsyncChunker::new().add_files(paths.clone(), &output).await.unwrap();
+
+        let chunker = AsyncChunker::new();
+        for path in paths.iter() {
+            chunker.restore_file(path, &output, &restored).await.unwrap();
+            let original = fs::read(path).unwrap();
+            let restored = fs::read(format!("{}/{}", restored, path.trim_start_matches("./"))).unwrap();
+            assert_eq!(original, restored);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_restore_auto_decompress() {
+        let source = fresh_dir("./tests/tmp/async_decompress/source");
+        let output = fresh_dir("./tests/tmp/async_decompress/chunks");
+        let restored = fresh_dir("./tests/tmp/async_decompress/restored");
+
+        let original = fs::read("./tests/data/CSmall.txt").unwrap();
+        let gz_path = format!("{}/CSmall.txt.gz", source);
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&original).unwrap();
+        fs::write(&gz_path, encoder.finish().unwrap()).unwrap();
+
+        AsyncChunker::new().add_files(vec![gz_path.clone()], &output).await.unwrap();
+
+        let chunker = AsyncChunker::from(Chunker::builder().auto_decompress(true).build());
+        chunker.restore_file(&gz_path, &output, &restored).await.unwrap();
+        let decompressed =
+            fs::read(format!("{}/{}/CSmall.txt", restored, source.trim_start_matches("./"))).unwrap();
+        assert_eq!(original, decompressed);
+    }
+
+    #[tokio::test]
+    async fn test_save_to_async_writer() {
+        let mut chunk = Chunk::new(RabinFingerprint::new());
+        let original = fs::read("./tests/data/CSmall.txt").unwrap();
+        chunk.add_file("CSmall.txt", 0, &original);
+
+        let mut sink: Vec<u8> = vec![];
+        let saved = chunk.save_to_async_writer(&mut sink, Compression::Snappy, None).await.unwrap();
+        assert_eq!(saved.compressed_len, sink.len() as u64);
+        let (header, compressed) = ChunkHeader::split(&sink).unwrap();
+        assert!(header.is_some());
+        assert_eq!(Compression::Snappy.decompress(compressed).unwrap(), original);
+    }
+
+    #[tokio::test]
+    async fn test_add_files_to_sink() {
+        let output = fresh_dir("./tests/tmp/async_sink/manifest");
+        let upload = fresh_dir("./tests/tmp/async_sink/upload");
+        let restored = fresh_dir("./tests/tmp/async_sink/restored");
+        let paths = vec!["./tests/data/A.txt".to_string(), "./tests/data/CSmall.txt".to_string()];
+
+        let upload_dir = upload.clone();
+        AsyncChunker::new()
+            .add_files_to_sink(paths.clone(), &output, move |chunk_name| {
+                let path = format!("{}/{}.chunk", upload_dir, chunk_name);
+                async move { Ok(Some(tokio::fs::File::create(path).await?)) }
+            })
+            .await
+            .unwrap();
+
+        // Chunks only went to the sink
+        assert_eq!(fs::read_dir(&output).unwrap().count(), 1);
+        fs::copy(format!("{}/restore_info.yaml", output), format!("{}/restore_info.yaml", upload)).unwrap();
+
+        let chunker = AsyncChunker::new();
+        for path in paths.iter() {
+            chunker.restore_file(path, &upload, &restored).await.unwrap();
+            let original = fs::read(path).unwrap();
+            let restored = fs::read(format!("{}/{}", restored, path.trim_start_matches("./"))).unwrap();
+            assert_eq!(original, restored);
+        }
+    }
+}
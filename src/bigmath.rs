@@ -1,8 +1,33 @@
-pub(crate) fn multiply_mod(a: u64, b: u64, modulus: u64) -> u64 {
+/// `a * b mod modulus`, widened to u128 so the product cannot overflow.
+///
+/// This holds for every modulus: both factors are below 2^64, so their product is below 2^128.
+pub fn multiply_mod(a: u64, b: u64, modulus: u64) -> u64 {
     (((a % modulus) as u128 * (b % modulus) as u128) % modulus as u128) as u64
 }
 
-pub(crate) fn mod_pow(mut base: u64, mut exp: u64, modulus: u64) -> u64 {
+/// `a + b mod modulus`, without overflowing even if `a + b` does not fit into a u64.
+pub(crate) fn add_mod(a: u64, b: u64, modulus: u64) -> u64 {
+    let (a, b) = (a % modulus, b % modulus);
+    // a + b >= modulus, written so that neither side can overflow
+    if a >= modulus - b {
+        a - (modulus - b)
+    } else {
+        a + b
+    }
+}
+
+/// `a - b mod modulus`, adding `modulus` first if `a < b`.
+pub(crate) fn sub_mod(a: u64, b: u64, modulus: u64) -> u64 {
+    let (a, b) = (a % modulus, b % modulus);
+    if a >= b {
+        a - b
+    } else {
+        a + (modulus - b)
+    }
+}
+
+/// `base ^ exp mod modulus`, by square-and-multiply.
+pub fn mod_pow(mut base: u64, mut exp: u64, modulus: u64) -> u64 {
     if modulus == 1 {
         return 0;
     }
@@ -17,3 +42,123 @@ pub(crate) fn mod_pow(mut base: u64, mut exp: u64, modulus: u64) -> u64 {
     }
     result
 }
+
+/// Deterministic Miller-Rabin primality test, exact for every `u64`.
+pub fn is_prime(n: u64) -> bool {
+    const WITNESSES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+    if n < 2 {
+        return false;
+    }
+    for &p in WITNESSES.iter() {
+        if n.is_multiple_of(p) {
+            return n == p;
+        }
+    }
+
+    // n - 1 = d * 2^s with d odd
+    let s = (n - 1).trailing_zeros();
+    let d = (n - 1) >> s;
+    'witness: for &a in WITNESSES.iter() {
+        let mut x = mod_pow(a, d, n);
+        if x == 1 || x == n - 1 {
+            continue;
+        }
+        for _ in 1..s {
+            x = multiply_mod(x, x, n);
+            if x == n - 1 {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_prime() {
+        assert!(!is_prime(0));
+        assert!(!is_prime(1));
+        assert!(is_prime(2));
+        assert!(is_prime(3));
+        assert!(!is_prime(4));
+        assert!(is_prime(37));
+        assert!(!is_prime(41 * 43));
+
+        // Known large primes
+        assert!(is_prime(1_099_511_627_791));
+        assert!(is_prime(18_446_744_073_709_551_557)); // Largest prime below 2^64
+        assert!(is_prime((1 << 61) - 1));
+
+        // Known composites, including strong pseudoprimes to small bases
+        assert!(!is_prime(1_099_511_627_793));
+        assert!(!is_prime(3_215_031_751));
+        assert!(!is_prime(3_825_123_056_546_413_051));
+        assert!(!is_prime(u64::MAX));
+        assert!(!is_prime(4_294_967_297)); // 641 * 6700417
+    }
+
+    #[test]
+    fn test_add_sub_mod_near_u64_max() {
+        let m = u64::MAX - 58; // Largest prime below 2^64
+        assert_eq!(add_mod(m - 1, m - 1, m), m - 2);
+        assert_eq!(sub_mod(0, m - 1, m), 1);
+        assert_eq!(sub_mod(5, 3, 7), 2);
+        assert_eq!(sub_mod(3, 5, 7), 5);
+    }
+
+    #[test]
+    fn test_multiply_mod_near_u64_max() {
+        // (-1) * (-1) = 1
+        assert_eq!(multiply_mod(u64::MAX - 1, u64::MAX - 1, u64::MAX), 1);
+        assert_eq!(multiply_mod(u64::MAX, u64::MAX, u64::MAX), 0);
+        assert_eq!(multiply_mod(u64::MAX, u64::MAX, u64::MAX - 1), 1);
+        let m = u64::MAX - 58; // Largest prime below 2^64
+        assert_eq!(multiply_mod(m - 1, 2, m), m - 2);
+        assert_eq!(multiply_mod(1 << 63, 1 << 63, m), mod_pow(2, 126, m));
+    }
+
+    /// `a * b mod modulus` by doubling and adding, slow but without any wider integer.
+    fn multiply_mod_by_doubling(a: u64, mut b: u64, modulus: u64) -> u64 {
+        let (mut result, mut a) = (0, a % modulus);
+        while b > 0 {
+            if b & 1 == 1 {
+                result = add_mod(result, a, modulus);
+            }
+            a = add_mod(a, a, modulus);
+            b >>= 1;
+        }
+        result
+    }
+
+    fn operands() -> impl proptest::strategy::Strategy<Value = (u64, u64, u64)> {
+        use proptest::prelude::*;
+        (2..u64::MAX / 2).prop_flat_map(|m| (0..m, 0..m, Just(m)))
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_add_mod((a, b, m) in operands()) {
+            proptest::prop_assert_eq!(add_mod(a, b, m), (a + b) % m);
+        }
+
+        #[test]
+        fn test_sub_mod((a, b, m) in operands()) {
+            proptest::prop_assert_eq!(sub_mod(a, b, m), (a + m - b) % m);
+        }
+
+        #[test]
+        fn test_multiply_mod_large_modulus(a in proptest::prelude::any::<u64>(), b in proptest::prelude::any::<u64>(), m in (1u64 << 63)..=u64::MAX) {
+            proptest::prop_assert_eq!(multiply_mod(a, b, m), multiply_mod_by_doubling(a, b, m));
+        }
+
+        #[test]
+        fn test_mod_pow(base in proptest::prelude::any::<u64>(), exp in 0..64u64, m in 2..u64::MAX) {
+            let expected = (0..exp).fold(1, |result, _| multiply_mod(result, base, m));
+            proptest::prop_assert_eq!(mod_pow(base, exp, m), expected);
+        }
+    }
+}
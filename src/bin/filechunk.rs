@@ -0,0 +1,194 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use file_chunk::{CdcAlgorithm, ChunkError, Chunker, Compression, RabinFingerprint, Result};
+use std::fs;
+use std::process::ExitCode;
+
+/// Deduplicating, content-defined chunk backups.
+#[derive(Parser)]
+#[command(name = "filechunk", version)]
+struct Cli {
+    /// Seed for the keyed BLAKE3 file and chunk hashes, must match the one the backup was made with
+    #[arg(long, global = true)]
+    hash_seed: Option<u64>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Back up every file below <DIR> into <OUTPUT>
+    Chunk {
+        dir: String,
+        output: String,
+        #[command(flatten)]
+        options: ChunkOptions,
+    },
+    /// Restore every file of the backup in <MANIFEST_DIR> below <OUTPUT>
+    Restore {
+        manifest_dir: String,
+        output: String,
+        /// Decompress `.gz` and `.bz2` files while restoring them
+        #[arg(long)]
+        auto_decompress: bool,
+    },
+    /// List the files of the backup in <MANIFEST_DIR>
+    List { manifest_dir: String },
+    /// Check every chunk of the backup in <MANIFEST_DIR> against its hash and fingerprint
+    Verify { manifest_dir: String },
+    /// Delete chunk files of <MANIFEST_DIR> that its manifest no longer references
+    Gc { manifest_dir: String },
+    /// Print the Rabin fingerprint of the whole file at <PATH>
+    Fingerprint { path: String },
+}
+
+#[derive(Args)]
+struct ChunkOptions {
+    /// Write the manifest Zstd compressed
+    #[arg(long)]
+    compress_manifest: bool,
+    /// Prefix for chunk file names, so several backups can share <OUTPUT>
+    #[arg(long)]
+    prefix: Option<String>,
+    /// Store chunks of changed files as XOR deltas against their previous version
+    #[arg(long)]
+    delta_chunks: bool,
+    /// Prime for the Rabin fingerprint
+    #[arg(long)]
+    fingerprint_prime: Option<u64>,
+    /// Refuse to start with less free space than this on the output filesystem
+    #[arg(long, value_name = "BYTES")]
+    min_free_space: Option<u64>,
+    /// Cap the rate chunk files are written at
+    #[arg(long, value_name = "BYTES_PER_SEC")]
+    rate_limit: Option<u64>,
+    /// Only publish the manifest once every chunk is on disk
+    #[arg(long)]
+    atomic: bool,
+    /// Compress chunks on this many threads
+    #[arg(long, default_value_t = 1)]
+    threads: usize,
+    /// How chunk boundaries are found
+    #[arg(long, value_enum, default_value_t = Cdc::Rabin)]
+    cdc: Cdc,
+    /// Smallest chunk with `--cdc fastcdc`
+    #[arg(long, value_name = "BYTES", default_value_t = 2 * 1024 * 1024)]
+    min_chunk: u64,
+    /// Chunk size `--cdc fastcdc` normalizes around
+    #[arg(long, value_name = "BYTES", default_value_t = 8 * 1024 * 1024)]
+    avg_chunk: u64,
+    /// Largest chunk with `--cdc fastcdc`
+    #[arg(long, value_name = "BYTES", default_value_t = 32 * 1024 * 1024)]
+    max_chunk: u64,
+    /// Codec new chunks are stored with, Snappy by default
+    #[arg(long, value_enum)]
+    compression: Option<Codec>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Cdc {
+    Rabin,
+    Fastcdc,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Codec {
+    None,
+    Snappy,
+    Zstd,
+}
+
+impl From<Codec> for Compression {
+    fn from(codec: Codec) -> Compression {
+        match codec {
+            Codec::None => Compression::None,
+            Codec::Snappy => Compression::Snappy,
+            Codec::Zstd => Compression::Zstd,
+        }
+    }
+}
+
+/// Returns every file below `dir`, not following symlinks.
+fn collect_files(dir: &str, files: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir).map_err(|e| ChunkError::from(e).context(format!("Reading {}", dir)))? {
+        let entry = entry?;
+        let path = format!("{}/{}", dir.trim_end_matches('/'), entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn run(cli: Cli) -> Result<bool> {
+    let builder = Chunker::builder().hash_seed(cli.hash_seed);
+    match cli.command {
+        Command::Chunk { dir, output, options } => {
+            let mut builder = builder
+                .compress_manifest(options.compress_manifest)
+                .chunk_name_prefix(options.prefix)
+                .delta_chunks(options.delta_chunks)
+                .min_free_space_bytes(options.min_free_space)
+                .rate_limit_bytes_per_sec(options.rate_limit)
+                .atomic_backup(options.atomic)
+                .parallel_compression_threads(options.threads);
+            if let Some(prime) = options.fingerprint_prime {
+                builder = builder.fingerprint_prime(prime);
+            }
+            if let Cdc::Fastcdc = options.cdc {
+                builder = builder.cdc_algorithm(CdcAlgorithm::FastCdc {
+                    min: options.min_chunk,
+                    avg: options.avg_chunk,
+                    max: options.max_chunk,
+                });
+            }
+            if let Some(codec) = options.compression {
+                builder = builder.per_file_compression(vec![("*".to_string(), codec.into())]);
+            }
+            let mut files = vec![];
+            collect_files(&dir, &mut files)?;
+            fs::create_dir_all(&output)?;
+            let stats = builder.build().add_files(files, &output)?;
+            println!(
+                "{} file(s), {} chunk(s) written, {} reused, {} bytes stored",
+                stats.files_processed, stats.chunks_written, stats.chunks_reused, stats.bytes_written_compressed
+            );
+        }
+        Command::Restore { manifest_dir, output, auto_decompress } => {
+            builder.auto_decompress(auto_decompress).build().restore_all_files(&manifest_dir, &output)?;
+        }
+        Command::List { manifest_dir } => {
+            for (filename, info) in builder.build().list_files(&manifest_dir)? {
+                println!("{}: {} chunk(s)", filename, info.chunks_count);
+            }
+        }
+        Command::Verify { manifest_dir } => {
+            let failed = builder.build().verify_all(&manifest_dir)?;
+            for chunk_name in failed.iter() {
+                println!("Corrupted chunk: {}", chunk_name);
+            }
+            return Ok(failed.is_empty());
+        }
+        Command::Gc { manifest_dir } => {
+            for path in builder.build().gc(&manifest_dir, None)? {
+                println!("Removed {}", path);
+            }
+        }
+        Command::Fingerprint { path } => {
+            println!("{}", RabinFingerprint::fingerprint_file(&path)?);
+        }
+    }
+    Ok(true)
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,448 @@
+use crate::cdc::{CdcAlgorithm, SplitStrategy};
+use crate::chunkstream::Chunker;
+use crate::clock::Clock;
+use crate::compression::Compression;
+use crate::storage::Storage;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ChunkerOptions {
+    pub(crate) auto_decompress: bool,
+    pub(crate) rate_limit_bytes_per_sec: Option<u64>,
+    pub(crate) compress_manifest: bool,
+    pub(crate) chunk_name_prefix: Option<String>,
+    pub(crate) min_free_space_bytes: Option<u64>,
+    pub(crate) delta_chunks: bool,
+    pub(crate) fingerprint_prime: Option<u64>,
+    pub(crate) pre_sort_by_similarity: bool,
+    pub(crate) hash_seed: Option<u64>,
+    pub(crate) atomic_backup: bool,
+    pub(crate) per_file_compression: Vec<(String, Compression)>,
+    pub(crate) storage: Option<Arc<dyn Storage>>,
+    pub(crate) clock: Option<Arc<dyn Clock>>,
+    pub(crate) restore_cache_bytes: Option<u64>,
+    pub(crate) parallel_hash_threshold_bytes: Option<u64>,
+    pub(crate) parallel_scan_threshold_bytes: Option<u64>,
+    pub(crate) cdc_algorithm: CdcAlgorithm,
+    pub(crate) split_strategy: SplitStrategy,
+    pub(crate) parallel_compression_threads: usize,
+    pub(crate) max_chunks_per_file: Option<usize>,
+    pub(crate) max_manifest_entries: Option<usize>,
+    pub(crate) content_addressable_links: bool,
+    pub(crate) hardlink_identical_chunks: bool,
+    pub(crate) max_memory_per_restore: Option<u64>,
+    pub(crate) min_chunk_bytes: Option<u64>,
+    pub(crate) progress_path: Option<PathBuf>,
+    pub(crate) metrics_path: Option<PathBuf>,
+    pub(crate) manifest_hmac_secret: Option<Vec<u8>>,
+    pub(crate) preserve_xattrs: bool,
+    pub(crate) use_mmap: bool,
+    pub(crate) mirror_paths: Vec<String>,
+    pub(crate) deduplicate_within_file: bool,
+    pub(crate) text_manifest: bool,
+    pub(crate) window_dedup_stats: bool,
+    pub(crate) streaming_compression: bool,
+    pub(crate) prefetch_limit_bytes: Option<u64>,
+    pub(crate) skip_preflight_check: bool,
+    pub(crate) concurrent_chunk_uploads: usize,
+    pub(crate) enable_reflink: bool,
+    pub(crate) compare_and_skip: bool,
+    #[cfg(feature = "perceptual-hash")]
+    pub(crate) perceptual_hash_images: bool,
+    #[cfg(feature = "mime-detection")]
+    pub(crate) detect_mime_types: bool,
+    #[cfg(feature = "encryption")]
+    pub(crate) encrypt_filenames: bool,
+    #[cfg(feature = "encryption")]
+    pub(crate) filename_key: Option<[u8; 32]>,
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) simulate_write_failures: Option<f64>,
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) simulate_read_latency: Option<std::time::Duration>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChunkerBuilder {
+    options: ChunkerOptions,
+}
+
+impl ChunkerBuilder {
+    pub fn new() -> ChunkerBuilder {
+        ChunkerBuilder::default()
+    }
+
+    /// Transparently decompress `.gz` and `.bz2` files when restoring them.
+    pub fn auto_decompress(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.auto_decompress = enabled;
+        self
+    }
+
+    /// Leave the content of a restored file alone if the file is there already with the BLAKE3
+    /// hash the manifest has for it, overwriting it otherwise. Metadata is set either way. Files
+    /// decompressed by `auto_decompress` are always restored.
+    pub fn compare_and_skip(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.compare_and_skip = enabled;
+        self
+    }
+
+    /// Caps the average rate at which chunk files are written during `add_files`.
+    pub fn rate_limit_bytes_per_sec(mut self, limit: Option<u64>) -> ChunkerBuilder {
+        self.options.rate_limit_bytes_per_sec = limit;
+        self
+    }
+
+    /// Write the manifest as Zstd compressed `restore_info.yaml.zst`.
+    pub fn compress_manifest(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.compress_manifest = enabled;
+        self
+    }
+
+    /// Prepends `<prefix>_` to every chunk file name, so several backups can share a directory.
+    pub fn chunk_name_prefix(mut self, prefix: Option<String>) -> ChunkerBuilder {
+        self.options.chunk_name_prefix = prefix;
+        self
+    }
+
+    /// Refuse to start a backup if the output filesystem has less free space than this.
+    pub fn min_free_space_bytes(mut self, bytes: Option<u64>) -> ChunkerBuilder {
+        self.options.min_free_space_bytes = bytes;
+        self
+    }
+
+    /// Store chunks of changed files as XOR deltas against their previous version, when smaller.
+    ///
+    /// Only applies to the blocking `add_files` and `add_file_map`.
+    pub fn delta_chunks(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.delta_chunks = enabled;
+        self
+    }
+
+    /// Split chunks with a fingerprint over `prime` instead of the default one.
+    ///
+    /// `add_files` fails with [`ChunkError::InvalidPrime`](crate::ChunkError::InvalidPrime) if it is not prime.
+    pub fn fingerprint_prime(mut self, prime: u64) -> ChunkerBuilder {
+        self.options.fingerprint_prime = Some(prime);
+        self
+    }
+
+    /// Chunk files grouped by content similarity, estimated with MinHash, instead of by path.
+    ///
+    /// Similar files then tend to share chunks, where Snappy compresses their common content
+    /// together. Expect savings close to what compressing each group of similar files as one
+    /// archive gives, and none for unrelated files. Files are read twice, and only the blocking
+    /// `add_files` and `add_file_map` apply it.
+    pub fn pre_sort_by_similarity(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.pre_sort_by_similarity = enabled;
+        self
+    }
+
+    /// Key the BLAKE3 file and chunk hashes with this seed. It is stored in the manifest, and
+    /// later runs and restores with a different seed are refused.
+    pub fn hash_seed(mut self, seed: Option<u64>) -> ChunkerBuilder {
+        self.options.hash_seed = seed;
+        self
+    }
+
+    /// Journal the chunks of a run in `restore_info.yaml.pending` and only rename it to the final
+    /// manifest once every chunk is synced to disk, so a visible manifest is always complete.
+    pub fn atomic_backup(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.atomic_backup = enabled;
+        self
+    }
+
+    /// Compress chunks with the codec of the first glob pattern matching their file, instead of Snappy.
+    ///
+    /// Patterns without a `/` match the file name, others the whole path. A chunk holding several
+    /// files follows the file with the most bytes in it, and keeps its codec in later runs.
+    pub fn per_file_compression(mut self, patterns: Vec<(String, Compression)>) -> ChunkerBuilder {
+        self.options.per_file_compression = patterns;
+        self
+    }
+
+    /// Keep chunks and the manifest in `storage` instead of the local output directory, whose
+    /// path is then ignored.
+    ///
+    /// Only applies to the blocking `Chunker`. The manifest history, `atomic_backup`,
+    /// `min_free_space_bytes`, `gc` and `verify_chain` keep working on local directories only.
+    pub fn storage(mut self, storage: impl Storage + 'static) -> ChunkerBuilder {
+        self.options.storage = Some(Arc::new(storage));
+        self
+    }
+
+    /// Go by the time `clock` tells instead of the system's, for the creation times of chunks
+    /// and the cutoff of `gc`.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> ChunkerBuilder {
+        self.options.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Keep up to this many bytes of decompressed chunks in memory, so chunks shared by several
+    /// restored files are read once. Defaults to 64 MiB, zero disables the cache.
+    pub fn restore_cache_bytes(mut self, bytes: u64) -> ChunkerBuilder {
+        self.options.restore_cache_bytes = Some(bytes);
+        self
+    }
+
+    /// Hash files of at least this many bytes on every core, through BLAKE3's rayon support.
+    /// Defaults to 64 MiB. The hashes are the same either way.
+    pub fn parallel_hash_threshold_bytes(mut self, bytes: u64) -> ChunkerBuilder {
+        self.options.parallel_hash_threshold_bytes = Some(bytes);
+        self
+    }
+
+    /// Find the [`CdcAlgorithm::FastCdc`] boundaries of files of at least this many bytes on
+    /// every core, splitting them into a segment per thread. Defaults to 64 MiB. The chunks are
+    /// the same either way. Has no effect with [`CdcAlgorithm::Rabin`], where a chunk's
+    /// fingerprint covers all of it, so it cannot be known before the previous chunk ends.
+    pub fn parallel_scan_threshold_bytes(mut self, bytes: u64) -> ChunkerBuilder {
+        self.options.parallel_scan_threshold_bytes = Some(bytes);
+        self
+    }
+
+    /// How chunk boundaries are found, Rabin fingerprint boundaries by default.
+    ///
+    /// Invalid [`CdcAlgorithm::FastCdc`] sizes make `add_files` fail.
+    pub fn cdc_algorithm(mut self, algorithm: CdcAlgorithm) -> ChunkerBuilder {
+        self.options.cdc_algorithm = algorithm;
+        self
+    }
+
+    /// Where [`CdcAlgorithm::Rabin`] ends chunks, only at fingerprint boundaries by default.
+    ///
+    /// Invalid [`SplitStrategy`] sizes make `add_files` fail. Has no effect with
+    /// [`CdcAlgorithm::FastCdc`], which bounds chunk sizes itself.
+    pub fn split_strategy(mut self, strategy: SplitStrategy) -> ChunkerBuilder {
+        self.options.split_strategy = strategy;
+        self
+    }
+
+    /// Compress chunks on this many worker threads while the next ones are being read. Chunks are
+    /// still written in order, from the calling thread. Zero or one compresses on the calling thread.
+    ///
+    /// Applies to the blocking `add_files`, `add_file_map` and `add_bytes`.
+    pub fn parallel_compression_threads(mut self, threads: usize) -> ChunkerBuilder {
+        self.options.parallel_compression_threads = threads;
+        self
+    }
+
+    /// Stop looking for chunk boundaries in a file once it hit this many, and put the rest of it
+    /// into a single chunk. Guards against files that would split into a huge number of chunks.
+    pub fn max_chunks_per_file(mut self, max: Option<usize>) -> ChunkerBuilder {
+        self.options.max_chunks_per_file = max;
+        self
+    }
+
+    /// Chunk at most this many files in one run, the first ones in path order, and leave the
+    /// rest unread. The manifest is written for the files chunked so far and the others are
+    /// listed in [`ChunkStats::deferred_files`](crate::ChunkStats::deferred_files), to be added
+    /// by another run into the same output path, which keeps the files already there. Bounds the
+    /// memory a run over a huge tree takes.
+    pub fn max_manifest_entries(mut self, max: Option<usize>) -> ChunkerBuilder {
+        self.options.max_manifest_entries = max;
+        self
+    }
+
+    /// Hard link new chunk files to existing ones with the same decompressed content, such as
+    /// chunks of another backup in the output directory using a different `chunk_name_prefix`
+    /// or `fingerprint_prime`. Chunk files are looked up by the BLAKE3 hash of their content in
+    /// `ca_index.yaml` next to the manifest, which also lists the links. A link keeps the header
+    /// of the file it links to, so [`Chunker::rebuild_manifest`](crate::Chunker::rebuild_manifest)
+    /// recovers nothing from it.
+    ///
+    /// Applies to the blocking `add_files`, `add_file_map` and `add_bytes` on local directories.
+    pub fn content_addressable_links(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.content_addressable_links = enabled;
+        self
+    }
+
+    /// Like `content_addressable_links`, but chunk files missing from `ca_index.yaml`, such as
+    /// those of backups made without either option, are decompressed and hashed into it when a
+    /// run starts, so new chunks are linked to them as well. Every chunk file is hashed once.
+    /// Links only work within one filesystem, where they fail the chunk is written instead.
+    ///
+    /// Applies to the blocking `add_files`, `add_file_map` and `add_bytes` on local directories.
+    pub fn hardlink_identical_chunks(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.hardlink_identical_chunks = enabled;
+        self
+    }
+
+    /// Restore chunks whose stored and decompressed size add up to more than this many bytes
+    /// by decompressing them as they are written, instead of all at once. Such chunks skip
+    /// the restore cache. Delta chunks are always rebuilt in memory.
+    pub fn max_memory_per_restore(mut self, bytes: Option<u64>) -> ChunkerBuilder {
+        self.options.max_memory_per_restore = bytes;
+        self
+    }
+
+    /// Fall back to chunks of this many bytes for the rest of a file, once its first ten Rabin
+    /// chunks average less than half of it. Data like long runs of zeros otherwise ends a Rabin
+    /// chunk after nearly every byte. Has no effect with [`CdcAlgorithm::FastCdc`].
+    pub fn min_chunk_bytes(mut self, bytes: Option<u64>) -> ChunkerBuilder {
+        self.options.min_chunk_bytes = bytes;
+        self
+    }
+
+    /// Append a line of JSON with the [`Progress`](crate::Progress) of the run to this file after
+    /// every file, so it can be followed with `tail -f`. The file is truncated when a run starts.
+    pub fn progress_path(mut self, path: Option<PathBuf>) -> ChunkerBuilder {
+        self.options.progress_path = path;
+        self
+    }
+
+    /// Write the [`ChunkStats`](crate::ChunkStats) of every run to this file in the Prometheus
+    /// text format, for the textfile collector of the node exporter. Samples are labeled with
+    /// job `filechunk` and the output path as instance.
+    pub fn metrics_path(mut self, path: Option<PathBuf>) -> ChunkerBuilder {
+        self.options.metrics_path = path;
+        self
+    }
+
+    /// Sign every manifest written to a local directory with HMAC-SHA256 under this shared
+    /// secret, stored hex encoded in `manifest.sig` next to it. See [`Chunker::verify_hmac`].
+    pub fn manifest_hmac_secret(mut self, secret: Option<Vec<u8>>) -> ChunkerBuilder {
+        self.options.manifest_hmac_secret = secret;
+        self
+    }
+
+    /// Record the extended attributes of every file in the manifest during `add_files`, and set
+    /// them again on restore. Only Linux and macOS have extended attributes.
+    pub fn preserve_xattrs(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.preserve_xattrs = enabled;
+        self
+    }
+
+    /// Also write every chunk and the manifest to these directories, so each is a complete
+    /// backup of its own. Failing to write to a directory only logs a warning, as long as one
+    /// of them took the chunk. Restores read every chunk from the first directory that has it.
+    /// Ignored with custom `storage`.
+    pub fn mirror_paths(mut self, paths: Vec<String>) -> ChunkerBuilder {
+        self.options.mirror_paths = paths;
+        self
+    }
+
+    /// Store a chunk that repeats an earlier chunk of the same file only once, as a reference
+    /// to the earlier one. Chunks are compared by their BLAKE3 hash, and only those holding
+    /// nothing but content of the file, which leaves out the one it ends in.
+    pub fn deduplicate_within_file(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.deduplicate_within_file = enabled;
+        self
+    }
+
+    /// Also write `MANIFEST.txt` next to the manifest, listing the path, size, BLAKE3 hash and
+    /// modification time of every file on a tab-separated line, for reading without this crate.
+    /// Restores don't use it. Ignored with custom `storage`.
+    pub fn text_manifest(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.text_manifest = enabled;
+        self
+    }
+
+    /// Compress chunks stored with Zstd, see `per_file_compression`, straight into their chunk
+    /// file instead of into memory first. This avoids the compressed copy of each chunk; the
+    /// chunk itself is still read into memory whole. Only applies without custom `storage`,
+    /// mirrors, `delta_chunks` and `parallel_compression_threads`, which need the compressed bytes.
+    pub fn streaming_compression(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.streaming_compression = enabled;
+        self
+    }
+
+    /// Read at most this many bytes ahead in `add_files_parallel_io`, 1 GiB by default. A file
+    /// larger than that is read on its own.
+    pub fn prefetch_limit_bytes(mut self, limit: u64) -> ChunkerBuilder {
+        self.options.prefetch_limit_bytes = Some(limit);
+        self
+    }
+
+    /// Clone chunk files as reflinks instead of copying their bytes, where they are copied
+    /// within one btrfs or XFS filesystem: to the `mirror_paths` and by `merge`. Falls back to a
+    /// regular copy where cloning fails, such as across filesystems. Linux only.
+    pub fn enable_reflink(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.enable_reflink = enabled;
+        self
+    }
+
+    /// Write up to this many chunks at once to a storage set through [`storage`](Self::storage),
+    /// such as an object store, instead of one after the other. Chunks are
+    /// handed to the uploads in the order they are cut, and the manifest is written once all of
+    /// them are stored. Values of 0 and 1 upload serially.
+    pub fn concurrent_chunk_uploads(mut self, uploads: usize) -> ChunkerBuilder {
+        self.options.concurrent_chunk_uploads = uploads;
+        self
+    }
+
+    /// Look up every input file of `add_files` before writing anything, failing with
+    /// [`ChunkError::PreflightFailure`](crate::ChunkError::PreflightFailure) listing all those that
+    /// cannot be read. On by default.
+    pub fn preflight_check(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.skip_preflight_check = !enabled;
+        self
+    }
+
+    /// Count how many of the 64 byte windows of the chunked files repeat within a run, in
+    /// [`ChunkStats::window_dedup`](crate::ChunkStats::window_dedup). For analytics only, chunks
+    /// are cut and stored the same either way. Keeps an entry for every distinct window, so
+    /// memory grows with the bytes read.
+    pub fn window_dedup_stats(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.window_dedup_stats = enabled;
+        self
+    }
+
+    /// Map input files into memory during `add_files` instead of reading them, advising the kernel
+    /// with `MADV_SEQUENTIAL` to read ahead. Only on Unix; files must not be truncated meanwhile.
+    pub fn use_mmap(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.use_mmap = enabled;
+        self
+    }
+
+    /// Record a perceptual hash of every `.jpg`, `.jpeg`, `.png` and `.webp` file in the
+    /// manifest during `add_files`, for [`Chunker::find_similar_images`].
+    #[cfg(feature = "perceptual-hash")]
+    pub fn perceptual_hash_images(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.perceptual_hash_images = enabled;
+        self
+    }
+
+    /// Detect the MIME type of every file from its magic bytes during `add_files` and record it
+    /// in the manifest. Chunks of the files whose content is compressed already, such as
+    /// `video/mp4` or `application/zip`, are stored without compression, whatever their
+    /// extension or `per_file_compression` says.
+    #[cfg(feature = "mime-detection")]
+    pub fn detect_mime_types(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.detect_mime_types = enabled;
+        self
+    }
+
+    /// Store every path in the manifest and the chunk headers encrypted with AES-256-SIV under
+    /// the `filename_key`, one path component at a time. Requires a `filename_key`.
+    #[cfg(feature = "encryption")]
+    pub fn encrypt_filenames(mut self, enabled: bool) -> ChunkerBuilder {
+        self.options.encrypt_filenames = enabled;
+        self
+    }
+
+    /// Master key the paths of manifests are encrypted with. Manifests with encrypted paths are
+    /// decrypted with it when they are read, so files can be restored by their plain paths.
+    #[cfg(feature = "encryption")]
+    pub fn filename_key(mut self, key: [u8; 32]) -> ChunkerBuilder {
+        self.options.filename_key = Some(key);
+        self
+    }
+
+    /// Fail saving a chunk with the given probability, to exercise error handling.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn simulate_write_failures(mut self, probability: Option<f64>) -> ChunkerBuilder {
+        self.options.simulate_write_failures = probability;
+        self
+    }
+
+    /// Wait this long before reading every file in `add_files`, to simulate a slow file system.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn simulate_read_latency(mut self, latency: Option<std::time::Duration>) -> ChunkerBuilder {
+        self.options.simulate_read_latency = latency;
+        self
+    }
+
+    pub fn build(self) -> Chunker {
+        Chunker::with_options(self.options)
+    }
+}
@@ -0,0 +1,323 @@
+use crate::error::{ChunkError, Result};
+use crate::rolling_hash::GearHash;
+use rayon::prelude::*;
+
+/// How chunk boundaries are found.
+///
+/// Chunks are named by their Rabin fingerprint either way, only where they end differs.
+/// On the 100 MiB of random data in `tests/data/B100MB.bin`, `Rabin` cuts 12 chunks of 0.2 to
+/// 19.9 MiB, three of them below 4 MiB, as its sizes are spread geometrically without bounds.
+/// `FastCdc { min: 2 MiB, avg: 8 MiB, max: 32 MiB }` cuts 10 chunks of 3.5 to 16.0 MiB, closer
+/// together around a mean of 10 MiB: skipping the first `min` bytes of every chunk shifts the
+/// mean above `avg`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CdcAlgorithm {
+    /// Ends a chunk wherever its Rabin fingerprint is a multiple of 10 MiB.
+    #[default]
+    Rabin,
+    /// FastCDC with a gear hash and normalized chunking at level 2.
+    ///
+    /// Chunks are at least `min` and at most `max` bytes long, `avg` is the size they are
+    /// normalized around. Requires `0 < min <= avg <= max`.
+    FastCdc { min: u64, avg: u64, max: u64 },
+}
+
+impl CdcAlgorithm {
+    /// Returns the boundary detector for a new chunk, `None` for Rabin.
+    pub(crate) fn cutter(&self) -> Result<Option<FastCdc>> {
+        match *self {
+            CdcAlgorithm::Rabin => Ok(None),
+            CdcAlgorithm::FastCdc { min, avg, max } => FastCdc::new(min, avg, max).map(Some),
+        }
+    }
+}
+
+/// Where [`CdcAlgorithm::Rabin`] ends chunks, see [`ChunkerBuilder::split_strategy`](crate::ChunkerBuilder::split_strategy).
+///
+/// Boundaries found by the fingerprint depend on the content only, so an insertion into a file
+/// shifts them along with it and the chunks after it are stored once. Cutting at fixed sizes
+/// makes chunk sizes predictable, but after an insertion every later chunk of the file differs
+/// and is stored again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SplitStrategy {
+    /// Ends a chunk every `max` bytes, whatever its fingerprint. Chunks are all `max` bytes
+    /// long, but deduplicate only content that sits at the same offset. Requires `0 < max`.
+    MaxFileSize { max: u64 },
+    /// Ends a chunk where its fingerprint is a multiple of 10 MiB only. Best deduplication, but
+    /// chunk sizes are spread geometrically without bounds.
+    #[default]
+    BoundaryOnly,
+    /// Ends a chunk at a fingerprint boundary once it is `min` bytes long, or else at `max`
+    /// bytes. Boundaries at which the chunk is shorter than `min` are skipped. Fingerprint
+    /// boundaries are 10 MiB apart on average, so only a window reaching well past that ends most
+    /// chunks at them, smaller ones behave much like `MaxFileSize`. Requires `0 < min <= max`.
+    Hybrid { min: u64, max: u64 },
+}
+
+impl SplitStrategy {
+    /// Returns the smallest and largest size of a chunk, `None` if it is not bounded.
+    pub(crate) fn bounds(&self) -> Result<Option<(u64, u64)>> {
+        let (min, max) = match *self {
+            SplitStrategy::BoundaryOnly => return Ok(None),
+            SplitStrategy::MaxFileSize { max } => (max, max),
+            SplitStrategy::Hybrid { min, max } => (min, max),
+        };
+        if min == 0 || min > max {
+            return Err(ChunkError::InvalidSplitSizes { min, max });
+        }
+        Ok(Some((min, max)))
+    }
+}
+
+/// Bytes a gear hash depends on, older ones are shifted out of it.
+const GEAR_WINDOW: usize = 64;
+
+/// Mask of the `bits` highest bits, which depend on the last 64 bytes of a gear hash.
+fn top_bits(bits: u32) -> u64 {
+    match bits {
+        0 => 0,
+        bits => u64::MAX << (64 - bits.min(64)),
+    }
+}
+
+/// FastCDC boundary detector for a single chunk.
+#[derive(Debug, Clone)]
+pub(crate) struct FastCdc {
+    hash: GearHash,
+    len: u64,
+    min: u64,
+    avg: u64,
+    max: u64,
+    /// Harder to match, used before `avg` to make small chunks less likely.
+    mask_small: u64,
+    /// Easier to match, used after `avg` to make large chunks less likely.
+    mask_large: u64,
+}
+
+impl FastCdc {
+    pub(crate) fn new(min: u64, avg: u64, max: u64) -> Result<FastCdc> {
+        if min == 0 || min > avg || avg > max {
+            return Err(ChunkError::InvalidChunkSizes { min, avg, max });
+        }
+        let bits = avg.ilog2();
+        Ok(FastCdc {
+            hash: GearHash::new(),
+            len: 0,
+            min,
+            avg,
+            max,
+            mask_small: top_bits(bits + 2),
+            mask_large: top_bits(bits.saturating_sub(2)),
+        })
+    }
+
+    /// Feeds the next byte of the chunk, returning whether the chunk ends after it.
+    pub(crate) fn push(&mut self, byte: u8) -> bool {
+        self.hash.push_byte(byte);
+        self.len += 1;
+        if self.len < self.min {
+            return false;
+        }
+        if self.len >= self.max {
+            return true;
+        }
+        let mask = if self.len < self.avg {
+            self.mask_small
+        } else {
+            self.mask_large
+        };
+        self.hash.value() & mask == 0
+    }
+
+    /// Returns the detector for the next chunk.
+    pub(crate) fn reset(&self) -> FastCdc {
+        FastCdc {
+            hash: GearHash::new(),
+            len: 0,
+            ..*self
+        }
+    }
+
+    /// Returns where chunks end in `bytes`, same as pushing them one by one from the current
+    /// state and starting over after every boundary. The bytes after the last boundary are left
+    /// for the caller to push.
+    ///
+    /// Segments of `segment_len` bytes are hashed on separate threads. Once a chunk is
+    /// `GEAR_WINDOW` bytes long its hash no longer depends on where the chunk started, so only
+    /// the first bytes of every chunk are pushed here, the rest comes down to looking up the
+    /// hashes that matched in the segments.
+    pub(crate) fn boundaries(&self, bytes: &[u8], segment_len: usize) -> Vec<usize> {
+        let matches = self.matches(bytes, segment_len.max(GEAR_WINDOW));
+        let mut ends = vec![];
+        let mut cutter = self.clone();
+        let mut start = 0;
+        'chunks: while start < bytes.len() {
+            let hashed = (start + GEAR_WINDOW - 1).min(bytes.len());
+            for (i, &byte) in bytes[start..hashed].iter().enumerate() {
+                if cutter.push(byte) {
+                    start += i + 1;
+                    ends.push(start);
+                    cutter = cutter.reset();
+                    continue 'chunks;
+                }
+            }
+            // Offset of the byte that makes the chunk `len` bytes long
+            let at_len = |len: u64| hashed as u64 + len.max(cutter.len + 1) - cutter.len - 1;
+            let (min, avg, max) = (at_len(self.min), at_len(self.avg), at_len(self.max));
+            let first = matches.partition_point(|&(i, _)| (i as u64) < min);
+            let end = matches[first..]
+                .iter()
+                .take_while(|&&(i, _)| (i as u64) < max)
+                .find(|&&(i, small)| small || i as u64 >= avg)
+                .map_or(max, |&(i, _)| i as u64)
+                + 1;
+            if end > bytes.len() as u64 {
+                break;
+            }
+            start = end as usize;
+            ends.push(start);
+            cutter = cutter.reset();
+        }
+        ends
+    }
+
+    /// Returns the offsets in `bytes` where the hash of the `GEAR_WINDOW` bytes up to them
+    /// matches `mask_large`, and whether it matches `mask_small` as well.
+    fn matches(&self, bytes: &[u8], segment_len: usize) -> Vec<(usize, bool)> {
+        let mut segments: Vec<(usize, Vec<(usize, bool)>)> = rayon::iter::split(0..bytes.len(), |range| {
+            if range.len() <= segment_len {
+                return (range, None);
+            }
+            let middle = range.start + range.len() / 2;
+            (range.start..middle, Some(middle..range.end))
+        })
+        .map(|range| {
+            let mut hash = GearHash::new();
+            let mut found = vec![];
+            // Hashing starts early enough for the first offset to see a full window
+            let hashed_from = range.start.saturating_sub(GEAR_WINDOW - 1);
+            for (i, &byte) in (hashed_from..range.end).zip(&bytes[hashed_from..range.end]) {
+                hash.push_byte(byte);
+                if i >= range.start && hash.value() & self.mask_large == 0 {
+                    found.push((i, hash.value() & self.mask_small == 0));
+                }
+            }
+            (range.start, found)
+        })
+        .collect();
+        segments.sort_unstable_by_key(|&(start, _)| start);
+        segments.into_iter().flat_map(|(_, found)| found).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::xorshift_bytes;
+
+    /// Returns the sizes of the chunks `data` is split into.
+    fn chunk_sizes(mut cutter: FastCdc, data: &[u8]) -> Vec<u64> {
+        let mut sizes = vec![];
+        let mut len = 0;
+        for &byte in data.iter() {
+            len += 1;
+            if cutter.push(byte) {
+                sizes.push(len);
+                len = 0;
+                cutter = cutter.reset();
+            }
+        }
+        if len > 0 {
+            sizes.push(len);
+        }
+        sizes
+    }
+
+    #[test]
+    fn test_fast_cdc_respects_bounds() {
+        let data = xorshift_bytes(0x1234_5678_9ABC_DEF1, 4 * 1024 * 1024);
+        let sizes = chunk_sizes(
+            FastCdc::new(16 * 1024, 64 * 1024, 256 * 1024).unwrap(),
+            &data,
+        );
+        let (last, full) = sizes.split_last().unwrap();
+        assert!(
+            full.iter()
+                .all(|&size| (16 * 1024..=256 * 1024).contains(&size)),
+            "{:?}",
+            sizes
+        );
+        assert!(*last <= 256 * 1024);
+        let average = data.len() as u64 / sizes.len() as u64;
+        assert!(
+            (32 * 1024..=128 * 1024).contains(&average),
+            "average chunk size: {}",
+            average
+        );
+    }
+
+    #[test]
+    fn test_fast_cdc_boundaries_survive_insertions() {
+        let data = xorshift_bytes(0x1234_5678_9ABC_DEF1, 2 * 1024 * 1024);
+        let mut shifted = b"inserted".to_vec();
+        shifted.extend_from_slice(&data);
+        let cutter = FastCdc::new(8 * 1024, 32 * 1024, 128 * 1024).unwrap();
+        let original = chunk_sizes(cutter.clone(), &data);
+        let moved = chunk_sizes(cutter, &shifted);
+        // Apart from the first chunks, boundaries realign with the content
+        assert_eq!(original[original.len() - 3..], moved[moved.len() - 3..]);
+    }
+
+    /// Returns the boundaries pushing every byte of `data` finds.
+    fn pushed_boundaries(mut cutter: FastCdc, data: &[u8]) -> Vec<usize> {
+        let mut ends = vec![];
+        for (i, &byte) in data.iter().enumerate() {
+            if cutter.push(byte) {
+                ends.push(i + 1);
+                cutter = cutter.reset();
+            }
+        }
+        ends
+    }
+
+    #[test]
+    fn test_boundaries_match_pushing_every_byte() {
+        let data = xorshift_bytes(0x1234_5678_9ABC_DEF1, 1024 * 1024);
+        for (min, avg, max) in [(4096, 16 * 1024, 64 * 1024), (16, 64, 256), (1, 8, 8), (100, 100, 100)] {
+            let cutter = FastCdc::new(min, avg, max).unwrap();
+            let expected = pushed_boundaries(cutter.clone(), &data);
+            for segment_len in [1, 1000, 64 * 1024, data.len()] {
+                assert_eq!(cutter.boundaries(&data, segment_len), expected, "{} {} {} {}", min, avg, max, segment_len);
+            }
+        }
+
+        // A chunk carried over from an earlier file goes on from where it was
+        let mut carried = FastCdc::new(4096, 16 * 1024, 64 * 1024).unwrap();
+        for &byte in data[..1000].iter() {
+            assert!(!carried.push(byte));
+        }
+        let expected: Vec<usize> = pushed_boundaries(FastCdc::new(4096, 16 * 1024, 64 * 1024).unwrap(), &data)
+            .into_iter()
+            .map(|end| end - 1000)
+            .collect();
+        assert_eq!(carried.boundaries(&data[1000..], 64 * 1024), expected);
+    }
+
+    #[test]
+    fn test_invalid_sizes() {
+        assert!(FastCdc::new(0, 8, 16).is_err());
+        assert!(FastCdc::new(16, 8, 32).is_err());
+        assert!(FastCdc::new(8, 32, 16).is_err());
+        assert!(CdcAlgorithm::Rabin.cutter().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_split_strategy_bounds() {
+        assert_eq!(SplitStrategy::BoundaryOnly.bounds().unwrap(), None);
+        assert_eq!(SplitStrategy::MaxFileSize { max: 8 }.bounds().unwrap(), Some((8, 8)));
+        assert_eq!(SplitStrategy::Hybrid { min: 4, max: 8 }.bounds().unwrap(), Some((4, 8)));
+        assert!(SplitStrategy::MaxFileSize { max: 0 }.bounds().is_err());
+        assert!(SplitStrategy::Hybrid { min: 0, max: 8 }.bounds().is_err());
+        assert!(SplitStrategy::Hybrid { min: 16, max: 8 }.bounds().is_err());
+    }
+}
@@ -1,354 +1,6561 @@
-use crate::rolling_hash::RabinFingerprint;
+use crate::builder::{ChunkerBuilder, ChunkerOptions};
+use crate::cdc::{CdcAlgorithm, FastCdc};
+use crate::clock::{unix_secs, Clock, SystemClock};
+use crate::compression::{glob_matches, Compression};
+use crate::compression_pool::{CompressedChunk, CompressionPool};
+use crate::disk_space::available_space;
+use crate::prefetch::Prefetcher;
+use crate::progress::ProgressLog;
+use crate::error::{ChunkError, Result, ResultExt};
+use crate::paths::normalize_path;
+use crate::rate_limit::RateLimiter;
+use crate::rolling_hash::{RabinFingerprint, WINDOW_SIZE};
+use crate::similarity;
+use crate::stats::{ChunkEstimate, ChunkStats, DedupReport, ManifestDiff, ManifestStats, SharedChunk, TruncatedFile};
+use crate::storage::{is_zstd, LocalStorage, MirroredStorage, NullStorage, Storage};
+use crate::text_manifest::{TextManifestEntry, TEXT_MANIFEST_FILE};
+use crate::upload_queue::UploadQueue;
+use hmac::{KeyInit, Mac};
 use indexmap::IndexMap;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 const CHUNK_MODULUS: u64 = 1024 * 1024 * 10;
+/// Rabin chunks of a file looked at before falling back to fixed-size chunks, see
+/// [`ChunkerBuilder::min_chunk_bytes`].
+const FALLBACK_AFTER_CHUNKS: u64 = 10;
+const MANIFEST_ZSTD_LEVEL: i32 = 3;
+/// Semver of the manifest format. Manifests of another major version are not restored from.
+pub(crate) const MANIFEST_FORMAT_VERSION: &str = "1.0.0";
+/// Supported manifest formats, in the order they are looked for.
+const MANIFEST_EXTENSIONS: [&str; 3] = ["yaml", "yaml.zst", "json.zst"];
+/// Directory inside the output path that manifests replaced by a later run are kept in.
+const MANIFEST_HISTORY_DIR: &str = "manifests";
+/// Directory inside the output path that the manifests of named snapshots are copied to.
+const SNAPSHOT_DIR: &str = "snapshots";
+/// Decompressed chunks kept in memory while restoring, unless set through `restore_cache_bytes`.
+const DEFAULT_RESTORE_CACHE_BYTES: u64 = 64 * 1024 * 1024;
+/// Files hashed on every core, unless set through `parallel_hash_threshold_bytes`.
+const DEFAULT_PARALLEL_HASH_THRESHOLD_BYTES: u64 = 64 * 1024 * 1024;
+/// Files scanned for boundaries on every core, unless set through `parallel_scan_threshold_bytes`.
+const DEFAULT_PARALLEL_SCAN_THRESHOLD_BYTES: u64 = 64 * 1024 * 1024;
+/// Smallest segment of a file scanned for boundaries on a thread of its own.
+const MIN_SCAN_SEGMENT_BYTES: usize = 1024 * 1024;
+/// Bytes `add_files_parallel_io` reads ahead at most, unless `prefetch_limit_bytes` is set.
+const DEFAULT_PREFETCH_LIMIT_BYTES: u64 = 1024 * 1024 * 1024;
+/// Chunk files start with these bytes, followed by the format version and the length of the header.
+const CHUNK_MAGIC: &[u8; 4] = b"FCHK";
+const CHUNK_FORMAT_VERSION: u8 = 1;
+const CHUNK_PREAMBLE_LEN: usize = CHUNK_MAGIC.len() + 1 + 4;
+/// Index of chunk contents in the output path, see [`ChunkerBuilder::content_addressable_links`].
+const CA_INDEX_FILE: &str = "ca_index.yaml";
+/// HMAC-SHA256 of the manifest, see [`ChunkerBuilder::manifest_hmac_secret`].
+const MANIFEST_SIG_FILE: &str = "manifest.sig";
+/// Bytes compressed by [`Chunk::estimated_compression_ratio`].
+const COMPRESSION_SAMPLE_BYTES: usize = 64 * 1024;
 
 #[derive(Debug, Clone)]
 pub(crate) struct Chunk {
     current_offset: u64,
     buffer: Vec<u8>,
     base: ChunkBase,
+    /// Finds the boundaries instead of the fingerprint, see [`CdcAlgorithm::FastCdc`](crate::CdcAlgorithm::FastCdc).
+    cutter: Option<FastCdc>,
+    at_boundary: bool,
+    /// Offset inside its file of every range in `base.files`.
+    file_offsets: Vec<u64>,
+    /// Takes in everything added, see [`ChunkerBuilder::max_chunks_per_file`].
+    unbounded: bool,
+    /// Smallest and largest size of a Rabin chunk, see [`ChunkerBuilder::split_strategy`].
+    bounds: Option<(u64, u64)>,
+    /// Encrypts the paths in the header, see [`ChunkerBuilder::encrypt_filenames`].
+    #[cfg(feature = "encryption")]
+    header_key: Option<[u8; 32]>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) struct ChunkBase {
     files: Vec<ChunkFile>,
     fingerprint: RabinFingerprint,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Manifests written before the switch to camelCase keys stay readable through the aliases
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub(crate) struct RestoreInformation {
-    files: HashMap<String, IndexMap<String, StartEndTuple>>,
+    /// See [`MANIFEST_FORMAT_VERSION`].
+    #[serde(default = "legacy_format_version")]
+    format_version: String,
+    /// Chunk ranges of every path, in the order of the bytes of the file they hold.
+    files: BTreeMap<String, IndexMap<String, StartEndTuple>>,
+    #[serde(serialize_with = "sorted")]
     hashes: HashMap<String, String>,
+    #[serde(serialize_with = "sorted")]
     duplicates: HashMap<String, Vec<String>>,
+    #[serde(default, alias = "chunk_hashes", serialize_with = "sorted")]
+    chunk_hashes: HashMap<String, String>,
+    #[serde(default, alias = "chunk_name_prefix")]
+    chunk_name_prefix: Option<String>,
+    /// Symlinks by path, mapped to their target. Their targets are not chunked.
+    #[serde(default, serialize_with = "sorted")]
+    symlinks: HashMap<String, String>,
+    #[serde(default, alias = "file_info", serialize_with = "sorted")]
+    file_info: HashMap<String, FileInfo>,
+    /// Chunks stored as a XOR delta, mapped to the chunk the delta applies to.
+    #[serde(default, serialize_with = "sorted")]
+    deltas: HashMap<String, String>,
+    /// BLAKE3 hash of the manifest file this one replaced, see [`Chunker::verify_chain`].
+    #[serde(default, alias = "previous_manifest_hash")]
+    previous_manifest_hash: Option<String>,
+    /// BLAKE3 hash of `previous_manifest_hash` and the hashes of the chunks this run added, see
+    /// [`Chunker::audit_chain`].
+    #[serde(default)]
+    manifest_hash: Option<String>,
+    /// Custom prime the chunk fingerprints were computed with, see [`RabinFingerprint::new_with_params`].
+    #[serde(default, alias = "fingerprint_prime")]
+    fingerprint_prime: Option<u64>,
+    /// Seed the BLAKE3 file and chunk hashes were keyed with.
+    #[serde(default, alias = "hash_seed")]
+    hash_seed: Option<u64>,
+    #[serde(default, alias = "compression_stats", serialize_with = "sorted")]
+    compression_stats: HashMap<String, CompressionStats>,
+    /// Codec of every chunk not stored with Snappy, see [`ChunkerBuilder::per_file_compression`].
+    #[serde(default, alias = "chunk_codecs", serialize_with = "sorted")]
+    chunk_codecs: HashMap<String, Compression>,
+    /// When every chunk was first written, in seconds since the Unix epoch, see [`Chunker::gc`].
+    #[serde(default, serialize_with = "sorted")]
+    chunk_timestamps: HashMap<String, u64>,
+    /// Whether every path is encrypted, see [`ChunkerBuilder::encrypt_filenames`].
+    #[serde(default)]
+    encrypted_filenames: bool,
+    /// Extended attributes by path and name, see [`ChunkerBuilder::preserve_xattrs`].
+    #[serde(default, serialize_with = "sorted_nested")]
+    xattrs: HashMap<String, HashMap<String, Vec<u8>>>,
+    /// Access ACLs in their text form, of the files that have more than their mode bits in
+    /// them. Recorded and set again with the `posix-acl` feature on Linux.
+    #[serde(default, serialize_with = "sorted")]
+    file_acls: HashMap<String, String>,
+    /// DCT perceptual hashes of images, see [`ChunkerBuilder::perceptual_hash_images`].
+    #[serde(default, serialize_with = "sorted")]
+    phashes: HashMap<String, u64>,
+    /// MIME types of the files whose magic bytes are known, see [`ChunkerBuilder::detect_mime_types`].
+    #[serde(default, serialize_with = "sorted")]
+    detected_mime_types: HashMap<String, String>,
+    /// Modification time, permissions and owner of every file, set again on restore.
+    #[serde(default, serialize_with = "sorted")]
+    metadata: HashMap<String, FileMetadata>,
+    /// Chunks repeated within a file, see [`ChunkerBuilder::deduplicate_within_file`].
+    #[serde(default, serialize_with = "sorted")]
+    forward_refs: HashMap<String, Vec<ForwardRef>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub(crate) struct StartEndTuple {
-    start: u64,
-    end: u64,
+/// Manifests written before the format version was recorded are all of the first version, which
+/// [`MANIFEST_FORMAT_VERSION`] still is.
+fn legacy_format_version() -> String {
+    MANIFEST_FORMAT_VERSION.to_string()
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub(crate) struct ChunkFile {
-    name: String,
-    start: u64,
-    end: u64,
-    filename: String,
+/// Writes the entries of `map` sorted by key, so the same manifest is always written the same way.
+fn sorted<S: serde::Serializer, V: Serialize>(map: &HashMap<String, V>, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
 }
 
-impl Chunk {
-    pub(crate) fn new() -> Chunk {
-        Chunk {
-            current_offset: 0,
-            buffer: vec![],
-            base: ChunkBase {
-                files: vec![],
-                fingerprint: RabinFingerprint::new(),
-            },
+/// Like [`sorted`], for maps of maps.
+fn sorted_nested<S: serde::Serializer, V: Serialize>(
+    map: &HashMap<String, HashMap<String, V>>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    map.iter()
+        .map(|(key, inner)| (key, inner.iter().collect::<BTreeMap<_, _>>()))
+        .collect::<BTreeMap<_, _>>()
+        .serialize(serializer)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileInfo {
+    /// Number of chunks the file content is spread over.
+    #[serde(alias = "chunks_count")]
+    pub chunks_count: u32,
+}
+
+/// Metadata of a file that `restore_file` sets again after writing its content.
+///
+/// Off Unix, `mode` is only `0o444` for read-only files and `0o644` else, and `uid` and `gid`
+/// are zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileMetadata {
+    /// Modification time in seconds since the Unix epoch.
+    pub mtime: u64,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl FileMetadata {
+    fn from_fs(metadata: &fs::Metadata) -> FileMetadata {
+        let mtime = metadata.modified().ok().and_then(|mtime| mtime.duration_since(std::time::UNIX_EPOCH).ok());
+        #[cfg(unix)]
+        let (mode, uid, gid) = {
+            use std::os::unix::fs::MetadataExt;
+            (metadata.mode() & 0o7777, metadata.uid(), metadata.gid())
+        };
+        #[cfg(not(unix))]
+        let (mode, uid, gid) = (if metadata.permissions().readonly() { 0o444 } else { 0o644 }, 0, 0);
+        FileMetadata {
+            mtime: mtime.map_or(0, |mtime| mtime.as_secs()),
+            mode,
+            uid,
+            gid,
         }
     }
 
+    /// Sets the metadata on the file at `path`. The owner is only set when running as root,
+    /// nobody else may give files away.
+    fn apply(&self, path: &str) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if unsafe { libc::geteuid() } == 0 {
+                std::os::unix::fs::chown(path, Some(self.uid), Some(self.gid))?;
+            }
+            fs::set_permissions(path, fs::Permissions::from_mode(self.mode))?;
+        }
+        #[cfg(not(unix))]
+        {
+            let mut permissions = fs::metadata(path)?.permissions();
+            permissions.set_readonly(self.mode & 0o200 == 0);
+            fs::set_permissions(path, permissions)?;
+        }
+        Ok(())
+    }
+}
 
-    pub(crate) fn repair(&mut self) {
-        if self.base.files.len() <= 1{
-            println!("No repair needed");
-            return;
+/// How well the content of a single file compressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompressionStats {
+    #[serde(alias = "original_bytes")]
+    pub original_bytes: u64,
+    /// The file's share of the stored size of every chunk it has content in, in proportion to
+    /// how many of the chunk's bytes belong to it.
+    #[serde(alias = "compressed_bytes")]
+    pub compressed_bytes: u64,
+}
+
+/// Details about a single chunk, as returned by [`Chunker::chunk_info`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkInfo {
+    pub name: String,
+    /// Size of the chunk file, or of the delta file for delta chunks.
+    pub on_disk_bytes: u64,
+    pub uncompressed_bytes: u64,
+    /// Every file with content in the chunk, with the byte range it occupies, sorted by file name.
+    pub referenced_by: Vec<(String, StartEndTuple)>,
+    pub codec: String,
+}
+
+/// A named copy of a manifest, as returned by [`Chunker::list_snapshots`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotInfo {
+    pub label: String,
+    /// When the snapshot file was created, or last modified where creation times are not kept.
+    pub created: std::time::SystemTime,
+}
+
+/// Byte range inside a decompressed chunk, `end` is exclusive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StartEndTuple {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// A chunk of a file that holds the same content as an earlier one, which is restored in its
+/// place, see [`ChunkerBuilder::deduplicate_within_file`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ForwardRef {
+    /// Index of the chunk among all chunk ranges of the file, these included.
+    position: u64,
+    /// The earlier chunk, whose range in the file is restored again.
+    chunk: String,
+}
+
+/// A range of a file inside a chunk, as listed in the `files` of a chunk header.
+///
+/// The fields are public API, renaming or removing one is a breaking change.
+///
+/// ```
+/// use file_chunk::ChunkFile;
+///
+/// let yaml = "
+/// - name: '277092865343'
+///   start: 0
+///   end: 26
+///   filename: a.txt
+/// - name: '277092865343'
+///   start: 26
+///   end: 55
+///   filename: b.txt
+/// ";
+/// let files: Vec<ChunkFile> = serde_yaml::from_str(yaml).unwrap();
+/// let referenced_bytes: u64 = files.iter().map(ChunkFile::byte_len).sum();
+/// assert_eq!(referenced_bytes, 55);
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunkFile {
+    /// Name of the chunk, its fingerprint.
+    pub name: String,
+    /// Offset of the range in the decompressed chunk.
+    pub start: u64,
+    /// End of the range in the decompressed chunk, exclusive.
+    pub end: u64,
+    pub filename: String,
+    /// The earlier chunk of the file this one repeats. Such chunks are not saved, and it only
+    /// goes into the manifest.
+    #[serde(skip)]
+    forward_ref: Option<String>,
+}
+
+impl ChunkFile {
+    /// Number of bytes of the file in the chunk.
+    pub fn byte_len(&self) -> u64 {
+        // Manifests are read from disk, so the range may be inverted
+        self.end.saturating_sub(self.start)
+    }
+}
+
+impl RestoreInformation {
+    /// Reads the manifest from `data_path`, accepting plain and Zstd compressed variants.
+    pub(crate) fn load(data_path: &str) -> Result<RestoreInformation> {
+        let (path, ext) = find_manifest(data_path, "restore_info")
+            .unwrap_or_else(|| (format!("{}/restore_info.json.zst", data_path), "json.zst"));
+        RestoreInformation::parse(&fs::read(path)?, ext)
+    }
+
+    /// Parses manifest file contents in the format given by its extension.
+    fn parse(bytes: &[u8], ext: &str) -> Result<RestoreInformation> {
+        match ext {
+            "yaml" => Ok(serde_yaml::from_slice(bytes)?),
+            "yaml.zst" => Ok(serde_yaml::from_slice(&zstd::decode_all(bytes)?)?),
+            _ => Ok(serde_json::from_slice(&zstd::decode_all(bytes)?)?),
         }
-        let fingerprint = self.base.fingerprint.value();
-        for i in 0..self.base.files.len() {
-            self.base.files[0].name = fingerprint.to_string();
+    }
+
+    /// Parses a manifest read from a [`Storage`], telling the formats apart by their content.
+    fn parse_stored(bytes: &[u8]) -> Result<RestoreInformation> {
+        let bytes = if is_zstd(bytes) { zstd::decode_all(bytes)? } else { bytes.to_vec() };
+        if bytes.starts_with(b"{") {
+            return Ok(serde_json::from_slice(&bytes)?);
         }
-        println!("Repairing chunk: {}", fingerprint);
-        println!("Files: {:#?}", self.base.files);
+        Ok(serde_yaml::from_slice(&bytes)?)
     }
 
-    pub(crate) fn add_file(&mut self, file: &str, bytes: &Vec<u8>) -> Vec<u8> {
-        let mut vec_dq_bytes = VecDeque::from(bytes.to_vec());
-        let mut written: u64 = 0;
-        for _ in 0..bytes.len() {
-            let byte = vec_dq_bytes.pop_front().unwrap();
-            self.buffer.push(byte);
-            self.base.fingerprint.push_byte(byte);
-            written += 1;
-            if self.base.fingerprint.value() % CHUNK_MODULUS == 0{
-                self.current_offset += written;
-                self.base.files.push(ChunkFile {
-                    filename: file.to_string(),
-                    name: self.base.fingerprint.value().to_string(),
-                    start: self.current_offset - written,
-                    end: self.current_offset,
-                });
-                return vec_dq_bytes.make_contiguous().to_vec();
-            }
+    /// Returns the path of the file holding `chunk_name`, which is a `.delta` file for delta chunks.
+    #[cfg(any(test, feature = "async"))]
+    pub(crate) fn stored_chunk_path(&self, data_path: &str, chunk_name: &str) -> String {
+        format!("{}/{}", data_path, self.stored_chunk_name(chunk_name))
+    }
+
+    /// Returns the name `chunk_name` is stored under, see [`RestoreInformation::stored_chunk_path`].
+    pub(crate) fn stored_chunk_name(&self, chunk_name: &str) -> String {
+        match self.deltas.get(chunk_name) {
+            Some(base) => delta_file_name(self.chunk_name_prefix.as_deref(), base, chunk_name),
+            None => chunk_file_name(self.chunk_name_prefix.as_deref(), chunk_name),
         }
-        self.current_offset += written;
-        println!("File: {}, written: {}, remaining: {}, fingerprint: {}", file, written, vec_dq_bytes.len(), self.base.fingerprint.value());
-        self.base.files.push(ChunkFile {
-            filename: file.to_string(),
-            name: self.base.fingerprint.value().to_string(),
-            start: self.current_offset - written,
-            end: self.current_offset,
-        });
+    }
 
-        vec_dq_bytes.make_contiguous().to_vec()
+    /// Returns the manifest with every path, including symlink targets, replaced by `f` of it.
+    #[cfg(feature = "encryption")]
+    fn map_paths(mut self, f: impl Fn(&str) -> Result<String>) -> Result<RestoreInformation> {
+        fn map_keys<V, M>(map: M, f: &impl Fn(&str) -> Result<String>) -> Result<M>
+        where
+            M: IntoIterator<Item = (String, V)> + FromIterator<(String, V)>,
+        {
+            map.into_iter().map(|(path, value)| Ok((f(&path)?, value))).collect()
+        }
+        self.files = map_keys(self.files, &f)?;
+        self.hashes = map_keys(self.hashes, &f)?;
+        self.file_info = map_keys(self.file_info, &f)?;
+        self.compression_stats = map_keys(self.compression_stats, &f)?;
+        self.xattrs = map_keys(self.xattrs, &f)?;
+        self.file_acls = map_keys(self.file_acls, &f)?;
+        self.phashes = map_keys(self.phashes, &f)?;
+        self.detected_mime_types = map_keys(self.detected_mime_types, &f)?;
+        self.metadata = map_keys(self.metadata, &f)?;
+        self.forward_refs = map_keys(self.forward_refs, &f)?;
+        self.symlinks = map_keys(self.symlinks, &f)?
+            .into_iter()
+            .map(|(path, target)| Ok((path, f(&target)?)))
+            .collect::<Result<_>>()?;
+        self.duplicates = self
+            .duplicates
+            .into_iter()
+            .map(|(hash, paths)| Ok((hash, paths.iter().map(|path| f(path)).collect::<Result<_>>()?)))
+            .collect::<Result<_>>()?;
+        Ok(self)
     }
 
-    fn save(&self, output_path: &str) {
-        let path = format!("{}/{}.chunk", output_path, self.base.fingerprint.value());
-        println!("Saving chunk: {}", path);
-        // Check if file exists
-        if std::path::Path::new(&path).exists() {
-            return;
+    /// Returns the manifest with its paths decrypted with `key`, if they are encrypted.
+    #[cfg(feature = "encryption")]
+    pub(crate) fn decrypted(self, key: &[u8; 32]) -> Result<RestoreInformation> {
+        if !self.encrypted_filenames {
+            return Ok(self);
         }
-        let mut file = fs::File::create(path).unwrap();
-        // Snappy compress
-        snap::write::FrameEncoder::new(&mut file)
-            .write_all(&self.buffer)
-            .unwrap();
+        let mut restore_info = self.map_paths(|path| crate::filename_crypto::decrypt_path(key, path))?;
+        restore_info.encrypted_filenames = false;
+        Ok(restore_info)
     }
-}
 
-pub struct Chunker {
-    bases: HashMap<String, Vec<ChunkBase>>,
-    hash_to_path_map: HashMap<String, Vec<String>>,
-    path_to_hash_map: HashMap<String, String>,
-}
+    /// Returns every file with its [`FileInfo`], sorted by name.
+    fn file_list(&self) -> Vec<(String, FileInfo)> {
+        let mut files: Vec<(String, FileInfo)> = self
+            .hashes
+            .keys()
+            .filter_map(|filename| Some((filename.clone(), self.file_info(filename)?)))
+            .collect();
+        files.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        files
+    }
 
-impl Chunker {
-    pub(crate) fn new() -> Chunker {
-        Chunker {
-            bases: HashMap::new(),
-            hash_to_path_map: HashMap::new(),
-            path_to_hash_map: HashMap::new(),
+    /// Returns the audit hash of this manifest: `previous_manifest_hash` followed by the sorted
+    /// hashes of the chunks missing from `previous_chunks`, the chunk hashes of the manifest it
+    /// replaced.
+    pub(crate) fn audit_hash(&self, previous_chunks: &HashMap<String, String>) -> String {
+        let mut new_chunks: Vec<&String> = self
+            .chunk_hashes
+            .iter()
+            .filter(|(chunk_name, _)| !previous_chunks.contains_key(*chunk_name))
+            .map(|(_, hash)| hash)
+            .collect();
+        new_chunks.sort_unstable();
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(self.previous_manifest_hash.as_deref().unwrap_or_default().as_bytes());
+        for hash in new_chunks {
+            hasher.update(hash.as_bytes());
         }
+        hasher.finalize().to_hex().to_ascii_lowercase()
     }
-    pub(crate) fn add_files(mut self, mut paths: Vec<String>, output_path: &str) {
-        paths.sort_unstable();
-        let mut chunk = Chunk::new();
-        let mut remaining_bytes = vec![];
-        let mut last_file = String::new();
-        for path in paths.iter() {
-            let now = std::time::Instant::now();
-            println!("Path: {}", path);
-            let path = path.replace('\\', "/");
-            // Try read file
-            remaining_bytes = fs::read(&path).expect("Unable to read file");
-            let file_hash_blake = blake3::hash(&remaining_bytes).to_hex().to_ascii_lowercase();
-            match self.hash_to_path_map.get(&file_hash_blake) {
-                None => {
-                    self.hash_to_path_map.insert(file_hash_blake.clone(), vec![path.clone()]);
-                    self.path_to_hash_map.insert(path.clone(), file_hash_blake.clone());
-                }
-                Some(h) => {
-                    let mut h = h.clone();
-                    h.push(path.clone());
-                    self.hash_to_path_map.insert(file_hash_blake.clone(), h);
-                    self.path_to_hash_map.insert(path.clone(), file_hash_blake.clone());
-                    // We can skip processing now, since we already have this file
-                    println!("Skipping file: {}", path);
-                    continue;
-                }
-            }
 
+    /// Returns the chunks `chunk_name` is rebuilt from, starting with the full chunk at the
+    /// root of its delta chain and ending with `chunk_name` itself.
+    pub(crate) fn delta_chain<'a>(&'a self, chunk_name: &'a str) -> Vec<&'a str> {
+        let mut chain = vec![chunk_name];
+        while let Some(base) = self.deltas.get(chain[chain.len() - 1]) {
+            chain.push(base);
+        }
+        chain.reverse();
+        chain
+    }
 
-            while !remaining_bytes.is_empty() {
-                remaining_bytes = chunk.add_file(&path, &remaining_bytes);
-                if chunk.base.fingerprint.value() % CHUNK_MODULUS == 0 {
-                    println!("Chunk: {}", chunk.base.fingerprint.value());
-                    chunk.repair();
-                    // save old chunk
-                    self.update_restore_info(&chunk);
-                    chunk.save(output_path);
-                    chunk = Chunk::new();
-                }
-            }
-            last_file = path.to_string();
-            println!("Time: {:?}", now.elapsed());
+    /// Returns every chunk a file of the manifest is rebuilt from, including delta bases.
+    pub(crate) fn referenced_chunks(&self) -> HashSet<&str> {
+        self.files
+            .values()
+            .flat_map(|file_map| file_map.keys())
+            .flat_map(|chunk_name| self.delta_chain(chunk_name))
+            .collect()
+    }
+
+    /// Tells whether the file `file_name` in the data path looks like a chunk or delta file
+    /// written with this manifest's prefix.
+    fn is_chunk_file_name(&self, file_name: &str) -> bool {
+        let name = match self.chunk_name_prefix.as_deref() {
+            Some(prefix) => match file_name.strip_prefix(prefix).and_then(|name| name.strip_prefix('_')) {
+                Some(name) => name,
+                None => return false,
+            },
+            None => file_name,
+        };
+        let is_number = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+        if let Some(stem) = name.strip_suffix(".chunk") {
+            return is_number(stem);
         }
-        assert!(remaining_bytes.is_empty());
-        if !chunk.buffer.is_empty() {
-            println!("Last chunk: {}", chunk.base.fingerprint.value());
-            chunk.repair();
-            // Save last chunk
-            self.update_restore_info(&chunk);
-            chunk.save(output_path);
+        match name.strip_suffix(".delta").and_then(|stem| stem.split_once('_')) {
+            Some((base, chunk)) => is_number(base) && is_number(chunk),
+            None => false,
         }
+    }
 
-        self.dump_restore_info(output_path);
+    /// Returns the decompressed size of `chunk_name`, as far as the files referencing it reach.
+    pub(crate) fn chunk_len(&self, chunk_name: &str) -> u64 {
+        self.files
+            .values()
+            .filter_map(|file_map| file_map.get(chunk_name))
+            .map(|start_end| start_end.end)
+            .max()
+            .unwrap_or(0)
     }
 
-    fn update_restore_info(&mut self, filename: &Chunk) {
-        println!("Bases: {:#?}", filename.base);
-        if filename.base.files.len() > 1 {
-            // We need to rename the base.name for the all files, except the last one to the last one
-            let last_base = filename.base.files.last().unwrap();
-            let last_base_name = last_base.name.clone();
-            println!("Last base name: {}", last_base_name);
-            for base in filename.base.files.iter() {
-                let mut base_clone = base.clone();
-                base_clone.name = last_base_name.clone();
-                self.update_restore_info_for_file(&base_clone, filename);
-            }
-        } else {
-            for base in filename.base.files.iter() {
-                println!("Updating restore info for: {}: {} {}->{}", base.name, base.filename, base.start, base.end);
-                self.update_restore_info_for_file(base, filename);
-            }
+    pub(crate) fn is_delta(&self, chunk_name: &str) -> bool {
+        self.deltas.contains_key(chunk_name)
+    }
+
+    /// Returns the codec the file holding `chunk_name` is compressed with. Deltas always use Snappy.
+    pub(crate) fn chunk_codec(&self, chunk_name: &str) -> Compression {
+        if self.is_delta(chunk_name) {
+            return Compression::Snappy;
         }
+        self.chunk_codecs.get(chunk_name).copied().unwrap_or_default()
     }
 
-    fn update_restore_info_for_file(&mut self, file: &ChunkFile, chunk: &Chunk) {
-        println!("Updating restore info for: {}: {} {}->{}", file.name, file.filename, file.start, file.end);
-        let filename = &file.filename;
-        match self.bases.get(filename) {
-            None => {
-                self.bases
-                    .insert(filename.to_string(), vec![chunk.base.clone()]);
-            }
-            Some(base) => {
-                let mut new_base = base.clone();
-                new_base.push(chunk.base.clone());
-                self.bases.insert(filename.to_string(), new_base);
-            }
+    /// Reads and decompresses `chunk_name` from `storage`, applying its delta chain.
+    pub(crate) fn read_chunk(&self, storage: &dyn Storage, chunk_name: &str) -> Result<Vec<u8>> {
+        let mut chunk = vec![];
+        for name in self.delta_chain(chunk_name) {
+            let stored = storage.read_chunk(&self.stored_chunk_name(name))?;
+            let stored = self.chunk_codec(name).decompress(ChunkHeader::split(&stored)?.1)?;
+            chunk = if self.is_delta(name) { xor_delta(&chunk, &stored) } else { stored };
         }
+        Ok(chunk)
     }
 
-    fn dump_restore_info(&mut self, output_path: &str) {
-        let path = format!("{}/restore_info.yaml", output_path);
-        let mut file = fs::File::create(path).unwrap();
+    /// Returns the [`FileInfo`] of `filename`, deriving it for manifests that predate it.
+    pub(crate) fn file_info(&self, filename: &str) -> Option<FileInfo> {
+        let original = self.primary_path(filename)?;
+        let file_map = self.files.get(original)?;
+        Some(self.file_info.get(original).copied().unwrap_or(FileInfo {
+            chunks_count: file_map.len() as u32,
+        }))
+    }
 
-        let mut restore_info = RestoreInformation {
-            files: HashMap::new(),
-            hashes: HashMap::new(),
-            duplicates: HashMap::new(),
-        };
+    /// Returns the path whose chunk ranges hold the content of `filename`.
+    ///
+    /// This is `filename` itself, unless it is a duplicate of another file.
+    pub(crate) fn primary_path<'a>(&'a self, filename: &'a str) -> Option<&'a str> {
+        if self.files.contains_key(filename) {
+            return Some(filename);
+        }
+        // Check if it is a duplicate file
+        let key = self.hashes.get(filename)?;
+        self.duplicates.get(key)?.first().map(String::as_str)
+    }
 
+    /// Looks up the chunk ranges of `filename`, following whole-file duplicates.
+    pub(crate) fn file_map(&self, filename: &str) -> Option<&IndexMap<String, StartEndTuple>> {
+        self.files.get(self.primary_path(filename)?)
+    }
 
-        for (filename, bases) in self.bases.iter() {
-            println!("Filename: {}", filename);
-            println!("Bases: {:?}", bases);
+    /// Returns the number of bytes `filename` is restored with.
+    pub(crate) fn file_len(&self, filename: &str) -> Option<u64> {
+        Some(self.file_ranges(filename)?.iter().map(|(_, range)| range.end.saturating_sub(range.start)).sum())
+    }
 
-            let mut file_map = IndexMap::new();
-            for base in bases.iter() {
-                for chunk_file in base.files.iter() {
-                    if &chunk_file.filename != filename{
-                        continue;
-                    }
-                    file_map.insert(
-                        chunk_file.name.clone(),
-                        StartEndTuple {
-                            start: chunk_file.start,
-                            end: chunk_file.end,
-                        },
-                    );
-                }
-            }
-            restore_info.files.insert(filename.to_string(), file_map);
-        }
-        restore_info.hashes = self.path_to_hash_map.clone();
-        // Filter out all keys, where there value is only len 1
-        restore_info.duplicates = self.hash_to_path_map.clone().into_iter().filter(|(_, v)| v.len() > 1).collect();
+    /// Lists every file, not symlinks, in the format of `MANIFEST.txt`.
+    fn text_manifest(&self) -> String {
+        let entries = self
+            .hashes
+            .iter()
+            .filter_map(|(path, hash)| {
+                Some(TextManifestEntry {
+                    path,
+                    size: self.file_len(path)?,
+                    hash,
+                    mtime: self.metadata.get(path).map(|metadata| metadata.mtime),
+                })
+            })
+            .collect();
+        crate::text_manifest::render(entries)
+    }
 
-        let yaml = serde_yaml::to_string(&restore_info).unwrap();
-        file.write_all(yaml.as_bytes()).unwrap();
+    /// Returns the chunk ranges of `filename` in the order they are restored in, with the chunks
+    /// repeated within it in place.
+    pub(crate) fn file_ranges(&self, filename: &str) -> Option<Vec<(&str, &StartEndTuple)>> {
+        let primary = self.primary_path(filename)?;
+        let file_map = self.files.get(primary)?;
+        let mut ranges: Vec<(&str, &StartEndTuple)> = file_map.iter().map(|(name, range)| (name.as_str(), range)).collect();
+        for forward_ref in self.forward_refs.get(primary).into_iter().flatten() {
+            let range = file_map.get(&forward_ref.chunk)?;
+            ranges.insert((forward_ref.position as usize).min(ranges.len()), (forward_ref.chunk.as_str(), range));
+        }
+        Some(ranges)
     }
+}
 
-    fn restore_file(&self, filename: &str, data_path: &str, output_path: &str) {
-        // Normalize filename to unix path
-        let filename = filename.replace("\\", "/");
+/// Returns the path of the manifest named `stem` in `dir` and its extension, if there is one.
+pub(crate) fn find_manifest(dir: &str, stem: &str) -> Option<(String, &'static str)> {
+    MANIFEST_EXTENSIONS
+        .into_iter()
+        .map(|ext| (format!("{}/{}.{}", dir, stem, ext), ext))
+        .find(|(path, _)| std::path::Path::new(path).exists())
+}
 
-        let filename_without_leading_dot_slash = filename.trim_start_matches("./");
-        let path = format!("{}/{}", output_path, filename_without_leading_dot_slash);
-        // Create parent directories
-        let parent = std::path::Path::new(&path).parent().unwrap();
-        std::fs::create_dir_all(parent).unwrap();
+#[cfg(any(test, feature = "async"))]
+pub(crate) fn chunk_file_path(data_path: &str, prefix: Option<&str>, chunk_name: &str) -> String {
+    format!("{}/{}", data_path, chunk_file_name(prefix, chunk_name))
+}
+
+pub(crate) fn chunk_file_name(prefix: Option<&str>, chunk_name: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{}_{}.chunk", prefix, chunk_name),
+        None => format!("{}.chunk", chunk_name),
+    }
+}
 
-        let mut file = fs::File::create(path).unwrap();
+pub(crate) fn delta_file_name(prefix: Option<&str>, base_name: &str, chunk_name: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{}_{}_{}.delta", prefix, base_name, chunk_name),
+        None => format!("{}_{}.delta", base_name, chunk_name),
+    }
+}
 
-        let restore_info_path = format!("{}/restore_info.yaml", data_path);
-        let restore_info = fs::read_to_string(restore_info_path).unwrap();
-        let restore_info: RestoreInformation = serde_yaml::from_str(&restore_info).unwrap();
+/// XORs `new` with `old`, padding `old` with zeros to the length of `new`.
+///
+/// Applying the result to `old` the same way yields `new` again.
+pub(crate) fn xor_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    new.iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ old.get(i).copied().unwrap_or(0))
+        .collect()
+}
 
-        println!("Restoring: {}", filename);
-        println!("{:?}", restore_info);
+/// Reads a path given to `add_files`, fetching `http://` and `https://` URLs with the `reqwest` feature.
+pub(crate) fn read_source(path: &str) -> Result<Vec<u8>> {
+    #[cfg(feature = "reqwest")]
+    if crate::http::is_url(path) {
+        return crate::http::fetch(path).with_context(|| format!("Fetching {}", path));
+    }
+    fs::read(path).with_context(|| format!("Reading {}", path))
+}
 
-        let file_map = match restore_info.files.get(&filename) {
-            None => {
-                // Check if it is a duplicate file
-                let key = restore_info.hashes.get(&filename).unwrap();
-                let ri_key = restore_info.duplicates.get(key).unwrap().first().unwrap();
-                restore_info.files.get(ri_key).unwrap()
-            }
-            Some(v) => {
-                v
-            }
-        };
+/// Contents of a path given to `add_files`, read or mapped into memory.
+pub(crate) enum SourceBytes {
+    Read(Vec<u8>),
+    #[cfg(unix)]
+    Mapped(crate::mmap::MappedFile),
+}
 
-        for (chunk_name, start_end) in file_map.iter() {
-            let chunk_path = format!("{}/{}.chunk", data_path, chunk_name);
-            println!("Chunk path: {}", chunk_path);
-            let chunk_bytes = fs::read(chunk_path).unwrap();
-            // Snappy decompress
-            let chunk_bytes = snap::read::FrameDecoder::new(&chunk_bytes[..])
-                .bytes()
-                .map(|x| x.unwrap())
-                .collect::<Vec<u8>>();
-            let chunk_bytes =
-                chunk_bytes[start_end.start as usize..start_end.end as usize].to_vec();
-            println!("Chunk: {}", chunk_name);
-            file.write_all(&chunk_bytes).unwrap();
+impl Deref for SourceBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            SourceBytes::Read(bytes) => bytes,
+            #[cfg(unix)]
+            SourceBytes::Mapped(mapped) => mapped,
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
+/// Like `read_source`, but maps files into memory for sequential reading, see
+/// [`ChunkerBuilder::use_mmap`]. URLs, empty files and files off Unix are read as usual.
+pub(crate) fn map_source(path: &str) -> Result<SourceBytes> {
+    #[cfg(feature = "reqwest")]
+    if crate::http::is_url(path) {
+        return read_source(path).map(SourceBytes::Read);
+    }
+    #[cfg(unix)]
+    return match crate::mmap::MappedFile::open_sequential(path).with_context(|| format!("Mapping {}", path))? {
+        Some(mapped) => Ok(SourceBytes::Mapped(mapped)),
+        None => Ok(SourceBytes::Read(vec![])),
+    };
+    #[cfg(not(unix))]
+    read_source(path).map(SourceBytes::Read)
+}
 
-    use super::*;
+/// Returns the size of the file `read_source` reads for `path`, zero for URLs and missing files.
+pub(crate) fn source_len(path: &str) -> u64 {
+    fs::metadata(path).map_or(0, |metadata| metadata.len())
+}
 
-    #[test]
-    fn test_chunking() {
-        // Scan ./tests/data for files
-        let mut paths: Vec<String> = Vec::new();
+/// Creates the fingerprint chunks are split with, using the default prime unless `prime` is set.
+pub(crate) fn new_fingerprint(prime: Option<u64>) -> Result<RabinFingerprint> {
+    match prime {
+        Some(prime) => RabinFingerprint::new_with_params(WINDOW_SIZE, prime),
+        None => Ok(RabinFingerprint::new()),
+    }
+}
 
-        for entry in fs::read_dir("./tests/data").unwrap() {
-            let entry = entry.unwrap();
-            let path = entry.path();
-            let path_str = path.to_str().unwrap();
-            paths.push(path_str.to_string());
+/// Hex BLAKE3 hash of `bytes`, keyed with a key derived from `hash_seed` if it is set.
+pub(crate) fn content_hash(hash_seed: Option<u64>, bytes: &[u8]) -> String {
+    let mut hasher = content_hasher(hash_seed);
+    hasher.update(bytes);
+    hasher.finalize().to_hex().to_ascii_lowercase()
+}
+
+/// Like [`content_hash`], but hashes `bytes` on every core if there are at least `threshold` of them.
+pub(crate) fn file_hash(hash_seed: Option<u64>, bytes: &[u8], threshold: u64) -> String {
+    let mut hasher = content_hasher(hash_seed);
+    if bytes.len() as u64 >= threshold {
+        hasher.update_rayon(bytes);
+    } else {
+        hasher.update(bytes);
+    }
+    hasher.finalize().to_hex().to_ascii_lowercase()
+}
+
+/// Returns the hasher [`content_hash`] uses, for hashing content as it is streamed.
+fn content_hasher(hash_seed: Option<u64>) -> blake3::Hasher {
+    match hash_seed {
+        Some(seed) => blake3::Hasher::new_keyed(&blake3::derive_key("fileChunk hash seed", &seed.to_le_bytes())),
+        None => blake3::Hasher::new(),
+    }
+}
+
+/// Passes writes through to `inner`, feeding them to `hasher` too if there is one.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Option<blake3::Hasher>,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(&buf[..written]);
         }
-        // Cleanup output folder
-        let _ = fs::remove_dir_all("./tests/chunks");
-        fs::create_dir("./tests/chunks").unwrap();
+        Ok(written)
+    }
 
-        let chunker = Chunker::new();
-        chunker.add_files(paths, "./tests/chunks");
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
 
-        // Attempt restore
-        let _ = fs::remove_dir_all("./tests/restored");
-        fs::create_dir("./tests/restored").unwrap();
+pub(crate) struct SavedChunk {
+    pub(crate) hash: String,
+    pub(crate) compressed_len: u64,
+    /// The chunk was already stored and nothing was written.
+    pub(crate) reused: bool,
+    pub(crate) codec: Compression,
+}
 
-        let chunker_restore = Chunker::new();
+impl SavedChunk {
+    /// Stands in for the compressed chunk of a chunk that is compressed while it is saved.
+    fn streamed(codec: Compression) -> SavedChunk {
+        SavedChunk {
+            hash: String::new(),
+            compressed_len: 0,
+            reused: false,
+            codec,
+        }
+    }
+}
 
-        // Check if restored files is the same as original
-        for entry in fs::read_dir("./tests/data").unwrap() {
-            let path = format!(
-                "./tests/data/{}",
-                entry.unwrap().file_name().to_str().unwrap()
+impl Chunk {
+    pub(crate) fn new(fingerprint: RabinFingerprint) -> Chunk {
+        Chunk {
+            current_offset: 0,
+            buffer: vec![],
+            base: ChunkBase {
+                files: vec![],
+                fingerprint,
+            },
+            cutter: None,
+            at_boundary: false,
+            file_offsets: vec![],
+            unbounded: false,
+            bounds: None,
+            #[cfg(feature = "encryption")]
+            header_key: None,
+        }
+    }
+
+    pub(crate) fn with_cutter(mut self, cutter: Option<FastCdc>) -> Chunk {
+        self.cutter = cutter;
+        self
+    }
+
+    pub(crate) fn with_bounds(mut self, bounds: Option<(u64, u64)>) -> Chunk {
+        self.bounds = bounds;
+        self
+    }
+
+    #[cfg(feature = "encryption")]
+    pub(crate) fn with_header_key(mut self, key: Option<[u8; 32]>) -> Chunk {
+        self.header_key = key;
+        self
+    }
+
+    /// Returns the empty chunk following this one.
+    pub(crate) fn next(&self) -> Chunk {
+        let next = Chunk::new(self.base.fingerprint.reset())
+            .with_cutter(self.cutter.as_ref().map(FastCdc::reset))
+            .with_bounds(self.bounds);
+        #[cfg(feature = "encryption")]
+        let next = next.with_header_key(self.header_key);
+        next
+    }
+
+    /// Returns the header of the chunk file when stored compressed with `codec`.
+    fn header(&self, codec: Compression) -> ChunkHeader {
+        #[allow(unused_mut)]
+        let mut base = self.base.clone();
+        #[cfg(feature = "encryption")]
+        if let Some(key) = &self.header_key {
+            for file in base.files.iter_mut() {
+                file.filename = crate::filename_crypto::encrypt_path(key, &file.filename);
+            }
+        }
+        ChunkHeader {
+            base,
+            file_offsets: self.file_offsets.clone(),
+            codec,
+        }
+    }
+
+
+    pub(crate) fn repair(&mut self) {
+        if self.base.files.len() <= 1{
+            tracing::debug!("No repair needed");
+            return;
+        }
+        let fingerprint = self.base.fingerprint.value();
+        for file in self.base.files.iter_mut() {
+            file.name = fingerprint.to_string();
+        }
+        tracing::debug!(chunk_name = %fingerprint, files = ?self.base.files, "Repairing chunk");
+    }
+
+    /// Appends `bytes` of `file`, which start at `offset` in it, to the chunk, stopping early at a
+    /// chunk boundary.
+    ///
+    /// Returns the bytes that did not fit and belong to the next chunk.
+    pub(crate) fn add_file<'a>(&mut self, file: &str, offset: u64, bytes: &'a [u8]) -> &'a [u8] {
+        // The file's data starts right after whatever earlier files put into this chunk
+        let start = self.current_offset;
+        let mut written: u64 = 0;
+        for &byte in bytes.iter() {
+            self.buffer.push(byte);
+            self.base.fingerprint.push_byte(byte);
+            if let Some(cutter) = self.cutter.as_mut() {
+                self.at_boundary = cutter.push(byte);
+            }
+            written += 1;
+            if self.is_boundary() {
+                break;
+            }
+        }
+        // `end` is exclusive, it is the offset the next file's data starts at
+        self.current_offset = start + written;
+        if !self.is_boundary() {
+            tracing::debug!(
+                file = %file,
+                written,
+                remaining = bytes.len() as u64 - written,
+                fingerprint = self.base.fingerprint.value(),
+                "Added file to chunk"
             );
-            let restored_path = format!("./tests/restored/{}", path);
+        }
+        self.record_file(file, offset, start);
 
-            chunker_restore.restore_file(&path, "./tests/chunks", "./tests/restored");
+        &bytes[written as usize..]
+    }
 
-            let original = fs::read(&path).unwrap();
-            let restored = fs::read(&restored_path).unwrap();
+    /// Appends `bytes` of `file`, which start at `offset` in it and end at a boundary found ahead
+    /// of time, see [`Chunker::scan_boundaries`]. `fingerprint` is the chunk's once they are in.
+    pub(crate) fn add_scanned(&mut self, file: &str, offset: u64, bytes: &[u8], fingerprint: RabinFingerprint) {
+        let start = self.current_offset;
+        self.buffer.extend_from_slice(bytes);
+        self.base.fingerprint = fingerprint;
+        self.at_boundary = true;
+        self.current_offset = start + bytes.len() as u64;
+        self.record_file(file, offset, start);
+    }
 
-            assert_eq!(original, restored);
+    /// Records that the chunk holds `file` from `offset` in it, starting at `start` of the chunk
+    /// and running up to its end.
+    fn record_file(&mut self, file: &str, offset: u64, start: u64) {
+        self.base.files.push(ChunkFile {
+            filename: file.to_string(),
+            name: self.base.fingerprint.value().to_string(),
+            start,
+            end: self.current_offset,
+            forward_ref: None,
+        });
+        self.file_offsets.push(offset);
+        debug_assert_eq!(self.current_offset, self.buffer.len() as u64);
+    }
+
+    pub(crate) fn is_boundary(&self) -> bool {
+        if self.unbounded {
+            return false;
+        }
+        if self.cutter.is_some() {
+            return self.at_boundary;
+        }
+        let at_fingerprint_boundary = self.base.fingerprint.value().is_multiple_of(CHUNK_MODULUS);
+        match self.bounds {
+            Some((min, max)) => {
+                let len = self.buffer.len() as u64;
+                len >= max || (len >= min && at_fingerprint_boundary)
+            }
+            None => at_fingerprint_boundary,
+        }
+    }
+
+    /// Returns the number of bytes added to the chunk so far.
+    pub(crate) fn buffer_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub(crate) fn name(&self) -> String {
+        self.base.fingerprint.value().to_string()
+    }
+
+    /// Guesses how well the chunk compresses with `codec`, as the compressed to uncompressed size
+    /// of its first 64 KiB. This is a heuristic: the rest of the chunk may compress differently.
+    ///
+    /// Returns 1.0 for an empty chunk.
+    pub(crate) fn estimated_compression_ratio(&self, codec: Compression) -> f64 {
+        let sample = &self.buffer[..self.buffer.len().min(COMPRESSION_SAMPLE_BYTES)];
+        if sample.is_empty() {
+            return 1.0;
+        }
+        codec.compress(sample).len() as f64 / sample.len() as f64
+    }
+
+    /// Returns the bytes as they are stored on disk, along with their BLAKE3 hash and size.
+    /// Returns the chunk file contents: the [`ChunkHeader`] followed by the compressed chunk.
+    pub(crate) fn compress(&self, codec: Compression, hash_seed: Option<u64>) -> Result<(Vec<u8>, SavedChunk)> {
+        let mut compressed = self.header(codec).encode()?;
+        compressed.extend(codec.compress(&self.buffer));
+        let saved = SavedChunk {
+            hash: content_hash(hash_seed, &compressed),
+            compressed_len: compressed.len() as u64,
+            reused: false,
+            codec,
+        };
+        Ok((compressed, saved))
+    }
+
+    /// Like [`Chunk::save`], but compresses the chunk with Zstd straight into `file_name` in
+    /// `dir`, without holding the compressed chunk in memory. The chunk file only appears once it
+    /// is complete.
+    ///
+    /// A chunk file already in `dir` is kept. Its hash is `known_hash` if the manifest has one,
+    /// and is read back from the file otherwise.
+    fn save_streaming(&self, dir: &str, file_name: &str, known_hash: Option<&str>, hash_seed: Option<u64>) -> Result<SavedChunk> {
+        let codec = Compression::Zstd;
+        tracing::info!(chunk_name = %self.name(), path = %file_name, codec = codec.name(), "Saving chunk streamed");
+        let path = format!("{}/{}", dir, file_name);
+        if Path::new(&path).exists() {
+            let hash = match known_hash {
+                Some(hash) => hash.to_string(),
+                None => {
+                    let mut hasher = content_hasher(hash_seed);
+                    io::copy(&mut fs::File::open(&path)?, &mut hasher)?;
+                    hasher.finalize().to_hex().to_ascii_lowercase()
+                }
+            };
+            return Ok(SavedChunk {
+                hash,
+                compressed_len: fs::metadata(&path)?.len(),
+                reused: true,
+                codec,
+            });
+        }
+        let partial = format!("{}.partial", path);
+        let mut file = HashingWriter {
+            inner: io::BufWriter::new(fs::File::create(&partial)?),
+            hasher: Some(content_hasher(hash_seed)),
+        };
+        file.write_all(&self.header(codec).encode()?)?;
+        let mut file = Compression::zstd_compress_into(file, &self.buffer)?;
+        file.flush()?;
+        let hasher = file.hasher.take().expect("the hasher is set above");
+        drop(file);
+        fs::rename(&partial, &path)?;
+        Ok(SavedChunk {
+            hash: hasher.finalize().to_hex().to_ascii_lowercase(),
+            compressed_len: fs::metadata(&path)?.len(),
+            reused: false,
+            codec,
+        })
+    }
+
+    /// Writes the chunk, compressed through [`Chunk::compress`], to `storage` as `file_name` and
+    /// returns the BLAKE3 hash and size of the stored bytes.
+    fn save(&self, storage: &dyn Storage, file_name: &str, (compressed, mut saved): (Vec<u8>, SavedChunk)) -> Result<SavedChunk> {
+        tracing::info!(chunk_name = %self.name(), path = %file_name, codec = saved.codec.name(), "Saving chunk");
+        if storage.has_chunk(file_name)? {
+            saved.reused = true;
+            return Ok(saved);
+        }
+        storage.write_chunk(file_name, &compressed)?;
+        Ok(saved)
+    }
+}
+
+impl Chunk {
+    /// Reads the [`ChunkHeader`] at the start of the chunk file at `path`, without the rest of it.
+    pub(crate) fn read_header(path: &str) -> Result<ChunkHeader> {
+        let mut file = fs::File::open(path)?;
+        let mut preamble = [0; CHUNK_PREAMBLE_LEN];
+        file.read_exact(&mut preamble)?;
+        let len = ChunkHeader::parse_preamble(&preamble)?
+            .ok_or_else(|| invalid_chunk(format!("{} has no chunk header", path)))?;
+        let mut header = vec![0; len];
+        file.read_exact(&mut header)?;
+        ChunkHeader::decode(&header)
+    }
+}
+
+/// Describes the chunk a chunk file holds, so files can be restored without the manifest, see
+/// [`Chunker::rebuild_manifest`].
+///
+/// Stored as [`CHUNK_MAGIC`], the format version, the length of the header as a little endian
+/// `u32` and the header itself in `bincode`, in front of the compressed chunk.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ChunkHeader {
+    base: ChunkBase,
+    file_offsets: Vec<u64>,
+    codec: Compression,
+}
+
+impl ChunkHeader {
+    fn encode(&self) -> Result<Vec<u8>> {
+        let header = bincode::serde::encode_to_vec(self, bincode::config::standard())
+            .map_err(|e| invalid_chunk(format!("Cannot encode chunk header: {}", e)))?;
+        let mut bytes = Vec::with_capacity(CHUNK_PREAMBLE_LEN + header.len());
+        bytes.extend_from_slice(CHUNK_MAGIC);
+        bytes.push(CHUNK_FORMAT_VERSION);
+        bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        bytes.extend(header);
+        Ok(bytes)
+    }
+
+    fn decode(header: &[u8]) -> Result<ChunkHeader> {
+        let (header, _) = bincode::serde::decode_from_slice(header, bincode::config::standard())
+            .map_err(|e| invalid_chunk(format!("Invalid chunk header: {}", e)))?;
+        Ok(header)
+    }
+
+    /// Returns the length of the header following `preamble`, `None` if it is not a chunk header.
+    fn parse_preamble(preamble: &[u8]) -> Result<Option<usize>> {
+        let Some([version, len @ ..]) = preamble.strip_prefix(CHUNK_MAGIC) else {
+            return Ok(None);
+        };
+        if *version != CHUNK_FORMAT_VERSION {
+            return Err(invalid_chunk(format!("Unsupported chunk format version {}", version)));
+        }
+        let len: [u8; 4] = len.try_into().map_err(|_| invalid_chunk("Truncated chunk header".to_string()))?;
+        Ok(Some(u32::from_le_bytes(len) as usize))
+    }
+
+    /// Splits the contents of a chunk file into its header and the compressed chunk. Chunk files
+    /// written before headers were added, and delta files, have no header.
+    pub(crate) fn split(stored: &[u8]) -> Result<(Option<ChunkHeader>, &[u8])> {
+        let Some(len) = ChunkHeader::parse_preamble(stored.get(..CHUNK_PREAMBLE_LEN).unwrap_or(stored))? else {
+            return Ok((None, stored));
+        };
+        let (header, payload) = stored[CHUNK_PREAMBLE_LEN..]
+            .split_at_checked(len)
+            .ok_or_else(|| invalid_chunk("Truncated chunk header".to_string()))?;
+        Ok((Some(ChunkHeader::decode(header)?), payload))
+    }
+}
+
+fn invalid_chunk(message: String) -> ChunkError {
+    io::Error::new(io::ErrorKind::InvalidData, message).into()
+}
+
+/// Looks up the metadata of every file of `paths`, failing with all those that cannot be read.
+/// URLs are left to the download.
+fn preflight_check(paths: &[String]) -> Result<()> {
+    let mut failures: Vec<(String, io::Error)> = vec![];
+    for path in paths.iter() {
+        #[cfg(feature = "reqwest")]
+        if crate::http::is_url(path) {
+            continue;
+        }
+        if let Err(e) = fs::metadata(path) {
+            failures.push((path.clone(), e));
+        }
+    }
+    if failures.is_empty() {
+        return Ok(());
+    }
+    for (path, e) in failures.iter() {
+        tracing::error!(path = %path, error = %e, "Cannot read input file");
+    }
+    Err(ChunkError::PreflightFailure(failures))
+}
+
+/// Removes the file at `path` when dropped, also while unwinding from a panic, unless it is kept.
+struct RemoveOnDrop {
+    path: String,
+    keep: bool,
+}
+
+impl RemoveOnDrop {
+    fn new(path: String) -> RemoveOnDrop {
+        RemoveOnDrop { path, keep: false }
+    }
+
+    fn keep(mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Entry of `ca_index.yaml`, see [`ChunkerBuilder::content_addressable_links`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexedChunk {
+    chunk_file: String,
+    codec: Compression,
+    /// Chunk files hard linked to `chunk_file`, whose header is the one written for it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    links: Vec<String>,
+    /// Other chunk files with the same content, found by `hardlink_identical_chunks`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    duplicates: Vec<String>,
+}
+
+/// Decompressed chunks shared between restores, evicting the least recently used ones once
+/// they take up more than the capacity.
+struct RestoreCache {
+    chunks: lru::LruCache<String, Arc<Vec<u8>>>,
+    bytes: u64,
+}
+
+impl RestoreCache {
+    fn new() -> RestoreCache {
+        RestoreCache {
+            chunks: lru::LruCache::unbounded(),
+            bytes: 0,
+        }
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.chunks.contains(key)
+    }
+
+    fn get(&mut self, key: &str) -> Option<Arc<Vec<u8>>> {
+        self.chunks.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: String, chunk: Arc<Vec<u8>>, capacity: u64) {
+        let len = chunk.len() as u64;
+        if len > capacity {
+            return;
+        }
+        while self.bytes + len > capacity {
+            let Some((_, evicted)) = self.chunks.pop_lru() else {
+                break;
+            };
+            self.bytes -= evicted.len() as u64;
+        }
+        if let Some(replaced) = self.chunks.put(key, chunk) {
+            self.bytes -= replaced.len() as u64;
+        }
+        self.bytes += len;
+    }
+}
+
+/// Journal of an `atomic_backup` or checkpointed run, listing the chunks written so far.
+struct PendingManifest {
+    journal: fs::File,
+    chunks: Vec<String>,
+    /// Chunks at the start of `chunks` synced to disk already.
+    synced: usize,
+}
+
+/// The list of finished files of an `add_files_checkpointed` run.
+struct Checkpoint {
+    path: String,
+    file: fs::File,
+    /// Files read to the end, with the number of chunks of the run stored once they are finished.
+    awaiting: Vec<(String, usize)>,
+    /// Files of the run not listed yet.
+    unfinished: HashSet<String>,
+    /// Whether the run listed any file.
+    listed: bool,
+}
+
+pub struct Chunker {
+    bases: HashMap<String, Vec<ChunkBase>>,
+    hash_to_path_map: HashMap<String, Vec<String>>,
+    path_to_hash_map: HashMap<String, String>,
+    chunk_hashes: HashMap<String, String>,
+    resumed_files: HashMap<String, IndexMap<String, StartEndTuple>>,
+    symlinks: HashMap<String, String>,
+    xattrs: HashMap<String, HashMap<String, Vec<u8>>>,
+    file_acls: HashMap<String, String>,
+    phashes: HashMap<String, u64>,
+    detected_mime_types: HashMap<String, String>,
+    metadata: HashMap<String, FileMetadata>,
+    /// Forward references of the files taken over from a previous manifest.
+    forward_refs: HashMap<String, Vec<ForwardRef>>,
+    deltas: HashMap<String, String>,
+    chunk_codecs: HashMap<String, Compression>,
+    chunk_timestamps: HashMap<String, u64>,
+    /// Uncompressed and stored size of every chunk saved in this run.
+    chunk_sizes: HashMap<String, (u64, u64)>,
+    /// Offset in the bytes read of the first window with every fingerprint, with `window_dedup_stats`.
+    window_offsets: HashMap<u64, u64>,
+    previous_manifest: Option<RestoreInformation>,
+    /// Chunk files by the hash of their decompressed content, with `content_addressable_links`
+    /// or `hardlink_identical_chunks`.
+    ca_index: Option<HashMap<String, IndexedChunk>>,
+    /// Chunks being written to the custom storage, with `concurrent_chunk_uploads`.
+    uploads: Option<Arc<UploadQueue>>,
+    /// Reads the sources ahead of chunking them, with `add_files_parallel_io`.
+    prefetcher: Option<Arc<Mutex<Prefetcher>>>,
+    pending: Option<PendingManifest>,
+    checkpoint: Option<Checkpoint>,
+    /// Estimated compression ratio of the first chunk of every file, only for `estimate_chunks`.
+    compression_ratio_samples: Option<Vec<f64>>,
+    restore_cache: Mutex<RestoreCache>,
+    #[cfg(any(test, feature = "testing"))]
+    write_failure_state: u64,
+    pub(crate) stats: ChunkStats,
+    pub(crate) options: ChunkerOptions,
+}
+
+impl Default for Chunker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Chunker {
+    pub fn new() -> Chunker {
+        Chunker::with_options(ChunkerOptions::default())
+    }
+
+    pub fn builder() -> ChunkerBuilder {
+        ChunkerBuilder::new()
+    }
+
+    pub(crate) fn with_options(options: ChunkerOptions) -> Chunker {
+        Chunker {
+            bases: HashMap::new(),
+            hash_to_path_map: HashMap::new(),
+            path_to_hash_map: HashMap::new(),
+            chunk_hashes: HashMap::new(),
+            resumed_files: HashMap::new(),
+            symlinks: HashMap::new(),
+            xattrs: HashMap::new(),
+            file_acls: HashMap::new(),
+            phashes: HashMap::new(),
+            detected_mime_types: HashMap::new(),
+            metadata: HashMap::new(),
+            forward_refs: HashMap::new(),
+            deltas: HashMap::new(),
+            chunk_codecs: HashMap::new(),
+            chunk_timestamps: HashMap::new(),
+            chunk_sizes: HashMap::new(),
+            window_offsets: HashMap::new(),
+            previous_manifest: None,
+            ca_index: None,
+            uploads: None,
+            prefetcher: None,
+            pending: None,
+            checkpoint: None,
+            compression_ratio_samples: None,
+            restore_cache: Mutex::new(RestoreCache::new()),
+            #[cfg(any(test, feature = "testing"))]
+            write_failure_state: 0x9E37_79B9_7F4A_7C15,
+            stats: ChunkStats::default(),
+            options,
+        }
+    }
+    pub fn add_files(self, paths: Vec<String>, output_path: &str) -> Result<ChunkStats> {
+        self.add_files_with_filter(paths, output_path, |_| true)
+    }
+
+    /// Like `add_files`, but skips every path `filter` returns `false` for without reading it.
+    ///
+    /// The skipped paths are listed in [`ChunkStats::excluded_files`].
+    pub fn add_files_with_filter<F: Fn(&Path) -> bool>(mut self, paths: Vec<String>, output_path: &str, filter: F) -> Result<ChunkStats> {
+        let (paths, excluded): (Vec<String>, Vec<String>) = paths.into_iter().partition(|path| filter(Path::new(path)));
+        for path in excluded.iter() {
+            tracing::info!(path = %path, "Excluded by filter");
+        }
+        self.stats.excluded_files = excluded.into_iter().map(|path| normalize_path(&path)).collect();
+        let paths = self.record_sources(paths)?;
+        #[cfg(any(test, feature = "testing"))]
+        if let Some(latency) = self.options.simulate_read_latency {
+            let read = |path: &str| {
+                std::thread::sleep(latency);
+                read_source(path)
+            };
+            return self.add_sources(paths, output_path, read, source_len);
+        }
+        match self.options.use_mmap {
+            true => self.add_sources(paths, output_path, map_source, source_len),
+            false => self.add_sources(paths, output_path, read_source, source_len),
+        }
+    }
+
+    /// Like `add_files`, but reads the files in parallel ahead of chunking them, in path order and
+    /// no more than [`ChunkerBuilder::prefetch_limit_bytes`] at a time. Chunking stays serial.
+    ///
+    /// Helps where reads wait on the disk or network rather than the CPU, such as spinning disks
+    /// and NFS mounts. Files are always read, not mapped, and those unchanged since the last run
+    /// are not read ahead.
+    pub fn add_files_parallel_io(mut self, paths: Vec<String>, output_path: &str) -> Result<ChunkStats> {
+        let paths = self.record_sources(paths)?;
+        #[cfg(any(test, feature = "testing"))]
+        let latency = self.options.simulate_read_latency;
+        #[cfg(not(any(test, feature = "testing")))]
+        let latency = None;
+        let limit = self.options.prefetch_limit_bytes.unwrap_or(DEFAULT_PREFETCH_LIMIT_BYTES);
+        // Told which paths to read ahead once those unchanged since the last run are known
+        let prefetcher = Arc::new(Mutex::new(Prefetcher::new(limit, latency)));
+        self.prefetcher = Some(prefetcher.clone());
+        self.add_sources(paths, output_path, |path| prefetcher.lock().unwrap().read(path), source_len)
+    }
+
+    /// Like `add_files`, but appends the path of every file to the list at `checkpoint_path`, one
+    /// per line, as soon as its chunks are synced to disk. Paths listed there and held by the
+    /// manifest are skipped, so a run that was interrupted picks up where it stopped.
+    ///
+    /// A run that fails still writes the manifest for the files it listed. Chunks are written to
+    /// a custom storage one at a time, regardless of `concurrent_chunk_uploads`.
+    pub fn add_files_checkpointed(mut self, paths: Vec<String>, output_path: &str, checkpoint_path: &str) -> Result<ChunkStats> {
+        let listed: HashSet<String> = match fs::read_to_string(checkpoint_path) {
+            Ok(checkpoint) => checkpoint.lines().map(String::from).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(ChunkError::from(e).context(format!("Reading checkpoint {}", checkpoint_path))),
+        };
+        // A run that was killed may have listed files without writing the manifest
+        let completed: HashSet<String> = match self.load_manifest(output_path) {
+            Ok(manifest) => listed.into_iter().filter(|path| manifest.hashes.contains_key(path)).collect(),
+            Err(_) => HashSet::new(),
+        };
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(checkpoint_path)
+            .with_context(|| format!("Opening checkpoint {}", checkpoint_path))?;
+        let paths: Vec<String> = paths
+            .iter()
+            .map(|path| normalize_path(path))
+            .filter(|path| {
+                let skipped = completed.contains(path);
+                if skipped {
+                    tracing::info!(path = %path, "Completed before, skipping");
+                }
+                !skipped
+            })
+            .collect();
+        self.checkpoint = Some(Checkpoint {
+            path: checkpoint_path.to_string(),
+            file,
+            awaiting: vec![],
+            unfinished: paths.iter().cloned().collect(),
+            listed: false,
+        });
+        self.add_files(paths, output_path)
+    }
+
+    /// Records what `add_files` keeps of `paths` besides their content, and returns the paths
+    /// whose content is to be chunked.
+    fn record_sources(&mut self, paths: Vec<String>) -> Result<Vec<String>> {
+        let paths = self.record_symlinks(paths)?;
+        // Nothing is recorded of the files left to a later run
+        let paths = self.defer_excess_entries(paths);
+        if !self.options.skip_preflight_check {
+            preflight_check(&paths)?;
+        }
+        if self.options.preserve_xattrs {
+            self.record_xattrs(&paths)?;
+        }
+        #[cfg(all(feature = "posix-acl", target_os = "linux"))]
+        self.record_acls(&paths)?;
+        self.record_metadata(&paths)?;
+        #[cfg(feature = "perceptual-hash")]
+        if self.options.perceptual_hash_images {
+            self.record_phashes(&paths);
+        }
+        Ok(paths)
+    }
+
+    /// Returns `paths` normalized and sorted, without those past `max_manifest_entries`, which are
+    /// listed in [`ChunkStats::deferred_files`] instead.
+    fn defer_excess_entries(&mut self, paths: Vec<String>) -> Vec<String> {
+        let mut paths: Vec<String> = paths.iter().map(|path| normalize_path(path)).collect();
+        paths.sort_unstable();
+        if let Some(max) = self.options.max_manifest_entries.filter(|max| paths.len() > *max) {
+            tracing::warn!(max, deferred = paths.len() - max, "Reached the maximum number of manifest entries, stopping");
+            self.stats.deferred_files = paths.split_off(max);
+        }
+        paths
+    }
+
+    /// Chunks in-memory files as if they were read from disk under their map keys.
+    ///
+    /// Produces the same manifest `add_files` would for files with those names and contents, but
+    /// without [`FileMetadata`], as in-memory files have none.
+    pub fn add_file_map(mut self, files: HashMap<String, Vec<u8>>, output_path: &str) -> Result<ChunkStats> {
+        let files: HashMap<String, Vec<u8>> = files
+            .into_iter()
+            .map(|(path, bytes)| (normalize_path(&path), bytes))
+            .collect();
+        let paths = self.defer_excess_entries(files.keys().cloned().collect());
+        self.add_sources(paths, output_path, |path| Ok(files[path].clone()), |path| files[path].len() as u64)
+    }
+
+    /// Chunks a single in-memory buffer, stored in the manifest under `name`.
+    ///
+    /// Goes through the same chunking as `add_files`, so other files already in the manifest of
+    /// `output_path` are kept.
+    pub fn add_bytes(mut self, name: &str, bytes: &[u8], output_path: &str) -> Result<()> {
+        let paths = self.defer_excess_entries(vec![name.to_string()]);
+        self.add_sources(paths, output_path, |_| Ok(bytes.to_vec()), |_| bytes.len() as u64)?;
+        Ok(())
+    }
+
+    /// Predicts what `add_files` would store for `paths` in an empty output directory, by
+    /// chunking them with the same options but without writing anything.
+    pub fn estimate_chunks(&self, paths: &[String]) -> Result<ChunkEstimate> {
+        let options = ChunkerOptions {
+            storage: Some(Arc::new(NullStorage)),
+            rate_limit_bytes_per_sec: None,
+            min_free_space_bytes: None,
+            delta_chunks: false,
+            atomic_backup: false,
+            progress_path: None,
+            metrics_path: None,
+            #[cfg(any(test, feature = "testing"))]
+            simulate_write_failures: None,
+            ..self.options.clone()
+        };
+        let mut dry_run = Chunker::with_options(options);
+        dry_run.compression_ratio_samples = Some(vec![]);
+        let paths = dry_run.record_symlinks(paths.to_vec())?;
+        let paths = dry_run.defer_excess_entries(paths);
+        let stats = match dry_run.options.use_mmap {
+            true => dry_run.add_sources(paths, "", map_source, source_len)?,
+            false => dry_run.add_sources(paths, "", read_source, source_len)?,
+        };
+        let unique_bytes: u64 = dry_run.chunk_sizes.values().map(|(uncompressed, _)| uncompressed).sum();
+        let samples = dry_run.compression_ratio_samples.unwrap_or_default();
+        Ok(ChunkEstimate {
+            estimated_chunks: dry_run.chunk_sizes.len(),
+            estimated_unique_bytes: unique_bytes,
+            estimated_duplicate_bytes: stats.bytes_read - unique_bytes,
+            estimated_files: stats.files_processed,
+            estimated_compression_ratio: match samples.len() {
+                0 => 1.0,
+                len => samples.iter().sum::<f64>() / len as f64,
+            },
+        })
+    }
+
+    /// Records the targets of all symlinks in `paths` and returns the remaining paths.
+    pub(crate) fn record_symlinks(&mut self, paths: Vec<String>) -> Result<Vec<String>> {
+        let mut remaining = Vec::with_capacity(paths.len());
+        for path in paths.into_iter() {
+            if !fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_symlink()) {
+                remaining.push(path);
+                continue;
+            }
+            let target = fs::read_link(&path).with_context(|| format!("Reading symlink {}", path))?;
+            let target = normalize_path(&target.to_string_lossy());
+            tracing::info!(path = %path, target = %target, "Recording symlink");
+            self.symlinks.insert(normalize_path(&path), target);
+        }
+        Ok(remaining)
+    }
+
+    /// Records the extended attributes of all files in `paths`.
+    fn record_xattrs(&mut self, paths: &[String]) -> Result<()> {
+        for path in paths.iter() {
+            #[cfg(feature = "reqwest")]
+            if crate::http::is_url(path) {
+                continue;
+            }
+            let attrs = read_xattrs(path).with_context(|| format!("Reading extended attributes of {}", path))?;
+            if !attrs.is_empty() {
+                tracing::debug!(path = %path, count = attrs.len(), "Recording extended attributes");
+                self.xattrs.insert(normalize_path(path), attrs);
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the access ACLs of all files in `paths`.
+    #[cfg(all(feature = "posix-acl", target_os = "linux"))]
+    fn record_acls(&mut self, paths: &[String]) -> Result<()> {
+        for path in paths.iter() {
+            #[cfg(feature = "reqwest")]
+            if crate::http::is_url(path) {
+                continue;
+            }
+            if let Some(acl) = crate::acl::read_acl(path).with_context(|| format!("Reading ACL of {}", path))? {
+                tracing::debug!(path = %path, "Recording ACL");
+                self.file_acls.insert(normalize_path(path), acl);
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the modification time, permissions and owner of all files in `paths`.
+    fn record_metadata(&mut self, paths: &[String]) -> Result<()> {
+        for path in paths.iter() {
+            #[cfg(feature = "reqwest")]
+            if crate::http::is_url(path) {
+                continue;
+            }
+            let metadata = fs::metadata(path).with_context(|| format!("Reading metadata of {}", path))?;
+            self.metadata.insert(normalize_path(path), FileMetadata::from_fs(&metadata));
+        }
+        Ok(())
+    }
+
+    /// Records the perceptual hashes of all images in `paths`, skipping the ones that cannot be
+    /// decoded.
+    #[cfg(feature = "perceptual-hash")]
+    fn record_phashes(&mut self, paths: &[String]) {
+        for path in paths.iter().filter(|path| crate::phash::is_image(path)) {
+            #[cfg(feature = "reqwest")]
+            if crate::http::is_url(path) {
+                continue;
+            }
+            if let Some(phash) = crate::phash::perceptual_hash(path) {
+                tracing::debug!(path = %path, phash, "Recording perceptual hash");
+                self.phashes.insert(normalize_path(path), phash);
+            }
+        }
+    }
+
+    /// Records the MIME type `bytes`, the content of `path`, are detected as, forgetting the one
+    /// of an earlier version if there is none.
+    #[cfg(feature = "mime-detection")]
+    fn record_mime_type(&mut self, path: &str, bytes: &[u8]) {
+        match crate::mime::detect(bytes) {
+            Some(mime) => {
+                tracing::debug!(path = %path, mime = %mime, "Recording MIME type");
+                self.detected_mime_types.insert(path.to_string(), mime);
+            }
+            None => {
+                self.detected_mime_types.remove(path);
+            }
+        }
+    }
+
+    /// Chunks the sources at `paths`, whose contents `read` returns and whose sizes `len` does.
+    ///
+    /// `paths` are those left by [`Self::defer_excess_entries`], which callers run exactly once.
+    fn add_sources<F, B, L>(&mut self, paths: Vec<String>, output_path: &str, read: F, len: L) -> Result<ChunkStats>
+    where
+        F: FnMut(&str) -> Result<B>,
+        B: Deref<Target = [u8]>,
+        L: Fn(&str) -> u64,
+    {
+        let start = std::time::Instant::now();
+        #[cfg(feature = "encryption")]
+        if self.options.encrypt_filenames && self.options.filename_key.is_none() {
+            return Err(ChunkError::MissingFilenameKey);
+        }
+        self.check_free_space(output_path)?;
+        if !self.has_custom_storage() {
+            for mirror in self.options.mirror_paths.iter() {
+                if let Err(e) = fs::create_dir_all(mirror) {
+                    tracing::warn!(dir = %mirror, error = %e, "Unable to create mirror");
+                }
+            }
+        }
+        self.begin_pending(output_path)?;
+        self.load_ca_index(output_path)?;
+        // A checkpoint only lists files whose chunks are written
+        if self.has_custom_storage() && self.options.concurrent_chunk_uploads > 1 && self.checkpoint.is_none() {
+            self.uploads = Some(Arc::new(UploadQueue::new(self.storage(output_path), self.options.concurrent_chunk_uploads)));
+        }
+        let chunked = self.chunk_sources(paths, output_path, read, len, start);
+        // Joined on errors too, so no upload is left running once the call returns
+        let uploaded = self.uploads.take().map_or(Ok(()), |uploads| uploads.finish());
+        self.prefetcher = None;
+        if let Err(e) = chunked.and(uploaded) {
+            if let Some(checkpoint) = self.checkpoint.take().filter(|checkpoint| checkpoint.listed) {
+                // The files listed in the checkpoint make it into the manifest, for the next run to skip
+                self.forget_files(&checkpoint.unfinished);
+                self.dump_restore_info(output_path)?;
+            }
+            return Err(e);
+        }
+        self.save_ca_index(output_path)?;
+        self.dump_restore_info(output_path)?;
+        let stats = self.stats.clone().finish(start.elapsed());
+        if let Some(metrics_path) = &self.options.metrics_path {
+            write_metrics(metrics_path, &stats.to_prometheus_text("filechunk", output_path))?;
+        }
+        Ok(stats)
+    }
+
+    /// Splits the sources of [`Self::add_sources`] into chunks and stores them.
+    fn chunk_sources<F, B, L>(&mut self, mut paths: Vec<String>, output_path: &str, mut read: F, len: L, start: std::time::Instant) -> Result<()>
+    where
+        F: FnMut(&str) -> Result<B>,
+        B: Deref<Target = [u8]>,
+        L: Fn(&str) -> u64,
+    {
+        let mut chunk = self.first_chunk()?;
+        let mut rate_limiter = self.options.rate_limit_bytes_per_sec.map(RateLimiter::new);
+        let threads = self.options.parallel_compression_threads;
+        let mut pool = (threads > 1).then(|| CompressionPool::new(threads, self.options.hash_seed));
+        let unchanged = self.resume_from_manifest(&paths, output_path, &mut read)?;
+        if self.options.pre_sort_by_similarity {
+            paths = sort_by_similarity(paths, &unchanged, &mut read)?;
+        }
+        if let Some(prefetcher) = &self.prefetcher {
+            prefetcher.lock().unwrap().queue(paths.iter().filter(|path| !unchanged.contains(*path)).cloned());
+        }
+        let mut progress = match &self.options.progress_path {
+            Some(progress_path) => {
+                let bytes_total = paths.iter().filter(|path| !unchanged.contains(*path)).map(|path| len(path)).sum();
+                Some(ProgressLog::create(progress_path, paths.len(), bytes_total)?)
+            }
+            None => None,
+        };
+        // Chunks split off and stored so far, which tell when a file is stored completely
+        let (mut sealed, mut stored) = (0, 0);
+        let read_all = (|| -> Result<()> {
+            for path in paths.iter() {
+                let now = std::time::Instant::now();
+                tracing::debug!(path = %path, "Chunking file");
+                if unchanged.contains(path) {
+                    tracing::info!(path = %path, "Already chunked");
+                    self.await_chunks(path, 0);
+                    if let Some(progress) = progress.as_mut() {
+                        progress.file_done(path, 0, start.elapsed())?;
+                    }
+                    continue;
+                }
+                let bytes = read(path)?;
+                let bytes_len = bytes.len() as u64;
+                #[cfg(feature = "mime-detection")]
+                if self.options.detect_mime_types {
+                    self.record_mime_type(path, &bytes);
+                }
+                for full_chunk in self.split_file(&mut chunk, path, &bytes) {
+                    sealed += 1;
+                    // save old chunk
+                    for compressed in self.compress_chunk(pool.as_mut(), full_chunk) {
+                        self.store_chunk(compressed, output_path, rate_limiter.as_mut())?;
+                        stored += 1;
+                    }
+                }
+                // The end of the file is in the chunk split off next
+                self.await_chunks(path, sealed + 1);
+                self.checkpoint_files(stored, output_path)?;
+                if let Some(progress) = progress.as_mut() {
+                    progress.file_done(path, bytes_len, start.elapsed())?;
+                }
+                tracing::debug!(path = %path, elapsed = ?now.elapsed(), "Chunked file");
+            }
+            Ok(())
+        })();
+        // A checkpointed run keeps the files read before a failure, so their last chunk is stored
+        if read_all.is_err() && self.checkpoint.is_none() {
+            return read_all;
+        }
+        let mut remaining = vec![];
+        if let Some(last_chunk) = self.seal_last_chunk(chunk) {
+            // Save last chunk
+            remaining = self.compress_chunk(pool.as_mut(), last_chunk);
+        }
+        if let Some(pool) = pool {
+            remaining.extend(pool.finish());
+        }
+        for compressed in remaining {
+            self.store_chunk(compressed, output_path, rate_limiter.as_mut())?;
+            stored += 1;
+        }
+        self.checkpoint_files(stored, output_path)?;
+        read_all
+    }
+
+    /// Compresses `chunk` right away, or hands it to `pool` and returns whichever chunks the
+    /// pool has done next in line.
+    fn compress_chunk(&mut self, pool: Option<&mut CompressionPool>, chunk: Chunk) -> Vec<CompressedChunk> {
+        let codec = self.chunk_compression(&chunk);
+        if let Some(samples) = self.compression_ratio_samples.as_mut() {
+            let first_chunks = chunk.file_offsets.iter().filter(|&&offset| offset == 0).count();
+            if first_chunks > 0 {
+                samples.extend(std::iter::repeat_n(chunk.estimated_compression_ratio(codec), first_chunks));
+            }
+        }
+        match pool {
+            Some(pool) => pool.submit(chunk, codec),
+            // Compressed as it is saved
+            None if self.streams_compression(codec) => vec![(chunk, Ok((vec![], SavedChunk::streamed(codec))))],
+            None => {
+                let compressed = chunk.compress(codec, self.options.hash_seed);
+                vec![(chunk, compressed)]
+            }
+        }
+    }
+
+    /// Tells whether chunks compressed with `codec` are compressed while they are saved, see
+    /// [`ChunkerBuilder::streaming_compression`].
+    fn streams_compression(&self, codec: Compression) -> bool {
+        self.options.streaming_compression
+            && codec == Compression::Zstd
+            && self.options.parallel_compression_threads <= 1
+            && !self.has_custom_storage()
+            && self.options.mirror_paths.is_empty()
+            && !self.options.delta_chunks
+    }
+
+    fn store_chunk(&mut self, (chunk, compressed): CompressedChunk, output_path: &str, rate_limiter: Option<&mut RateLimiter>) -> Result<()> {
+        let saved = self.save_chunk(&chunk, output_path, compressed?)?;
+        self.record_saved_chunk(&chunk, &saved);
+        if !saved.reused && !self.deltas.contains_key(&chunk.name()) {
+            self.index_chunk(&chunk, saved.codec);
+        }
+        // Reused chunks are not written, so they take none of the bandwidth
+        if let Some(limiter) = rate_limiter.filter(|_| !saved.reused) {
+            std::thread::sleep(limiter.consume(saved.compressed_len));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_free_space(&self, output_path: &str) -> Result<()> {
+        let Some(required) = self.options.min_free_space_bytes else {
+            return Ok(());
+        };
+        if self.has_custom_storage() {
+            return Ok(());
+        }
+        let available = available_space(output_path)?;
+        if available < required {
+            return Err(ChunkError::InsufficientDiskSpace { available, required });
+        }
+        Ok(())
+    }
+
+    /// Returns where chunks and the manifest of `path` are kept: the storage set through
+    /// [`ChunkerBuilder::storage`], or else the directory `path` itself.
+    pub(crate) fn storage(&self, path: &str) -> Arc<dyn Storage> {
+        if let Some(uploads) = &self.uploads {
+            return uploads.clone();
+        }
+        match &self.options.storage {
+            Some(storage) => storage.clone(),
+            None if !self.options.mirror_paths.is_empty() => Arc::new(MirroredStorage::new(path, &self.options.mirror_paths, self.options.enable_reflink)),
+            None => Arc::new(LocalStorage::new(path)),
+        }
+    }
+
+    /// Returns the time of the clock set through [`ChunkerBuilder::clock`], or of [`SystemClock`].
+    pub(crate) fn now(&self) -> SystemTime {
+        match &self.options.clock {
+            Some(clock) => clock.now(),
+            None => SystemClock.now(),
+        }
+    }
+
+    /// Tells whether chunks and the manifest go to a storage set through [`ChunkerBuilder::storage`],
+    /// instead of the local output directory.
+    pub(crate) fn has_custom_storage(&self) -> bool {
+        self.options.storage.is_some()
+    }
+
+    /// Reads the manifest of `data_path`, or of the storage set through [`ChunkerBuilder::storage`].
+    pub(crate) fn load_manifest(&self, data_path: &str) -> Result<RestoreInformation> {
+        let restore_info = match &self.options.storage {
+            Some(storage) => RestoreInformation::parse_stored(&storage.read_manifest()?)?,
+            None => RestoreInformation::load(data_path)?,
+        };
+        #[cfg(feature = "encryption")]
+        if let Some(key) = &self.options.filename_key {
+            return restore_info.decrypted(key);
+        }
+        Ok(restore_info)
+    }
+
+    /// Returns the name `chunk_name` is stored under, which is a `.delta` file for delta chunks.
+    fn stored_chunk_name(&self, chunk_name: &str) -> String {
+        let prefix = self.options.chunk_name_prefix.as_deref();
+        match self.deltas.get(chunk_name) {
+            Some(base) => delta_file_name(prefix, base, chunk_name),
+            None => chunk_file_name(prefix, chunk_name),
+        }
+    }
+
+    /// Saves `chunk`, compressed through [`Chunk::compress`], to `output_path`.
+    ///
+    /// With `delta_chunks` enabled, a chunk that replaces one of the previous run is stored as the
+    /// XOR delta against it instead, if that turns out smaller.
+    fn save_chunk(&mut self, chunk: &Chunk, output_path: &str, compressed: (Vec<u8>, SavedChunk)) -> Result<SavedChunk> {
+        let name = chunk.name();
+        let storage = self.storage(output_path);
+        self.journal_chunk(&name)?;
+        #[cfg(any(test, feature = "testing"))]
+        self.simulate_write_failure()?;
+        if let (true, Some(hash)) = (self.deltas.contains_key(&name), self.chunk_hashes.get(&name)) {
+            tracing::debug!(chunk_name = %name, "Already stored as delta");
+            return Ok(SavedChunk {
+                hash: hash.clone(),
+                compressed_len: storage.chunk_size(&self.stored_chunk_name(&name))?,
+                reused: true,
+                codec: Compression::Snappy,
+            });
+        }
+        let file_name = chunk_file_name(self.options.chunk_name_prefix.as_deref(), &name);
+        if let Some(saved) = self.link_duplicate_chunk(chunk, output_path, &file_name)? {
+            return Ok(saved);
+        }
+        if self.streams_compression(compressed.1.codec) {
+            let known_hash = self.chunk_hashes.get(&name).map(String::as_str);
+            return chunk.save_streaming(output_path, &file_name, known_hash, self.options.hash_seed);
+        }
+        if !self.options.delta_chunks || storage.has_chunk(&file_name)? {
+            return chunk.save(&*storage, &file_name, compressed);
+        }
+        let Some((base, delta)) = self.compute_delta(chunk, &*storage) else {
+            return chunk.save(&*storage, &file_name, compressed);
+        };
+        if delta.len() >= compressed.0.len() {
+            return chunk.save(&*storage, &file_name, compressed);
+        }
+
+        let delta_name = delta_file_name(self.options.chunk_name_prefix.as_deref(), &base, &name);
+        tracing::info!(chunk_name = %name, base = %base, path = %delta_name, "Saving delta");
+        storage.write_chunk(&delta_name, &delta)?;
+        self.deltas.insert(name, base);
+        Ok(SavedChunk {
+            hash: content_hash(self.options.hash_seed, &delta),
+            compressed_len: delta.len() as u64,
+            reused: false,
+            codec: Compression::Snappy,
+        })
+    }
+
+    /// Reads `ca_index.yaml` of `output_path`, if `content_addressable_links` or
+    /// `hardlink_identical_chunks` is enabled. With the latter, chunk files not in the index yet
+    /// are added to it.
+    fn load_ca_index(&mut self, output_path: &str) -> Result<()> {
+        if !(self.options.content_addressable_links || self.options.hardlink_identical_chunks) || self.has_custom_storage() {
+            return Ok(());
+        }
+        let mut index: HashMap<String, IndexedChunk> = match fs::read(format!("{}/{}", output_path, CA_INDEX_FILE)) {
+            Ok(bytes) => serde_yaml::from_slice(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        if self.options.hardlink_identical_chunks {
+            self.index_stored_chunks(output_path, &mut index)?;
+        }
+        self.ca_index = Some(index);
+        Ok(())
+    }
+
+    /// Adds the chunk files of `output_path` missing from `index`, such as those of backups made
+    /// without it, by the hash of their decompressed content.
+    fn index_stored_chunks(&self, output_path: &str, index: &mut HashMap<String, IndexedChunk>) -> Result<()> {
+        let known: HashSet<String> = index
+            .values()
+            .flat_map(|indexed| std::iter::once(&indexed.chunk_file).chain(&indexed.links).chain(&indexed.duplicates))
+            .cloned()
+            .collect();
+        for entry in fs::read_dir(output_path)? {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if !entry.file_type()?.is_file() || !file_name.ends_with(".chunk") || known.contains(&file_name) {
+                continue;
+            }
+            let stored = fs::read(entry.path())?;
+            let (header, payload) = ChunkHeader::split(&stored).with_context(|| format!("Reading {}", file_name))?;
+            let codec = header.map_or(Compression::Snappy, |header| header.codec);
+            let content = codec.decompress(payload).with_context(|| format!("Decompressing {}", file_name))?;
+            match index.entry(content_hash(self.options.hash_seed, &content)) {
+                Entry::Occupied(mut entry) => entry.get_mut().duplicates.push(file_name),
+                Entry::Vacant(entry) => {
+                    entry.insert(IndexedChunk {
+                        chunk_file: file_name,
+                        codec,
+                        links: vec![],
+                        duplicates: vec![],
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn save_ca_index(&self, output_path: &str) -> Result<()> {
+        let Some(index) = &self.ca_index else {
+            return Ok(());
+        };
+        fs::write(format!("{}/{}", output_path, CA_INDEX_FILE), serde_yaml::to_string(index)?)
+            .with_context(|| format!("Writing {}", CA_INDEX_FILE))
+    }
+
+    /// Records that the chunk file of `chunk` holds its content, for `content_addressable_links`.
+    fn index_chunk(&mut self, chunk: &Chunk, codec: Compression) {
+        let Some(index) = self.ca_index.as_mut() else {
+            return;
+        };
+        let chunk_file = chunk_file_name(self.options.chunk_name_prefix.as_deref(), &chunk.name());
+        index
+            .entry(content_hash(self.options.hash_seed, &chunk.buffer))
+            .or_insert(IndexedChunk {
+                chunk_file,
+                codec,
+                links: vec![],
+                duplicates: vec![],
+            });
+    }
+
+    /// Hard links `file_name` to an existing chunk file with the same content as `chunk` instead
+    /// of writing it, if `content_addressable_links` or `hardlink_identical_chunks` is enabled and
+    /// there is one.
+    fn link_duplicate_chunk(&mut self, chunk: &Chunk, output_path: &str, file_name: &str) -> Result<Option<SavedChunk>> {
+        let content = content_hash(self.options.hash_seed, &chunk.buffer);
+        let Some(index) = self.ca_index.as_mut() else {
+            return Ok(None);
+        };
+        let target = format!("{}/{}", output_path, file_name);
+        if Path::new(&target).exists() {
+            return Ok(None);
+        }
+        let Some(indexed) = index.get_mut(&content) else {
+            return Ok(None);
+        };
+        let source = format!("{}/{}", output_path, indexed.chunk_file);
+        if let Err(e) = fs::hard_link(&source, &target) {
+            // Such as after `gc` removed it, the chunk is written instead
+            tracing::debug!(source = %source, error = %e, "Not linking duplicate chunk");
+            return Ok(None);
+        }
+        tracing::info!(chunk_name = %chunk.name(), path = %file_name, source = %indexed.chunk_file, "Linking duplicate chunk");
+        indexed.links.push(file_name.to_string());
+        Ok(Some(SavedChunk {
+            hash: content_hash(self.options.hash_seed, &fs::read(&target)?),
+            compressed_len: 0,
+            reused: false,
+            codec: indexed.codec,
+        }))
+    }
+
+    /// Picks the codec for `chunk`: the one it was stored with before, if any, or else the one
+    /// for the file with the most bytes in it. That is none for content detected as compressed
+    /// already by `detect_mime_types`, else the first `per_file_compression` pattern matching it.
+    pub(crate) fn chunk_compression(&self, chunk: &Chunk) -> Compression {
+        if let Some(codec) = self.chunk_codecs.get(&chunk.name()) {
+            return *codec;
+        }
+        // Earliest file wins ties
+        let Some(file) = chunk.base.files.iter().rev().max_by_key(|file| file.byte_len()) else {
+            return Compression::Snappy;
+        };
+        #[cfg(feature = "mime-detection")]
+        if self.options.detect_mime_types
+            && self.detected_mime_types.get(&file.filename).is_some_and(|mime| crate::mime::is_compressed(mime))
+        {
+            return Compression::None;
+        }
+        self.options
+            .per_file_compression
+            .iter()
+            .find(|(pattern, _)| glob_matches(pattern, &file.filename))
+            .map(|(_, codec)| *codec)
+            .unwrap_or_default()
+    }
+
+    /// Fails with the probability set through `simulate_write_failures`.
+    #[cfg(any(test, feature = "testing"))]
+    fn simulate_write_failure(&mut self) -> std::io::Result<()> {
+        let Some(probability) = self.options.simulate_write_failures else {
+            return Ok(());
+        };
+        // xorshift64 with a fixed seed, so failing runs are reproducible
+        self.write_failure_state ^= self.write_failure_state << 13;
+        self.write_failure_state ^= self.write_failure_state >> 7;
+        self.write_failure_state ^= self.write_failure_state << 17;
+        let sample = (self.write_failure_state >> 11) as f64 / (1u64 << 53) as f64;
+        if sample < probability {
+            return Err(std::io::Error::other("Simulated write failure"));
+        }
+        Ok(())
+    }
+
+    /// Pairs `chunk` with the chunk at the same position in the previous version of one of its
+    /// files, and returns that chunk's name along with the compressed XOR delta against it.
+    fn compute_delta(&self, chunk: &Chunk, storage: &dyn Storage) -> Option<(String, Vec<u8>)> {
+        let previous = self.previous_manifest.as_ref()?;
+        let base = chunk.base.files.iter().find_map(|file| {
+            // The chunk is already recorded, so it is the last one of the file
+            let index = self.bases.get(&file.filename)?.len().checked_sub(1)?;
+            let (base, _) = previous.files.get(&file.filename)?.get_index(index)?;
+            Some(base.clone())
+        })?;
+        let old = previous.read_chunk(storage, &base).ok()?;
+        Some((base, Compression::Snappy.compress(&xor_delta(&old, &chunk.buffer))))
+    }
+
+    fn parallel_hash_threshold(&self) -> u64 {
+        self.options.parallel_hash_threshold_bytes.unwrap_or(DEFAULT_PARALLEL_HASH_THRESHOLD_BYTES)
+    }
+
+    /// Returns where the chunks of `bytes`, the rest of a file going into `chunk`, end along with
+    /// their fingerprints, found on every core. Empty unless `chunk` is cut by FastCDC and the file
+    /// reaches [`ChunkerBuilder::parallel_scan_threshold_bytes`](crate::ChunkerBuilder::parallel_scan_threshold_bytes).
+    fn scan_boundaries(&self, chunk: &Chunk, bytes: &[u8]) -> Vec<(usize, RabinFingerprint)> {
+        let threshold = self.options.parallel_scan_threshold_bytes.unwrap_or(DEFAULT_PARALLEL_SCAN_THRESHOLD_BYTES);
+        let Some(cutter) = chunk.cutter.as_ref().filter(|_| bytes.len() as u64 >= threshold) else {
+            return vec![];
+        };
+        let segment_len = bytes.len().div_ceil(rayon::current_num_threads()).max(MIN_SCAN_SEGMENT_BYTES);
+        let ends = cutter.boundaries(bytes, segment_len);
+        let starts = std::iter::once(0).chain(ends.iter().copied());
+        let ranges: Vec<(usize, usize)> = starts.zip(ends.iter().copied()).collect();
+        ranges
+            .into_par_iter()
+            .map(|(start, end)| {
+                // Only the first chunk holds data of earlier files
+                let mut fingerprint = match start {
+                    0 => chunk.base.fingerprint,
+                    _ => chunk.base.fingerprint.reset(),
+                };
+                for &byte in bytes[start..end].iter() {
+                    fingerprint.push_byte(byte);
+                }
+                (end, fingerprint)
+            })
+            .collect()
+    }
+
+    /// Records the content hash of `path`, returning `false` if identical content was already seen.
+    fn register_file(&mut self, path: &str, bytes: &[u8]) -> bool {
+        let file_hash_blake = file_hash(self.options.hash_seed, bytes, self.parallel_hash_threshold());
+        self.path_to_hash_map.insert(path.to_string(), file_hash_blake.clone());
+        match self.hash_to_path_map.get_mut(&file_hash_blake) {
+            None => {
+                self.hash_to_path_map.insert(file_hash_blake, vec![path.to_string()]);
+                true
+            }
+            Some(h) => {
+                h.push(path.to_string());
+                false
+            }
+        }
+    }
+
+    /// Feeds the content of `path` into `chunk`, returning every chunk that was completed on the way.
+    ///
+    /// The returned chunks are already recorded in the restore information and only need saving.
+    pub(crate) fn split_file(&mut self, chunk: &mut Chunk, path: &str, bytes: &[u8]) -> Vec<Chunk> {
+        let stream_offset = self.stats.bytes_read;
+        self.stats.files_processed += 1;
+        self.stats.bytes_read += bytes.len() as u64;
+        if !self.register_file(path, bytes) {
+            // We can skip processing now, since we already have this file
+            tracing::info!(path = %path, "Skipping duplicate file");
+            self.stats.chunks_reused += self.chunks_referenced_by_duplicate(path, chunk);
+            return vec![];
+        }
+        if bytes.is_empty() {
+            // Empty files are in no chunk, but still need an entry to be restorable
+            self.bases.entry(path.to_string()).or_default();
+            return vec![];
+        }
+        if self.options.window_dedup_stats {
+            self.count_windows(chunk.base.fingerprint.reset(), stream_offset, bytes);
+        }
+
+        let mut completed = vec![];
+        let len = bytes.len() as u64;
+        let mut remaining_bytes = bytes;
+        let mut boundaries = 0;
+        let mut chunked_bytes = 0;
+        let mut fixed_size = false;
+        // Chunks of the file so far by content hash, for `deduplicate_within_file`
+        let mut seen = HashMap::new();
+        let mut scanned = self.scan_boundaries(chunk, bytes).into_iter();
+        while !remaining_bytes.is_empty() {
+            if self.options.max_chunks_per_file.is_some_and(|max| boundaries >= max) {
+                tracing::warn!(path = %path, "File {} exceeded max chunk count", path);
+                // The rest of the file goes into a single chunk of its own
+                chunk.unbounded = true;
+                chunk.add_file(path, len - remaining_bytes.len() as u64, remaining_bytes);
+                let next = chunk.next();
+                completed.extend(self.seal_file_chunk(std::mem::replace(chunk, next), &mut seen));
+                break;
+            }
+            let offset = len - remaining_bytes.len() as u64;
+            remaining_bytes = match scanned.next() {
+                Some((end, fingerprint)) => {
+                    chunk.add_scanned(path, offset, &bytes[offset as usize..end], fingerprint);
+                    &bytes[end..]
+                }
+                None => chunk.add_file(path, offset, remaining_bytes),
+            };
+            if chunk.is_boundary() {
+                tracing::debug!(chunk_name = %chunk.name(), "Found chunk boundary");
+                boundaries += 1;
+                chunked_bytes += chunk.buffer_len() as u64;
+                let next = chunk.next();
+                completed.extend(self.seal_file_chunk(std::mem::replace(chunk, next), &mut seen));
+                if !fixed_size && self.falls_back_to_fixed_size(boundaries as u64, chunked_bytes) {
+                    let size = self.options.min_chunk_bytes.unwrap_or_default();
+                    tracing::warn!(
+                        path = %path,
+                        "Rabin chunks of {} average {} bytes, using chunks of {} bytes for the rest of it",
+                        path,
+                        chunked_bytes / boundaries as u64,
+                        size
+                    );
+                    // Cut after every `size` bytes
+                    chunk.cutter = FastCdc::new(size, size, size).ok();
+                    fixed_size = true;
+                }
+            }
+        }
+        if fixed_size {
+            // The next file is chunked with Rabin again
+            chunk.cutter = None;
+        }
+        completed
+    }
+
+    /// Counts the windows of `bytes`, which start at `stream_offset` of the bytes read, in
+    /// [`ChunkStats::window_dedup`], fingerprinting them with the empty `fingerprint`.
+    fn count_windows(&mut self, mut fingerprint: RabinFingerprint, stream_offset: u64, bytes: &[u8]) {
+        let stats = &mut self.stats.window_dedup;
+        for (i, &byte) in bytes.iter().enumerate() {
+            match i.checked_sub(WINDOW_SIZE) {
+                Some(oldest) => fingerprint.roll_byte(bytes[oldest], byte),
+                None => fingerprint.push_byte(byte),
+            }
+            let Some(start) = (i + 1).checked_sub(WINDOW_SIZE) else {
+                continue;
+            };
+            stats.windows += 1;
+            match self.window_offsets.entry(fingerprint.value()) {
+                Entry::Occupied(_) => stats.repeated_windows += 1,
+                Entry::Vacant(entry) => {
+                    entry.insert(stream_offset + start as u64);
+                }
+            }
+        }
+    }
+
+    /// Tells whether the Rabin chunks of a file so far are small enough on average to switch to
+    /// fixed-size chunks, see [`ChunkerBuilder::min_chunk_bytes`].
+    fn falls_back_to_fixed_size(&self, chunks: u64, chunked_bytes: u64) -> bool {
+        match self.options.min_chunk_bytes {
+            Some(min) if self.options.cdc_algorithm == CdcAlgorithm::Rabin => {
+                chunks >= FALLBACK_AFTER_CHUNKS && chunked_bytes / chunks < min / 2
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the chunk a run starts with, set up for the configured [`CdcAlgorithm`](crate::CdcAlgorithm).
+    pub(crate) fn first_chunk(&self) -> Result<Chunk> {
+        let fingerprint = new_fingerprint(self.options.fingerprint_prime)?;
+        let chunk = Chunk::new(fingerprint)
+            .with_cutter(self.options.cdc_algorithm.cutter()?)
+            .with_bounds(self.options.split_strategy.bounds()?);
+        #[cfg(feature = "encryption")]
+        let chunk = chunk.with_header_key(self.options.filename_key.filter(|_| self.options.encrypt_filenames));
+        Ok(chunk)
+    }
+
+    /// Completes the trailing chunk of a run, if it holds any data.
+    pub(crate) fn seal_last_chunk(&mut self, chunk: Chunk) -> Option<Chunk> {
+        if chunk.is_empty() {
+            return None;
+        }
+        tracing::debug!(chunk_name = %chunk.name(), "Sealing last chunk");
+        Some(self.seal_chunk(chunk))
+    }
+
+    fn seal_chunk(&mut self, mut chunk: Chunk) -> Chunk {
+        chunk.repair();
+        self.update_restore_info(&chunk);
+        chunk
+    }
+
+    /// Seals a chunk completed within a file. With `deduplicate_within_file`, a chunk of the file
+    /// alone whose content `seen`, the earlier such chunks by content hash, holds already is
+    /// recorded as a reference to that one and not returned for saving.
+    fn seal_file_chunk(&mut self, mut chunk: Chunk, seen: &mut HashMap<String, String>) -> Option<Chunk> {
+        if !self.options.deduplicate_within_file || chunk.base.files.len() != 1 {
+            return Some(self.seal_chunk(chunk));
+        }
+        let hash = content_hash(self.options.hash_seed, &chunk.buffer);
+        let Some(earlier) = seen.get(&hash) else {
+            seen.insert(hash, chunk.name());
+            return Some(self.seal_chunk(chunk));
+        };
+        tracing::debug!(chunk_name = %chunk.name(), earlier = %earlier, "Chunk repeats within file");
+        chunk.base.files[0].forward_ref = Some(earlier.clone());
+        self.update_restore_info(&chunk);
+        self.stats.chunks_reused += 1;
+        None
+    }
+
+    /// Counts the chunks holding the content of the file that `path` duplicates.
+    fn chunks_referenced_by_duplicate(&self, path: &str, open_chunk: &Chunk) -> usize {
+        let original = &self.hash_to_path_map[&self.path_to_hash_map[path]][0];
+        let sealed = self.bases.get(original).map_or(0, |b| b.len());
+        let resumed = self.resumed_files.get(original).map_or(0, |f| f.len());
+        let open = open_chunk.base.files.iter().any(|f| &f.filename == original) as usize;
+        sealed + resumed + open
+    }
+
+    pub(crate) fn record_saved_chunk(&mut self, chunk: &Chunk, saved: &SavedChunk) {
+        self.chunk_hashes.insert(chunk.name(), saved.hash.clone());
+        self.chunk_sizes.insert(chunk.name(), (chunk.buffer_len() as u64, saved.compressed_len));
+        // A reused chunk keeps the codec it was first stored with
+        if !saved.reused && saved.codec != Compression::Snappy {
+            self.chunk_codecs.insert(chunk.name(), saved.codec);
+        }
+        if saved.reused {
+            self.stats.chunks_reused += 1;
+        } else {
+            self.stats.chunks_written += 1;
+            self.stats.bytes_written_compressed += saved.compressed_len;
+        }
+    }
+
+    /// Seeds the chunker with the manifest of a previous run in `output_path`, if any.
+    ///
+    /// Returns the paths whose content is unchanged since that run, so they can be skipped.
+    /// Files that changed are dropped from the previous manifest and chunked again.
+    ///
+    /// Fails if the previous run used a different hash seed, its hashes could not be compared.
+    pub(crate) fn resume_from_manifest<F, B>(&mut self, paths: &[String], output_path: &str, read: &mut F) -> Result<HashSet<String>>
+    where
+        F: FnMut(&str) -> Result<B>,
+        B: Deref<Target = [u8]>,
+    {
+        let previous = match self.load_manifest(output_path) {
+            Ok(previous) => previous,
+            Err(_) => return Ok(HashSet::new()),
+        };
+        if previous.hash_seed != self.options.hash_seed {
+            return Err(ChunkError::HashSeedMismatch {
+                manifest: previous.hash_seed,
+                chunker: self.options.hash_seed,
+            });
+        }
+
+        let mut changed = HashSet::new();
+        for path in paths.iter() {
+            let path = normalize_path(path);
+            if let Some(previous_hash) = previous.hashes.get(&path) {
+                let bytes = read(&path)?;
+                if &file_hash(self.options.hash_seed, &bytes, self.parallel_hash_threshold()) != previous_hash {
+                    tracing::info!(path = %path, "Changed since last run");
+                    let current_size = bytes.len() as u64;
+                    match previous.file_len(&path) {
+                        Some(previous_size) if current_size < previous_size => {
+                            tracing::warn!(path = %path, previous_size, current_size, "File was truncated since last run");
+                            self.stats.truncated_files.push(TruncatedFile {
+                                path: path.clone(),
+                                previous_size,
+                                current_size,
+                            });
+                        }
+                        _ => {}
+                    }
+                    changed.insert(path);
+                }
+            }
+        }
+
+        // Group paths by content, the path holding the chunk references comes first
+        let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+        for (path, hash) in previous.hashes.iter() {
+            let group = groups.entry(hash.clone()).or_default();
+            if previous.files.contains_key(path) {
+                group.insert(0, path.clone());
+            } else {
+                group.push(path.clone());
+            }
+        }
+
+        let mut unchanged = HashSet::new();
+        for (hash, group) in groups.into_iter() {
+            // Duplicates of a changed file lose their data and need to be chunked again
+            if changed.contains(&group[0]) {
+                continue;
+            }
+            let group: Vec<String> = group.into_iter().filter(|p| !changed.contains(p)).collect();
+            for path in group.iter() {
+                self.path_to_hash_map.insert(path.clone(), hash.clone());
+                unchanged.insert(path.clone());
+            }
+            if let Some(file_map) = previous.files.get(&group[0]) {
+                self.resumed_files.insert(group[0].clone(), file_map.clone());
+            }
+            if let Some(forward_refs) = previous.forward_refs.get(&group[0]) {
+                self.forward_refs.insert(group[0].clone(), forward_refs.clone());
+            }
+            self.hash_to_path_map.insert(hash, group);
+        }
+        self.chunk_hashes.extend(previous.chunk_hashes.clone());
+        self.deltas.extend(previous.deltas.clone());
+        self.chunk_codecs.extend(previous.chunk_codecs.clone());
+        self.chunk_timestamps.extend(previous.chunk_timestamps.clone());
+        for (path, target) in previous.symlinks.iter() {
+            self.symlinks.entry(path.clone()).or_insert(target.clone());
+        }
+        // Files whose attributes were read again keep only the current ones
+        let reread: HashSet<String> = match self.options.preserve_xattrs {
+            true => paths.iter().map(|path| normalize_path(path)).collect(),
+            false => HashSet::new(),
+        };
+        for (path, attrs) in previous.xattrs.iter().filter(|(path, _)| !reread.contains(*path)) {
+            self.xattrs.entry(path.clone()).or_insert(attrs.clone());
+        }
+        let current: HashSet<String> = match cfg!(all(feature = "posix-acl", target_os = "linux")) {
+            true => paths.iter().map(|path| normalize_path(path)).collect(),
+            false => HashSet::new(),
+        };
+        for (path, acl) in previous.file_acls.iter().filter(|(path, _)| !current.contains(*path)) {
+            self.file_acls.entry(path.clone()).or_insert(acl.clone());
+        }
+        #[cfg(feature = "perceptual-hash")]
+        let rehashed: HashSet<String> = match self.options.perceptual_hash_images {
+            true => paths.iter().map(|path| normalize_path(path)).collect(),
+            false => HashSet::new(),
+        };
+        #[cfg(not(feature = "perceptual-hash"))]
+        let rehashed: HashSet<String> = HashSet::new();
+        for (path, phash) in previous.phashes.iter().filter(|(path, _)| !rehashed.contains(*path)) {
+            self.phashes.entry(path.clone()).or_insert(*phash);
+        }
+        for (path, mime) in previous.detected_mime_types.iter() {
+            self.detected_mime_types.entry(path.clone()).or_insert(mime.clone());
+        }
+        for (path, metadata) in previous.metadata.iter() {
+            self.metadata.entry(path.clone()).or_insert(*metadata);
+        }
+        self.previous_manifest = Some(previous);
+        Ok(unchanged)
+    }
+
+    fn update_restore_info(&mut self, filename: &Chunk) {
+        tracing::debug!(base = ?filename.base, "Updating restore info");
+        if filename.base.files.len() > 1 {
+            // We need to rename the base.name for the all files, except the last one to the last one
+            let last_base = filename.base.files.last().unwrap();
+            let last_base_name = last_base.name.clone();
+            for base in filename.base.files.iter() {
+                let mut base_clone = base.clone();
+                base_clone.name = last_base_name.clone();
+                self.update_restore_info_for_file(&base_clone, filename);
+            }
+        } else {
+            for base in filename.base.files.iter() {
+                self.update_restore_info_for_file(base, filename);
+            }
+        }
+    }
+
+    fn update_restore_info_for_file(&mut self, file: &ChunkFile, chunk: &Chunk) {
+        tracing::debug!(
+            chunk_name = %file.name,
+            file = %file.filename,
+            start = file.start,
+            end = file.end,
+            "Updating restore info for file"
+        );
+        let bases = self.bases.entry(file.filename.clone()).or_default();
+        // A chunk lists a file once for every range of it, but is one chunk of the file. A repeated
+        // chunk has a single range and follows an identical one
+        if bases.last() != Some(&chunk.base) || file.forward_ref.is_some() {
+            bases.push(chunk.base.clone());
+        }
+    }
+
+    pub(crate) fn dump_restore_info(&mut self, output_path: &str) -> Result<()> {
+
+        let mut restore_info = RestoreInformation {
+            format_version: MANIFEST_FORMAT_VERSION.to_string(),
+            chunk_name_prefix: self.options.chunk_name_prefix.clone(),
+            symlinks: self.symlinks.clone(),
+            xattrs: self.xattrs.clone(),
+            file_acls: self.file_acls.clone(),
+            phashes: self.phashes.clone(),
+            detected_mime_types: self.detected_mime_types.clone(),
+            metadata: self.metadata.clone(),
+            forward_refs: self.forward_refs.clone(),
+            ..Default::default()
+        };
+
+        restore_info.files.extend(self.resumed_files.clone());
+
+        for (filename, bases) in self.bases.iter() {
+            tracing::debug!(file = %filename, bases = ?bases, "Writing manifest entry");
+
+            let mut file_map: IndexMap<String, StartEndTuple> = IndexMap::new();
+            let mut forward_refs = vec![];
+            for base in bases.iter() {
+                for chunk_file in base.files.iter() {
+                    if &chunk_file.filename != filename{
+                        continue;
+                    }
+                    if let Some(chunk) = &chunk_file.forward_ref {
+                        forward_refs.push(ForwardRef {
+                            position: (file_map.len() + forward_refs.len()) as u64,
+                            chunk: chunk.clone(),
+                        });
+                        continue;
+                    }
+                    match file_map.get_mut(&chunk_file.name) {
+                        // Already recorded through another base of the same chunk
+                        Some(range) if range.start == chunk_file.start && range.end == chunk_file.end => {}
+                        // The file continues within the chunk
+                        Some(range) if range.end == chunk_file.start => range.end = chunk_file.end,
+                        _ => {
+                            file_map.insert(
+                                chunk_file.name.clone(),
+                                StartEndTuple {
+                                    start: chunk_file.start,
+                                    end: chunk_file.end,
+                                },
+                            );
+                        }
+                    }
+                }
+            }
+            restore_info.files.insert(filename.to_string(), file_map);
+            if forward_refs.is_empty() {
+                restore_info.forward_refs.remove(filename);
+            } else {
+                restore_info.forward_refs.insert(filename.to_string(), forward_refs);
+            }
+        }
+        restore_info.hashes = self.path_to_hash_map.clone();
+        // Filter out all keys, where there value is only len 1
+        restore_info.duplicates = self.hash_to_path_map.clone().into_iter().filter(|(_, v)| v.len() > 1).collect();
+        restore_info.chunk_hashes = self.chunk_hashes.clone();
+        restore_info.deltas = self.deltas.clone();
+        restore_info.chunk_codecs = self.chunk_codecs.clone();
+        let now = unix_secs(self.now());
+        restore_info.chunk_timestamps = self
+            .chunk_hashes
+            .keys()
+            .chain(self.deltas.keys())
+            .map(|chunk_name| (chunk_name.clone(), self.chunk_timestamps.get(chunk_name).copied().unwrap_or(now)))
+            .collect();
+        // The manifest history is kept next to local backups only
+        let mut previous_chunks = HashMap::new();
+        if !self.has_custom_storage() {
+            if let Ok(previous) = RestoreInformation::load(output_path) {
+                previous_chunks = previous.chunk_hashes;
+            }
+            restore_info.previous_manifest_hash = archive_previous_manifest(output_path)?;
+        }
+        restore_info.manifest_hash = Some(restore_info.audit_hash(&previous_chunks));
+        restore_info.fingerprint_prime = self.options.fingerprint_prime;
+        restore_info.hash_seed = self.options.hash_seed;
+        restore_info.file_info = restore_info
+            .files
+            .iter()
+            .map(|(filename, file_map)| {
+                let info = FileInfo {
+                    chunks_count: file_map.len() as u32,
+                };
+                (filename.clone(), info)
+            })
+            .collect();
+        restore_info.compression_stats = restore_info
+            .files
+            .iter()
+            .filter_map(|(filename, file_map)| Some((filename.clone(), self.compression_stats(filename, file_map)?)))
+            .collect();
+
+        #[cfg(feature = "encryption")]
+        if let Some(key) = self.options.filename_key.filter(|_| self.options.encrypt_filenames) {
+            restore_info = restore_info.map_paths(|path| Ok(crate::filename_crypto::encrypt_path(&key, path)))?;
+            restore_info.encrypted_filenames = true;
+        }
+
+        let text_manifest = (self.options.text_manifest && !self.has_custom_storage()).then(|| restore_info.text_manifest());
+        let yaml = serde_yaml::to_string(&restore_info)?;
+        let manifest = if self.options.compress_manifest {
+            zstd::encode_all(yaml.as_bytes(), MANIFEST_ZSTD_LEVEL)?
+        } else {
+            yaml.into_bytes()
+        };
+        if let Some(storage) = &self.options.storage {
+            return storage.write_manifest(&manifest);
+        }
+        let path = self.manifest_path(output_path);
+        let signature = self.options.manifest_hmac_secret.as_ref().map(|secret| {
+            manifest_hmac(secret, &manifest).finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect::<String>()
+        });
+        if let Some(signature) = &signature {
+            let sig_path = format!("{}/{}", output_path, MANIFEST_SIG_FILE);
+            fs::write(&sig_path, signature).with_context(|| format!("Writing {}", sig_path))?;
+        }
+        match self.pending.take() {
+            Some(pending) => self.commit_pending(pending, output_path, &manifest)?,
+            None => fs::write(&path, &manifest).with_context(|| format!("Writing {}", path))?,
+        }
+        if self.options.compress_manifest {
+            // Don't leave a stale manifest from an earlier run around, it would take precedence
+            let _ = fs::remove_file(format!("{}/restore_info.yaml", output_path));
+        }
+        if let Some(text) = &text_manifest {
+            let text_path = format!("{}/{}", output_path, TEXT_MANIFEST_FILE);
+            fs::write(&text_path, text).with_context(|| format!("Writing {}", text_path))?;
+        }
+        // Every mirror is a complete backup of its own
+        for mirror in self.options.mirror_paths.iter() {
+            let written = LocalStorage::new(mirror).write_manifest(&manifest).and_then(|()| match &signature {
+                Some(signature) => Ok(fs::write(format!("{}/{}", mirror, MANIFEST_SIG_FILE), signature)?),
+                None => Ok(()),
+            });
+            let written = written.and_then(|()| match &text_manifest {
+                Some(text) => Ok(fs::write(format!("{}/{}", mirror, TEXT_MANIFEST_FILE), text)?),
+                None => Ok(()),
+            });
+            if let Err(e) = written {
+                tracing::warn!(dir = %mirror, error = %e, "Unable to write the manifest to mirror");
+            }
+        }
+        Ok(())
+    }
+
+    fn manifest_path(&self, output_path: &str) -> String {
+        if self.options.compress_manifest {
+            format!("{}/restore_info.yaml.zst", output_path)
+        } else {
+            format!("{}/restore_info.yaml", output_path)
+        }
+    }
+
+    /// Starts the `.pending` journal of an atomic backup, if `atomic_backup` is enabled or the run
+    /// is checkpointed.
+    pub(crate) fn begin_pending(&mut self, output_path: &str) -> Result<()> {
+        if !(self.options.atomic_backup || self.checkpoint.is_some()) || self.has_custom_storage() {
+            return Ok(());
+        }
+        let journal = fs::File::create(format!("{}.pending", self.manifest_path(output_path)))?;
+        self.pending = Some(PendingManifest {
+            journal,
+            chunks: vec![],
+            synced: 0,
+        });
+        Ok(())
+    }
+
+    /// Lists `chunk_name` in the `.pending` journal before the chunk is written.
+    pub(crate) fn journal_chunk(&mut self, chunk_name: &str) -> std::io::Result<()> {
+        if let Some(pending) = self.pending.as_mut() {
+            writeln!(pending.journal, "{}", chunk_name)?;
+            pending.chunks.push(chunk_name.to_string());
+        }
+        Ok(())
+    }
+
+    /// Syncs every chunk of the run to disk, then replaces the journal with `manifest` and
+    /// renames it into place, so the manifest only becomes visible complete.
+    fn commit_pending(&self, pending: PendingManifest, output_path: &str, manifest: &[u8]) -> Result<()> {
+        drop(pending.journal);
+        self.sync_chunks(&pending.chunks[pending.synced..], output_path)?;
+        let path = self.manifest_path(output_path);
+        let pending_path = format!("{}.pending", path);
+        let mut file = fs::File::create(&pending_path).with_context(|| format!("Creating {}", pending_path))?;
+        file.write_all(manifest)
+            .and_then(|()| file.sync_all())
+            .with_context(|| format!("Writing {}", pending_path))?;
+        fs::rename(&pending_path, &path).with_context(|| format!("Renaming {} to {}", pending_path, path))
+    }
+
+    fn sync_chunks(&self, chunk_names: &[String], output_path: &str) -> Result<()> {
+        for chunk_name in chunk_names.iter() {
+            let chunk_path = format!("{}/{}", output_path, self.stored_chunk_name(chunk_name));
+            // Chunks written to a sink instead of `output_path` are not ours to sync
+            if let Ok(file) = fs::File::open(&chunk_path) {
+                file.sync_all().with_context(|| format!("Syncing {}", chunk_path))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Notes that `path` is finished once `chunks` chunks of a checkpointed run are stored.
+    fn await_chunks(&mut self, path: &str, chunks: usize) {
+        if let Some(checkpoint) = self.checkpoint.as_mut() {
+            checkpoint.awaiting.push((path.to_string(), chunks));
+        }
+    }
+
+    /// Lists the files finished with `stored` chunks of the run in the checkpoint, once the chunks
+    /// written so far are synced to disk.
+    fn checkpoint_files(&mut self, stored: usize, output_path: &str) -> Result<()> {
+        let Some(checkpoint) = self.checkpoint.as_mut() else {
+            return Ok(());
+        };
+        let (finished, awaiting): (Vec<_>, Vec<_>) = std::mem::take(&mut checkpoint.awaiting)
+            .into_iter()
+            .partition(|(_, chunks)| *chunks <= stored);
+        checkpoint.awaiting = awaiting;
+        if finished.is_empty() {
+            return Ok(());
+        }
+        let unsynced = match self.pending.as_mut() {
+            Some(pending) => pending.chunks[std::mem::replace(&mut pending.synced, pending.chunks.len())..].to_vec(),
+            None => vec![],
+        };
+        self.sync_chunks(&unsynced, output_path)?;
+        let checkpoint = self.checkpoint.as_mut().unwrap();
+        for (path, _) in finished {
+            writeln!(checkpoint.file, "{}", path).with_context(|| format!("Writing checkpoint {}", checkpoint.path))?;
+            checkpoint.unfinished.remove(&path);
+        }
+        checkpoint.listed = true;
+        checkpoint.file.sync_data().with_context(|| format!("Syncing checkpoint {}", checkpoint.path))
+    }
+
+    /// Leaves `paths` out of the manifest, as if they were not part of the run.
+    fn forget_files(&mut self, paths: &HashSet<String>) {
+        for path in paths.iter() {
+            self.bases.remove(path);
+            self.metadata.remove(path);
+            self.xattrs.remove(path);
+            self.file_acls.remove(path);
+            self.phashes.remove(path);
+            self.detected_mime_types.remove(path);
+            if let Some(hash) = self.path_to_hash_map.remove(path) {
+                if let Some(group) = self.hash_to_path_map.get_mut(&hash) {
+                    group.retain(|other| other != path);
+                    if group.is_empty() {
+                        self.hash_to_path_map.remove(&hash);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Checks the `manifest.sig` of `data_path` against its manifest with the shared `secret`,
+    /// see [`ChunkerBuilder::manifest_hmac_secret`]. A missing signature fails the check.
+    pub fn verify_hmac(&self, data_path: &str, secret: &[u8]) -> Result<bool> {
+        let (path, _) = find_manifest(data_path, "restore_info")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No manifest in {}", data_path)))?;
+        let signature = match fs::read_to_string(format!("{}/{}", data_path, MANIFEST_SIG_FILE)) {
+            Ok(signature) => signature,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let signature = signature.trim();
+        let Some(signature) = (0..signature.len())
+            .step_by(2)
+            .map(|i| signature.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect::<Option<Vec<u8>>>()
+        else {
+            return Ok(false);
+        };
+        Ok(manifest_hmac(secret, &fs::read(path)?).verify_slice(&signature).is_ok())
+    }
+
+    /// Follows the `previous_manifest_hash` links from the manifest in `data_path` through the
+    /// archived manifests in `manifest_dir`, usually `<data_path>/manifests`.
+    ///
+    /// Returns `false` if an archived manifest does not match the hash it is referenced by.
+    pub fn verify_chain(&self, data_path: &str, manifest_dir: &str) -> Result<bool> {
+        let mut restore_info = RestoreInformation::load(data_path)?;
+        while let Some(expected) = restore_info.previous_manifest_hash.take() {
+            match load_archived_manifest(manifest_dir, &expected)? {
+                Some(previous) => restore_info = previous,
+                None => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Replays the audit hash chain of the backup in `manifest_dir` from its current manifest
+    /// back to the first one, through the manifest history in `<manifest_dir>/manifests`.
+    ///
+    /// Every manifest's `manifest_hash` must match the BLAKE3 hash of its `previous_manifest_hash`
+    /// and the sorted hashes of the chunks it added, and every archived manifest the hash it is
+    /// referenced by. Returns `false` if an entry was modified, and fails if one was removed.
+    /// Manifests written before the audit chain end the traversal.
+    pub fn audit_chain(&self, manifest_dir: &str) -> Result<bool> {
+        let history_dir = format!("{}/{}", manifest_dir, MANIFEST_HISTORY_DIR);
+        let mut restore_info = RestoreInformation::load(manifest_dir)?;
+        loop {
+            let previous = match restore_info.previous_manifest_hash.as_deref() {
+                Some(expected) => match load_archived_manifest(&history_dir, expected)? {
+                    Some(previous) => Some(previous),
+                    None => return Ok(false),
+                },
+                None => None,
+            };
+            let previous_chunks = previous.as_ref().map(|p| p.chunk_hashes.clone()).unwrap_or_default();
+            match restore_info.manifest_hash.as_deref() {
+                None => return Ok(true),
+                Some(hash) if hash == restore_info.audit_hash(&previous_chunks) => {}
+                Some(hash) => {
+                    tracing::warn!(manifest_hash = %hash, "Audit chain broken");
+                    return Ok(false);
+                }
+            }
+            match previous {
+                Some(previous) => restore_info = previous,
+                None => return Ok(true),
+            }
+        }
+    }
+
+    /// Computes the [`CompressionStats`] of `filename`, taking them from the previous manifest for
+    /// files that were not chunked again.
+    fn compression_stats(&self, filename: &str, file_map: &IndexMap<String, StartEndTuple>) -> Option<CompressionStats> {
+        if self.resumed_files.contains_key(filename) {
+            return self.previous_manifest.as_ref()?.compression_stats.get(filename).copied();
+        }
+        let mut stats = CompressionStats::default();
+        for (chunk_name, start_end) in file_map.iter() {
+            let &(uncompressed, compressed) = self.chunk_sizes.get(chunk_name)?;
+            let len = start_end.end - start_end.start;
+            stats.original_bytes += len;
+            stats.compressed_bytes += (len as u128 * compressed as u128 / uncompressed.max(1) as u128) as u64;
+        }
+        Some(stats)
+    }
+
+    /// Writes the manifest of `data_path` as pretty-printed JSON to `output`.
+    pub fn export_manifest_json(&self, data_path: &str, output: &str) -> Result<()> {
+        let restore_info = self.load_manifest(data_path)?;
+        let file = std::io::BufWriter::new(fs::File::create(output)?);
+        serde_json::to_writer_pretty(file, &restore_info)?;
+        Ok(())
+    }
+
+    /// Packs the backup in the local directory `data_path` into the tar archive `output_tar`: its
+    /// manifest, every chunk and delta file with the manifest's prefix, and the manifest history,
+    /// snapshots and content-addressed index if there are any. Entry names are relative to
+    /// `data_path`, see [`Chunker::import_from_tar`].
+    pub fn export_chunk_files_as_tar(&self, data_path: &str, output_tar: &str) -> Result<()> {
+        let restore_info = RestoreInformation::load(data_path)?;
+        let (manifest_path, _) = find_manifest(data_path, "restore_info")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No manifest in {}", data_path)))?;
+        let mut names = vec![manifest_path[data_path.len() + 1..].to_string()];
+        for entry in fs::read_dir(data_path)? {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let is_extra = [MANIFEST_HISTORY_DIR, SNAPSHOT_DIR, CA_INDEX_FILE, MANIFEST_SIG_FILE].contains(&file_name.as_str());
+            if is_extra || restore_info.is_chunk_file_name(&file_name) {
+                names.push(file_name);
+            }
+        }
+        names.sort_unstable();
+
+        let file = fs::File::create(output_tar).with_context(|| format!("Creating {}", output_tar))?;
+        let mut archive = tar::Builder::new(std::io::BufWriter::new(file));
+        for name in names.iter() {
+            let path = format!("{}/{}", data_path, name);
+            if Path::new(&path).is_dir() {
+                archive.append_dir_all(name, &path)
+            } else {
+                archive.append_path_with_name(&path, name)
+            }
+            .with_context(|| format!("Adding {} to {}", path, output_tar))?;
+        }
+        archive.into_inner()?.flush()?;
+        Ok(())
+    }
+
+    /// Unpacks an archive written by [`Chunker::export_chunk_files_as_tar`] into `output_dir`,
+    /// creating it if needed. Entries reaching outside of `output_dir` are skipped.
+    pub fn import_from_tar(&self, tar_path: &str, output_dir: &str) -> Result<()> {
+        fs::create_dir_all(output_dir).with_context(|| format!("Creating {}", output_dir))?;
+        let file = fs::File::open(tar_path).with_context(|| format!("Opening {}", tar_path))?;
+        tar::Archive::new(io::BufReader::new(file))
+            .unpack(output_dir)
+            .with_context(|| format!("Unpacking {} into {}", tar_path, output_dir))?;
+        Ok(())
+    }
+
+    /// Lists all files in the manifest of `data_path`, sorted by name.
+    pub fn list_files(&self, data_path: &str) -> Result<Vec<(String, FileInfo)>> {
+        Ok(self.load_manifest(data_path)?.file_list())
+    }
+
+    /// Like `list_files`, but decrypts the paths of a manifest written with
+    /// [`ChunkerBuilder::encrypt_filenames`] with `key`.
+    #[cfg(feature = "encryption")]
+    pub fn list_files_decrypted(&self, data_path: &str, key: &[u8; 32]) -> Result<Vec<(String, FileInfo)>> {
+        Ok(self.load_manifest(data_path)?.decrypted(key)?.file_list())
+    }
+
+    /// Summarises the manifest of `data_path`.
+    pub fn stats(&self, data_path: &str) -> Result<ManifestStats> {
+        let restore_info = self.load_manifest(data_path)?;
+        let chunk_counts: Vec<u32> = restore_info
+            .hashes
+            .keys()
+            .filter_map(|filename| restore_info.file_info(filename))
+            .map(|info| info.chunks_count)
+            .collect();
+        let chunks: HashSet<&String> = restore_info.files.values().flat_map(|m| m.keys()).collect();
+        let average_chunks_per_file = if chunk_counts.is_empty() {
+            0.0
+        } else {
+            chunk_counts.iter().map(|&c| c as f64).sum::<f64>() / chunk_counts.len() as f64
+        };
+        Ok(ManifestStats {
+            files: restore_info.hashes.len(),
+            chunks: chunks.len(),
+            average_chunks_per_file,
+        })
+    }
+
+    /// Compares the manifests of the local directories `old_manifest` and `new_manifest`, usually
+    /// two runs of the same backup.
+    pub fn diff(&self, old_manifest: &str, new_manifest: &str) -> Result<ManifestDiff> {
+        let load = |data_path: &str| -> Result<RestoreInformation> {
+            let restore_info = RestoreInformation::load(data_path).with_context(|| format!("Loading manifest of {}", data_path))?;
+            #[cfg(feature = "encryption")]
+            if let Some(key) = &self.options.filename_key {
+                return restore_info.decrypted(key);
+            }
+            Ok(restore_info)
+        };
+        let (old, new) = (load(old_manifest)?, load(new_manifest)?);
+        let sorted = |mut list: Vec<String>| {
+            list.sort_unstable();
+            list
+        };
+        let (old_chunks, new_chunks) = (old.referenced_chunks(), new.referenced_chunks());
+        Ok(ManifestDiff {
+            added_files: sorted(new.hashes.keys().filter(|path| !old.hashes.contains_key(*path)).cloned().collect()),
+            removed_files: sorted(old.hashes.keys().filter(|path| !new.hashes.contains_key(*path)).cloned().collect()),
+            modified_files: sorted(
+                new.hashes
+                    .iter()
+                    .filter(|(path, hash)| old.hashes.get(*path).is_some_and(|old_hash| old_hash != *hash))
+                    .map(|(path, _)| path.clone())
+                    .collect(),
+            ),
+            new_chunks: sorted(new_chunks.difference(&old_chunks).map(|chunk_name| chunk_name.to_string()).collect()),
+            removed_chunks: sorted(old_chunks.difference(&new_chunks).map(|chunk_name| chunk_name.to_string()).collect()),
+        })
+    }
+
+    /// Checks the BLAKE3 hash of a chunk file on disk against the one recorded in the manifest.
+    ///
+    /// Also checks that the fingerprint of the decompressed content still matches the chunk name.
+    pub fn verify_chunk(&self, chunk_name: &str, data_path: &str) -> Result<bool> {
+        let restore_info = self.load_manifest(data_path)?;
+
+        let expected = restore_info
+            .chunk_hashes
+            .get(chunk_name)
+            .ok_or_else(|| ChunkError::UnknownChunk(chunk_name.to_string()))?;
+
+        let storage = self.storage(data_path);
+        let chunk_bytes = storage.read_chunk(&restore_info.stored_chunk_name(chunk_name))?;
+        let actual = content_hash(restore_info.hash_seed, &chunk_bytes);
+        if &actual != expected {
+            return Ok(false);
+        }
+        let content = restore_info.read_chunk(&*storage, chunk_name)?;
+        let fingerprint = match restore_info.fingerprint_prime {
+            None => RabinFingerprint::compute_for_slice(&content),
+            Some(prime) => {
+                let mut fingerprint = new_fingerprint(Some(prime))?;
+                content.iter().for_each(|&byte| fingerprint.push_byte(byte));
+                fingerprint.value()
+            }
+        };
+        Ok(fingerprint.to_string() == chunk_name)
+    }
+
+    /// Writes a new manifest for the local directory `data_path` from the headers of its chunk
+    /// files, for when the manifest was lost. Returns the recovered files, sorted.
+    ///
+    /// Only chunk files with the configured `chunk_name_prefix` are read. Files are recovered if
+    /// the chunk files written for them are all there, so those with chunks stored as a delta or
+    /// shared with a file chunked before them are not, nor are duplicates of other files or
+    /// symlinks. A file whose chunks from several versions are present is skipped as well, as is
+    /// a file missing its trailing chunks, which goes unnoticed. Chunk files hard linked by
+    /// `content_addressable_links` or `hardlink_identical_chunks` carry the header of the file
+    /// they link to, so they are left out along with the files in them.
+    ///
+    /// Headers written with `encrypt_filenames` name their files encrypted, the plain paths are
+    /// only recovered with the `filename_key` they were encrypted with.
+    pub fn rebuild_manifest(&self, data_path: &str) -> Result<Vec<String>> {
+        let prefix = self.options.chunk_name_prefix.as_deref();
+        let linked: HashSet<String> = match fs::read(format!("{}/{}", data_path, CA_INDEX_FILE)) {
+            Ok(bytes) => serde_yaml::from_slice::<HashMap<String, IndexedChunk>>(&bytes)?
+                .into_values()
+                .flat_map(|indexed| indexed.links)
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(ChunkError::from(e).context(format!("Reading {}", CA_INDEX_FILE))),
+        };
+        let mut restore_info = RestoreInformation {
+            format_version: MANIFEST_FORMAT_VERSION.to_string(),
+            chunk_name_prefix: prefix.map(str::to_string),
+            hash_seed: self.options.hash_seed,
+            ..Default::default()
+        };
+        // The ranges of every file, by their offset inside it
+        let mut pieces: HashMap<String, Vec<(u64, String, StartEndTuple)>> = HashMap::new();
+        for entry in fs::read_dir(data_path)? {
+            let file_name = entry?.file_name().to_string_lossy().into_owned();
+            let Some(name) = file_name.strip_suffix(".chunk") else {
+                continue;
+            };
+            if linked.contains(&file_name) {
+                tracing::warn!(path = %file_name, "Chunk file is a link to another one, not recovering its files");
+                continue;
+            }
+            let name = match prefix {
+                Some(prefix) => match name.strip_prefix(prefix).and_then(|name| name.strip_prefix('_')) {
+                    Some(name) => name,
+                    None => continue,
+                },
+                None => name,
+            };
+            let path = format!("{}/{}", data_path, file_name);
+            let header = Chunk::read_header(&path).with_context(|| format!("Reading header of {}", path))?;
+            if header.base.fingerprint.value().to_string() != name {
+                tracing::warn!(path = %path, "Chunk header does not match the file name");
+                continue;
+            }
+            let mut hasher = content_hasher(self.options.hash_seed);
+            io::copy(&mut fs::File::open(&path)?, &mut hasher)?;
+            restore_info.chunk_hashes.insert(name.to_string(), hasher.finalize().to_hex().to_ascii_lowercase());
+            if header.codec != Compression::Snappy {
+                restore_info.chunk_codecs.insert(name.to_string(), header.codec);
+            }
+            if header.base.fingerprint.prime() != RabinFingerprint::new().prime() {
+                restore_info.fingerprint_prime = Some(header.base.fingerprint.prime());
+            }
+            for (file, offset) in header.base.files.iter().zip(header.file_offsets.iter()) {
+                let range = StartEndTuple { start: file.start, end: file.end };
+                #[allow(unused_mut)]
+                let mut filename = file.filename.clone();
+                #[cfg(feature = "encryption")]
+                if let Some(key) = &self.options.filename_key {
+                    // Headers of backups made without `encrypt_filenames` name their files in plain
+                    filename = crate::filename_crypto::decrypt_path(key, &filename).unwrap_or(filename);
+                }
+                pieces.entry(filename).or_default().push((*offset, name.to_string(), range));
+            }
+        }
+
+        for (filename, mut ranges) in pieces.into_iter() {
+            ranges.sort_by_key(|(offset, _, _)| *offset);
+            let mut file_map = IndexMap::new();
+            let mut expected_offset = 0;
+            // Ranges have to follow each other without gaps or overlaps
+            let contiguous = ranges.into_iter().all(|(offset, name, range)| {
+                if offset != expected_offset {
+                    return false;
+                }
+                expected_offset += range.end - range.start;
+                file_map.insert(name, range);
+                true
+            });
+            if !contiguous {
+                tracing::warn!(file = %filename, "Chunks are missing or from several versions, not recovering");
+                continue;
+            }
+            restore_info.file_info.insert(filename.clone(), FileInfo { chunks_count: file_map.len() as u32 });
+            restore_info.files.insert(filename, file_map);
+        }
+        let mut recovered: Vec<String> = restore_info.files.keys().cloned().collect();
+        recovered.sort_unstable();
+        for filename in recovered.iter() {
+            let mut hasher = content_hasher(self.options.hash_seed);
+            self.write_restored(&restore_info, filename, data_path, &mut hasher)?;
+            restore_info.hashes.insert(filename.clone(), hasher.finalize().to_hex().to_ascii_lowercase());
+        }
+
+        #[cfg(feature = "encryption")]
+        if let Some(key) = self.options.filename_key.filter(|_| self.options.encrypt_filenames) {
+            restore_info = restore_info.map_paths(|path| Ok(crate::filename_crypto::encrypt_path(&key, path)))?;
+            restore_info.encrypted_filenames = true;
+        }
+        let yaml = serde_yaml::to_string(&restore_info)?;
+        let manifest = if self.options.compress_manifest {
+            zstd::encode_all(yaml.as_bytes(), MANIFEST_ZSTD_LEVEL)?
+        } else {
+            yaml.into_bytes()
+        };
+        LocalStorage::new(data_path).write_manifest(&manifest)?;
+        Ok(recovered)
+    }
+
+    /// Verifies every chunk the manifest of `data_path` references, returning those that fail.
+    pub fn verify_all(&self, data_path: &str) -> Result<Vec<String>> {
+        let restore_info = self.load_manifest(data_path)?;
+        let mut chunk_names: Vec<&str> = restore_info.referenced_chunks().into_iter().collect();
+        chunk_names.sort_unstable();
+        let mut failed = vec![];
+        for chunk_name in chunk_names {
+            // A missing or unreadable chunk fails verification like a corrupted one
+            if !matches!(self.verify_chunk(chunk_name, data_path), Ok(true)) {
+                failed.push(chunk_name.to_string());
+            }
+        }
+        Ok(failed)
+    }
+
+    /// Checks the copy of every chunk in `data_path` and in every directory of
+    /// [`ChunkerBuilder::mirror_paths`] against its hash, returning the chunk name and directory
+    /// of every copy that is missing or differs, sorted.
+    pub fn verify_mirrors(&self, data_path: &str) -> Result<Vec<(String, String)>> {
+        let restore_info = self.load_manifest(data_path)?;
+        let mut chunk_names: Vec<&str> = restore_info.referenced_chunks().into_iter().collect();
+        chunk_names.sort_unstable();
+        let mut diverged = vec![];
+        for dir in std::iter::once(data_path).chain(self.options.mirror_paths.iter().map(String::as_str)) {
+            let location = LocalStorage::new(dir);
+            for chunk_name in chunk_names.iter() {
+                let expected = restore_info.chunk_hashes.get(*chunk_name);
+                let matches = location
+                    .read_chunk(&restore_info.stored_chunk_name(chunk_name))
+                    .is_ok_and(|bytes| Some(&content_hash(restore_info.hash_seed, &bytes)) == expected);
+                if !matches {
+                    diverged.push((chunk_name.to_string(), dir.to_string()));
+                }
+            }
+        }
+        diverged.sort_unstable();
+        Ok(diverged)
+    }
+
+    /// Deletes the chunk and delta files in `data_path` that no file of its manifest is rebuilt
+    /// from anymore, returning their paths.
+    ///
+    /// Only files named like chunks with the manifest's prefix are touched, so backups with another
+    /// prefix sharing the directory are left alone. Archived manifests don't keep chunks alive,
+    /// snapshots do.
+    ///
+    /// With `older_than`, only snapshots created within that long before now keep their chunks,
+    /// unless the chunks themselves were written within it. Chunks without a recorded creation
+    /// time count as old. The current manifest keeps its chunks either way.
+    pub fn gc(&self, data_path: &str, older_than: Option<Duration>) -> Result<Vec<String>> {
+        let restore_info = RestoreInformation::load(data_path)?;
+        let cutoff = older_than.map(|age| self.now().checked_sub(age).unwrap_or(UNIX_EPOCH));
+        let mut keep: HashSet<String> = HashSet::new();
+        let mut timestamps = restore_info.chunk_timestamps.clone();
+        for snapshot in self.list_snapshots(data_path)? {
+            let created = snapshot.created;
+            let snapshot = self.load_snapshot(data_path, &snapshot.label)?;
+            for (chunk_name, timestamp) in snapshot.chunk_timestamps.iter() {
+                timestamps.entry(chunk_name.clone()).or_insert(*timestamp);
+            }
+            if cutoff.is_some_and(|cutoff| created < cutoff) {
+                continue;
+            }
+            keep.extend(snapshot.referenced_chunks().into_iter().map(|chunk_name| snapshot.stored_chunk_name(chunk_name)));
+        }
+        if let Some(cutoff) = cutoff.map(unix_secs) {
+            keep.extend(
+                timestamps
+                    .iter()
+                    .filter(|(_, timestamp)| **timestamp >= cutoff)
+                    .map(|(chunk_name, _)| restore_info.stored_chunk_name(chunk_name)),
+            );
+        }
+        keep.extend(
+            restore_info
+                .referenced_chunks()
+                .into_iter()
+                .map(|chunk_name| restore_info.stored_chunk_name(chunk_name)),
+        );
+        let mut removed = vec![];
+        for entry in fs::read_dir(data_path)? {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if !entry.file_type()?.is_file() || keep.contains(&file_name) || !restore_info.is_chunk_file_name(&file_name) {
+                continue;
+            }
+            let path = format!("{}/{}", data_path, file_name);
+            fs::remove_file(&path).with_context(|| format!("Removing unreferenced chunk file {}", path))?;
+            removed.push(path);
+        }
+        removed.sort_unstable();
+        Ok(removed)
+    }
+
+    /// Rewrites the backup in `data_path` into `output_path`, usually an empty directory, by
+    /// restoring every file to memory and chunking them all again from scratch with this
+    /// chunker's options. Symlinks and what else is kept of the files besides their content is
+    /// taken over as it is.
+    ///
+    /// Content that earlier runs cut into chunks at different places is stored once again.
+    /// [`ChunkStats::bytes_saved`] tells how much smaller the chunks referenced by the new
+    /// manifest are than those of the old one.
+    ///
+    /// Both paths are local directories. A storage set through [`ChunkerBuilder::storage`] is not
+    /// used, as it would stand for `data_path` and `output_path` alike.
+    pub fn compact(&self, data_path: &str, output_path: &str) -> Result<ChunkStats> {
+        if self.has_custom_storage() {
+            let local = Chunker::with_options(ChunkerOptions {
+                storage: None,
+                ..self.options.clone()
+            });
+            return local.compact(data_path, output_path);
+        }
+        let restore_info = self.load_manifest(data_path)?;
+        self.check_format_version(&restore_info)?;
+        self.check_hash_seed(&restore_info)?;
+        let mut files = HashMap::new();
+        for filename in restore_info.hashes.keys() {
+            let mut bytes = vec![];
+            self.write_restored(&restore_info, filename, data_path, &mut bytes)?;
+            files.insert(filename.clone(), bytes);
+        }
+
+        let mut chunker = Chunker::with_options(self.options.clone());
+        chunker.symlinks = restore_info.symlinks.clone();
+        chunker.xattrs = restore_info.xattrs.clone();
+        chunker.file_acls = restore_info.file_acls.clone();
+        chunker.phashes = restore_info.phashes.clone();
+        chunker.detected_mime_types = restore_info.detected_mime_types.clone();
+        chunker.metadata = restore_info.metadata.clone();
+        let mut stats = chunker.add_file_map(files, output_path)?;
+        let compacted = self.load_manifest(output_path)?;
+        stats.bytes_saved = self
+            .stored_bytes(&restore_info, data_path)?
+            .saturating_sub(self.stored_bytes(&compacted, output_path)?);
+        Ok(stats)
+    }
+
+    /// Returns the size of the chunk and delta files of `data_path` that `restore_info` references.
+    fn stored_bytes(&self, restore_info: &RestoreInformation, data_path: &str) -> Result<u64> {
+        let storage = self.storage(data_path);
+        restore_info
+            .referenced_chunks()
+            .into_iter()
+            .map(|chunk_name| storage.chunk_size(&restore_info.stored_chunk_name(chunk_name)))
+            .sum()
+    }
+
+    /// Combines the backups in the local directories `sources` into one backup in `output`.
+    ///
+    /// Chunk files are copied over once, so chunks shared between the sources are stored once,
+    /// and the merged manifest is written with this chunker's options. A path found in several
+    /// sources keeps the version of the last of them. Every source has to be made with the
+    /// chunker's `hash_seed` and `fingerprint_prime`, as chunk names would not be comparable
+    /// otherwise. A storage set through [`ChunkerBuilder::storage`] is not used.
+    pub fn merge(&self, sources: &[&str], output: &str) -> Result<()> {
+        let mut merged = Chunker::with_options(ChunkerOptions {
+            storage: None,
+            ..self.options.clone()
+        });
+        let mut compression_stats = HashMap::new();
+        for source in sources.iter() {
+            let restore_info = RestoreInformation::load(source).with_context(|| format!("Loading manifest of {}", source))?;
+            #[cfg(feature = "encryption")]
+            let restore_info = match &self.options.filename_key {
+                Some(key) => restore_info.decrypted(key)?,
+                None => restore_info,
+            };
+            if restore_info.hash_seed != self.options.hash_seed {
+                return Err(ChunkError::HashSeedMismatch {
+                    manifest: restore_info.hash_seed,
+                    chunker: self.options.hash_seed,
+                });
+            }
+            if restore_info.fingerprint_prime != self.options.fingerprint_prime {
+                return Err(ChunkError::FingerprintPrimeMismatch {
+                    manifest: restore_info.fingerprint_prime,
+                    chunker: self.options.fingerprint_prime,
+                });
+            }
+
+            let mut chunk_names: Vec<&str> = restore_info.referenced_chunks().into_iter().collect();
+            chunk_names.sort_unstable();
+            for chunk_name in chunk_names {
+                if merged.chunk_hashes.contains_key(chunk_name) || merged.deltas.contains_key(chunk_name) {
+                    continue;
+                }
+                if let Some(hash) = restore_info.chunk_hashes.get(chunk_name) {
+                    merged.chunk_hashes.insert(chunk_name.to_string(), hash.clone());
+                }
+                if let Some(base) = restore_info.deltas.get(chunk_name) {
+                    merged.deltas.insert(chunk_name.to_string(), base.clone());
+                }
+                if let Some(codec) = restore_info.chunk_codecs.get(chunk_name) {
+                    merged.chunk_codecs.insert(chunk_name.to_string(), *codec);
+                }
+                if let Some(timestamp) = restore_info.chunk_timestamps.get(chunk_name) {
+                    merged.chunk_timestamps.insert(chunk_name.to_string(), *timestamp);
+                }
+                let from = format!("{}/{}", source, restore_info.stored_chunk_name(chunk_name));
+                let to = format!("{}/{}", output, merged.stored_chunk_name(chunk_name));
+                // Merging into one of the sources leaves its chunk files where they are
+                if Path::new(&to).exists() {
+                    continue;
+                }
+                crate::reflink::copy_file(&from, &to, self.options.enable_reflink).with_context(|| format!("Copying {} to {}", from, to))?;
+            }
+
+            for (path, hash) in restore_info.hashes.iter() {
+                let Some(file_map) = restore_info.file_map(path) else {
+                    continue;
+                };
+                if let Some(old_hash) = merged.path_to_hash_map.insert(path.clone(), hash.clone()) {
+                    if let Some(group) = merged.hash_to_path_map.get_mut(&old_hash) {
+                        group.retain(|p| p != path);
+                    }
+                }
+                merged.hash_to_path_map.entry(hash.clone()).or_default().push(path.clone());
+                merged.resumed_files.insert(path.clone(), file_map.clone());
+                match restore_info.forward_refs.get(restore_info.primary_path(path).unwrap_or(path)) {
+                    Some(forward_refs) => merged.forward_refs.insert(path.clone(), forward_refs.clone()),
+                    None => merged.forward_refs.remove(path),
+                };
+                match restore_info.compression_stats.get(restore_info.primary_path(path).unwrap_or(path)) {
+                    Some(stats) => compression_stats.insert(path.clone(), *stats),
+                    None => compression_stats.remove(path),
+                };
+            }
+            merged.symlinks.extend(restore_info.symlinks.clone());
+            merged.xattrs.extend(restore_info.xattrs.clone());
+            merged.file_acls.extend(restore_info.file_acls.clone());
+            merged.phashes.extend(restore_info.phashes.clone());
+            merged.detected_mime_types.extend(restore_info.detected_mime_types.clone());
+            merged.metadata.extend(restore_info.metadata.clone());
+        }
+
+        // Only the first path of every content keeps its chunk ranges, as `dump_restore_info` writes them
+        merged.hash_to_path_map.retain(|_, group| !group.is_empty());
+        for group in merged.hash_to_path_map.values_mut() {
+            group.sort_unstable();
+            for path in group[1..].iter() {
+                merged.resumed_files.remove(path);
+                merged.forward_refs.remove(path);
+            }
+        }
+        // Resumed files take their compression stats from the previous manifest
+        merged.previous_manifest = Some(RestoreInformation {
+            compression_stats,
+            ..Default::default()
+        });
+        merged.dump_restore_info(output)
+    }
+
+    /// Copies the current manifest of the local directory `data_path` into its snapshots, so its
+    /// files can still be restored through [`Chunker::restore_file_from_snapshot`] after later runs.
+    ///
+    /// `label` has to be a plain file name, and fails with an I/O error if it is taken already.
+    pub fn snapshot(&self, label: &str, data_path: &str) -> Result<()> {
+        if label.is_empty() || label == "." || label == ".." || label.contains(['/', '\\']) {
+            return Err(ChunkError::InvalidSnapshotLabel(label.to_string()));
+        }
+        let (path, ext) = find_manifest(data_path, "restore_info").ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, format!("No manifest in {}", data_path))
+        })?;
+        let snapshot_dir = format!("{}/{}", data_path, SNAPSHOT_DIR);
+        fs::create_dir_all(&snapshot_dir)?;
+        let snapshot_path = format!("{}/{}.{}", snapshot_dir, label, ext);
+        let mut snapshot = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&snapshot_path)
+            .with_context(|| format!("Creating snapshot {}", snapshot_path))?;
+        snapshot.write_all(&fs::read(path)?)?;
+        tracing::info!(label = %label, "Created snapshot");
+        Ok(())
+    }
+
+    /// Lists the snapshots of `data_path`, oldest first.
+    pub fn list_snapshots(&self, data_path: &str) -> Result<Vec<SnapshotInfo>> {
+        let snapshot_dir = format!("{}/{}", data_path, SNAPSHOT_DIR);
+        let entries = match fs::read_dir(&snapshot_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut snapshots = vec![];
+        for entry in entries {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let Some(label) = MANIFEST_EXTENSIONS.iter().find_map(|ext| file_name.strip_suffix(&format!(".{}", ext))) else {
+                continue;
+            };
+            let metadata = entry.metadata()?;
+            snapshots.push(SnapshotInfo {
+                label: label.to_string(),
+                created: metadata.created().or_else(|_| metadata.modified())?,
+            });
+        }
+        snapshots.sort_by(|a, b| a.created.cmp(&b.created).then_with(|| a.label.cmp(&b.label)));
+        Ok(snapshots)
+    }
+
+    /// Reads the manifest of the snapshot `label` of `data_path`.
+    fn load_snapshot(&self, data_path: &str, label: &str) -> Result<RestoreInformation> {
+        let snapshot_dir = format!("{}/{}", data_path, SNAPSHOT_DIR);
+        let (path, ext) = find_manifest(&snapshot_dir, label).ok_or_else(|| ChunkError::UnknownSnapshot(label.to_string()))?;
+        let restore_info = RestoreInformation::parse(&fs::read(path)?, ext)?;
+        #[cfg(feature = "encryption")]
+        if let Some(key) = &self.options.filename_key {
+            return restore_info.decrypted(key);
+        }
+        Ok(restore_info)
+    }
+
+    /// Inspects the chunk `chunk_name` stored in `data_path`.
+    pub fn chunk_info(&self, chunk_name: &str, data_path: &str) -> Result<ChunkInfo> {
+        let restore_info = self.load_manifest(data_path)?;
+        if !restore_info.chunk_hashes.contains_key(chunk_name) {
+            return Err(ChunkError::UnknownChunk(chunk_name.to_string()));
+        }
+
+        let storage = self.storage(data_path);
+        let on_disk_bytes = storage.chunk_size(&restore_info.stored_chunk_name(chunk_name))?;
+        let uncompressed_bytes = restore_info.read_chunk(&*storage, chunk_name)?.len() as u64;
+        let mut referenced_by: Vec<(String, StartEndTuple)> = restore_info
+            .hashes
+            .keys()
+            .filter_map(|filename| {
+                let start_end = restore_info.file_map(filename)?.get(chunk_name)?;
+                Some((filename.clone(), start_end.clone()))
+            })
+            .collect();
+        referenced_by.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        let codec = if restore_info.is_delta(chunk_name) { "snappy+xor-delta" } else { restore_info.chunk_codec(chunk_name).name() };
+        Ok(ChunkInfo {
+            name: chunk_name.to_string(),
+            on_disk_bytes,
+            uncompressed_bytes,
+            referenced_by,
+            codec: codec.to_string(),
+        })
+    }
+
+    /// Lists the chunks of the manifest of `data_path` whose content is used by more than one file.
+    ///
+    /// A chunk only counts as shared by files whose ranges in it overlap, not by files that
+    /// merely follow each other in it. Whole-file duplicates are left out, they do not reference
+    /// chunks of their own.
+    pub fn dedup_report(&self, data_path: &str) -> Result<DedupReport> {
+        let restore_info = self.load_manifest(data_path)?;
+        let mut ranges: HashMap<&str, Vec<(&str, &StartEndTuple)>> = HashMap::new();
+        for (filename, file_map) in restore_info.files.iter() {
+            for (chunk_name, start_end) in file_map.iter() {
+                ranges.entry(chunk_name).or_default().push((filename, start_end));
+            }
+        }
+
+        let storage = self.storage(data_path);
+        let mut shared_chunks = vec![];
+        for (chunk_name, references) in ranges.into_iter() {
+            let overlaps = |(file, range): &(&str, &StartEndTuple)| {
+                references
+                    .iter()
+                    .any(|(other, other_range)| other != file && range.start < other_range.end && other_range.start < range.end)
+            };
+            let mut referenced_by: Vec<String> =
+                references.iter().filter(|reference| overlaps(reference)).map(|(file, _)| file.to_string()).collect();
+            if referenced_by.len() < 2 {
+                continue;
+            }
+            referenced_by.sort_unstable();
+            let stored_bytes = storage.chunk_size(&restore_info.stored_chunk_name(chunk_name))?;
+            shared_chunks.push(SharedChunk {
+                chunk_name: chunk_name.to_string(),
+                savings_bytes: (referenced_by.len() as u64 - 1) * stored_bytes,
+                referenced_by,
+            });
+        }
+        shared_chunks.sort_unstable_by(|a, b| b.savings_bytes.cmp(&a.savings_bytes).then_with(|| a.chunk_name.cmp(&b.chunk_name)));
+        Ok(DedupReport { shared_chunks })
+    }
+
+    /// Lists the pairs of images in the manifest of `data_path` whose perceptual hashes differ in
+    /// at most `threshold` of their 64 bits, along with that Hamming distance, closest first.
+    ///
+    /// Only images backed up with [`ChunkerBuilder::perceptual_hash_images`] have a hash.
+    pub fn find_similar_images(&self, data_path: &str, threshold: u32) -> Result<Vec<(String, String, u32)>> {
+        let restore_info = self.load_manifest(data_path)?;
+        let mut images: Vec<(&String, u64)> = restore_info.phashes.iter().map(|(path, phash)| (path, *phash)).collect();
+        images.sort_unstable();
+        let mut similar = vec![];
+        for (i, (path, phash)) in images.iter().enumerate() {
+            for (other, other_phash) in images[i + 1..].iter() {
+                let distance = (phash ^ other_phash).count_ones();
+                if distance <= threshold {
+                    similar.push((path.to_string(), other.to_string(), distance));
+                }
+            }
+        }
+        similar.sort_by_key(|(_, _, distance)| *distance);
+        Ok(similar)
+    }
+
+    /// Reads `chunk_name` like [`RestoreInformation::read_chunk`], going through the restore cache.
+    fn read_chunk_cached(
+        &self,
+        restore_info: &RestoreInformation,
+        storage: &dyn Storage,
+        data_path: &str,
+        chunk_name: &str,
+    ) -> Result<Arc<Vec<u8>>> {
+        let key = format!("{}/{}", data_path, chunk_name);
+        if let Some(chunk) = self.restore_cache.lock().unwrap().get(&key) {
+            return Ok(chunk);
+        }
+        let chunk = Arc::new(restore_info.read_chunk(storage, chunk_name)?);
+        let capacity = self.options.restore_cache_bytes.unwrap_or(DEFAULT_RESTORE_CACHE_BYTES);
+        self.restore_cache.lock().unwrap().insert(key, chunk.clone(), capacity);
+        Ok(chunk)
+    }
+
+    /// Returns the path `filename` is restored to, and the compression extension that gets
+    /// stripped from it when `auto_decompress` is enabled.
+    pub(crate) fn restore_target(&self, filename: &str, output_path: &str) -> (String, Option<&'static str>) {
+        // URLs are restored below a directory named after their host
+        let filename = filename.strip_prefix("https://").or_else(|| filename.strip_prefix("http://")).unwrap_or(filename);
+        let filename_without_leading_dot_slash = filename.trim_start_matches("./");
+        let mut path = format!("{}/{}", output_path, filename_without_leading_dot_slash);
+        // Compressed files can be decompressed on the fly, dropping their extension
+        let compression_ext = if self.options.auto_decompress {
+            [".gz", ".bz2"].into_iter().find(|ext| path.ends_with(ext))
+        } else {
+            None
+        };
+        if let Some(ext) = compression_ext {
+            path.truncate(path.len() - ext.len());
+        }
+        (path, compression_ext)
+    }
+
+    pub fn restore_file(&self, filename: &str, data_path: &str, output_path: &str) -> Result<()> {
+        // Normalize filename to unix path
+        let filename = normalize_path(filename);
+        let restore_info = self.load_manifest(data_path).with_context(|| restoring(&filename))?;
+        self.restore_from(&restore_info, &filename, data_path, output_path, false, false)
+    }
+
+    /// Like `restore_file`, but writes the content to `<path>.tmp` next to the restored file and
+    /// renames it into place once complete, so a crash never leaves a partial file behind. The
+    /// temporary file is removed again if restoring fails.
+    pub fn restore_file_atomic(&self, filename: &str, data_path: &str, output_path: &str) -> Result<()> {
+        // Normalize filename to unix path
+        let filename = normalize_path(filename);
+        let restore_info = self.load_manifest(data_path).with_context(|| restoring(&filename))?;
+        self.restore_from(&restore_info, &filename, data_path, output_path, false, true)
+    }
+
+    /// Like `restore_file`, but hashes the content as it is written and fails with
+    /// [`ChunkError::IntegrityMismatch`] if it differs from the hash in the manifest, removing
+    /// the restored file again.
+    pub fn restore_file_verified(&self, filename: &str, data_path: &str, output_path: &str) -> Result<()> {
+        // Normalize filename to unix path
+        let filename = normalize_path(filename);
+        let restore_info = self.load_manifest(data_path).with_context(|| restoring(&filename))?;
+        self.restore_from(&restore_info, &filename, data_path, output_path, true, false)
+    }
+
+    /// Like `restore_file`, but restores `filename` as it was when the snapshot `snapshot` was
+    /// taken, see [`Chunker::snapshot`]. Restores from the current manifest for `None`.
+    pub fn restore_file_from_snapshot(&self, filename: &str, snapshot: Option<&str>, data_path: &str, output_path: &str) -> Result<()> {
+        let Some(label) = snapshot else {
+            return self.restore_file(filename, data_path, output_path);
+        };
+        // Normalize filename to unix path
+        let filename = normalize_path(filename);
+        let restore_info = self.load_snapshot(data_path, label).with_context(|| restoring(&filename))?;
+        self.restore_from(&restore_info, &filename, data_path, output_path, false, false)
+    }
+
+    /// Restores every file and symlink recorded in the manifest of `data_path`.
+    pub fn restore_all_files(&self, data_path: &str, output_path: &str) -> Result<()> {
+        let restore_info = self.load_manifest(data_path)?;
+        let mut filenames: Vec<&String> = restore_info.hashes.keys().chain(restore_info.symlinks.keys()).collect();
+        filenames.sort_unstable();
+        for filename in filenames {
+            self.restore_from(&restore_info, filename, data_path, output_path, false, false)?;
+        }
+        Ok(())
+    }
+
+    /// Restores `filename` below `output_path`, checking its content against the manifest's
+    /// hash if `verify` is set, and through a temporary file if `atomic` is set.
+    fn restore_from(
+        &self,
+        restore_info: &RestoreInformation,
+        filename: &str,
+        data_path: &str,
+        output_path: &str,
+        verify: bool,
+        atomic: bool,
+    ) -> Result<()> {
+        let (path, compression_ext) = self.restore_target(filename, output_path);
+        // Create parent directories
+        if let Some(parent) = std::path::Path::new(&path).parent() {
+            fs::create_dir_all(parent).with_context(|| restoring(filename))?;
+        }
+
+        if let Some(target) = restore_info.symlinks.get(filename) {
+            tracing::info!(file = %filename, target = %target, "Restoring symlink");
+            // Symlinks keep their name even if it looks like a compressed file
+            let path = format!("{}/{}", output_path, filename.trim_start_matches("./"));
+            let _ = fs::remove_file(&path);
+            create_symlink(target, &path).with_context(|| restoring(filename))?;
+            return Ok(());
+        }
+
+        self.check_format_version(restore_info).with_context(|| restoring(filename))?;
+        self.check_hash_seed(restore_info).with_context(|| restoring(filename))?;
+        if self.options.compare_and_skip && compression_ext.is_none() && self.is_restored(restore_info, filename, &path) {
+            tracing::info!("Skipping already-restored file: {}", path);
+        } else if atomic {
+            let temp = RemoveOnDrop::new(format!("{}.tmp", path));
+            self.write_restored_file(restore_info, filename, data_path, &temp.path, compression_ext, verify)?;
+            fs::rename(&temp.path, &path).with_context(|| restoring(filename))?;
+            temp.keep();
+        } else {
+            self.write_restored_file(restore_info, filename, data_path, &path, compression_ext, verify)?;
+        }
+        if let Some(attrs) = restore_info.xattrs.get(filename) {
+            write_xattrs(&path, attrs).with_context(|| format!("Setting extended attributes of {}", path))?;
+        }
+        let metadata = restore_info.metadata.get(filename);
+        // Set after the extended attributes, which need write access, and before the ACL, whose
+        // mask the mode would overwrite
+        if let Some(metadata) = metadata {
+            metadata.apply(&path).with_context(|| format!("Setting metadata of {}", path))?;
+        }
+        #[cfg(all(feature = "posix-acl", target_os = "linux"))]
+        if let Some(acl) = restore_info.file_acls.get(filename) {
+            crate::acl::write_acl(&path, acl).with_context(|| format!("Setting ACL of {}", path))?;
+        }
+        if let Some(metadata) = metadata {
+            let mtime = filetime::FileTime::from_unix_time(metadata.mtime as i64, 0);
+            filetime::set_file_mtime(&path, mtime).with_context(|| format!("Setting modification time of {}", path))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the content of `filename` to `path`, decompressing it for `compression_ext`, and
+    /// checks it against the manifest's hash if `verify` is set.
+    fn write_restored_file(
+        &self,
+        restore_info: &RestoreInformation,
+        filename: &str,
+        data_path: &str,
+        path: &str,
+        compression_ext: Option<&str>,
+        verify: bool,
+    ) -> Result<()> {
+        let expected_hash = match verify {
+            true => Some(
+                restore_info
+                    .hashes
+                    .get(filename)
+                    .ok_or_else(|| ChunkError::UnknownFile(filename.to_string()))
+                    .with_context(|| restoring(filename))?,
+            ),
+            false => None,
+        };
+        let file = std::io::BufWriter::new(fs::File::create(path).with_context(|| restoring(filename))?);
+        let file: Box<dyn Write> = match compression_ext {
+            Some(".gz") => Box::new(flate2::write::GzDecoder::new(file)),
+            Some(".bz2") => Box::new(bzip2::write::BzDecoder::new(file)),
+            _ => Box::new(file),
+        };
+        // The manifest hashes the content as it was backed up, before any decompression
+        let mut file = HashingWriter {
+            inner: file,
+            hasher: expected_hash.map(|_| content_hasher(restore_info.hash_seed)),
+        };
+        self.write_restored(restore_info, filename, data_path, &mut file)?;
+        file.flush().with_context(|| restoring(filename))?;
+        if let (Some(expected), Some(hasher)) = (expected_hash, file.hasher.take()) {
+            let actual = hasher.finalize().to_hex().to_ascii_lowercase();
+            if &actual != expected {
+                drop(file);
+                let _ = fs::remove_file(path);
+                return Err(ChunkError::IntegrityMismatch {
+                    filename: filename.to_string(),
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells whether the file at `path` holds the content of `filename` already, see
+    /// [`ChunkerBuilder::compare_and_skip`].
+    fn is_restored(&self, restore_info: &RestoreInformation, filename: &str, path: &str) -> bool {
+        let (Some(expected), Ok(mut file)) = (restore_info.hashes.get(filename), fs::File::open(path)) else {
+            return false;
+        };
+        let mut hasher = content_hasher(restore_info.hash_seed);
+        io::copy(&mut file, &mut hasher).is_ok() && &hasher.finalize().to_hex().to_ascii_lowercase() == expected
+    }
+
+    /// Makes sure a manifest has the major format version this crate writes, warning about
+    /// another minor version.
+    pub(crate) fn check_format_version(&self, restore_info: &RestoreInformation) -> Result<()> {
+        let major_minor = |version: &str| -> Option<(u64, u64)> {
+            let mut parts = version.split('.');
+            let major = parts.next()?.parse().ok()?;
+            let minor = parts.next()?.parse().ok()?;
+            Some((major, minor))
+        };
+        let (required_major, required_minor) = major_minor(MANIFEST_FORMAT_VERSION).unwrap();
+        match major_minor(&restore_info.format_version) {
+            Some((major, minor)) if major == required_major => {
+                if minor != required_minor {
+                    tracing::warn!(found = %restore_info.format_version, current = MANIFEST_FORMAT_VERSION, "Manifest has another minor format version");
+                }
+                Ok(())
+            }
+            _ => Err(ChunkError::IncompatibleManifestVersion {
+                found: restore_info.format_version.clone(),
+                required: MANIFEST_FORMAT_VERSION.to_string(),
+            }),
+        }
+    }
+
+    /// Makes sure a manifest was written with the hash seed this chunker is configured with, if any.
+    pub(crate) fn check_hash_seed(&self, restore_info: &RestoreInformation) -> Result<()> {
+        match self.options.hash_seed {
+            Some(seed) if restore_info.hash_seed != Some(seed) => Err(ChunkError::HashSeedMismatch {
+                manifest: restore_info.hash_seed,
+                chunker: Some(seed),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Streams the content of `filename` into `writer`, returning the number of bytes written.
+    pub fn restore_file_to_writer<W: Write>(&self, filename: &str, data_path: &str, writer: &mut W) -> Result<u64> {
+        // Normalize filename to unix path
+        let filename = normalize_path(filename);
+        let restore_info = self.load_manifest(data_path).with_context(|| restoring(&filename))?;
+        self.check_format_version(&restore_info).with_context(|| restoring(&filename))?;
+        self.check_hash_seed(&restore_info).with_context(|| restoring(&filename))?;
+        self.write_restored(&restore_info, &filename, data_path, writer)
+    }
+
+    /// Writes bytes `byte_start..byte_end` of `filename` to `writer`, reading only the chunks
+    /// that hold part of them. The range may span chunk boundaries and is cut off at the end of
+    /// the file.
+    pub fn restore_file_range<W: Write>(
+        &self,
+        filename: &str,
+        byte_start: u64,
+        byte_end: u64,
+        data_path: &str,
+        writer: &mut W,
+    ) -> Result<()> {
+        // Normalize filename to unix path
+        let filename = normalize_path(filename);
+        let restore_info = self.load_manifest(data_path).with_context(|| restoring(&filename))?;
+        self.check_format_version(&restore_info).with_context(|| restoring(&filename))?;
+        self.check_hash_seed(&restore_info).with_context(|| restoring(&filename))?;
+        self.write_restored_range(&restore_info, &filename, data_path, byte_start..byte_end, writer)?;
+        Ok(())
+    }
+
+    fn write_restored<W: Write>(&self, restore_info: &RestoreInformation, filename: &str, data_path: &str, writer: &mut W) -> Result<u64> {
+        self.write_restored_range(restore_info, filename, data_path, 0..u64::MAX, writer)
+    }
+
+    /// Writes the part of `filename` inside `range` to `writer`, returning the number of bytes written.
+    fn write_restored_range<W: Write>(
+        &self,
+        restore_info: &RestoreInformation,
+        filename: &str,
+        data_path: &str,
+        range: std::ops::Range<u64>,
+        writer: &mut W,
+    ) -> Result<u64> {
+        tracing::info!(file = %filename, "Restoring");
+
+        let file_ranges = restore_info
+            .file_ranges(filename)
+            .ok_or_else(|| ChunkError::UnknownFile(filename.to_string()))
+            .with_context(|| restoring(filename))?;
+        if range.is_empty() {
+            return Ok(0);
+        }
+
+        let storage = self.storage(data_path);
+        let mut written = 0;
+        // Offset in the file of the current chunk's range
+        let mut offset = 0;
+        for (chunk_name, start_end) in file_ranges.into_iter() {
+            let chunk_offset = offset;
+            offset += start_end.end.saturating_sub(start_end.start);
+            if offset <= range.start {
+                continue;
+            }
+            if chunk_offset >= range.end {
+                break;
+            }
+            // The part of the chunk's range that is inside `range`
+            let start_end = &StartEndTuple {
+                start: start_end.start + range.start.saturating_sub(chunk_offset),
+                end: start_end.end - offset.saturating_sub(range.end),
+            };
+            let context = || restoring_chunk(filename, chunk_name, chunk_offset);
+            if let Some(streamed) = self
+                .stream_chunk_range(restore_info, &*storage, data_path, chunk_name, start_end, writer)
+                .with_context(context)?
+            {
+                written += streamed;
+                continue;
+            }
+            let chunk_bytes = self
+                .read_chunk_cached(restore_info, &*storage, data_path, chunk_name)
+                .with_context(context)?;
+            let chunk_bytes = chunk_range(&chunk_bytes, start_end).with_context(context)?;
+            tracing::debug!(chunk_name = %chunk_name, offset = chunk_offset, "Restoring chunk");
+            writer.write_all(chunk_bytes).with_context(context)?;
+            written += chunk_bytes.len() as u64;
+        }
+        Ok(written)
+    }
+
+    /// Writes the `start_end` range of `chunk_name` to `writer` while decompressing it, if
+    /// decompressing all of it would take more than `max_memory_per_restore`.
+    ///
+    /// Returns `None` if the chunk is to be read whole instead.
+    fn stream_chunk_range<W: Write>(
+        &self,
+        restore_info: &RestoreInformation,
+        storage: &dyn Storage,
+        data_path: &str,
+        chunk_name: &str,
+        start_end: &StartEndTuple,
+        writer: &mut W,
+    ) -> Result<Option<u64>> {
+        let Some(limit) = self.options.max_memory_per_restore else {
+            return Ok(None);
+        };
+        // Deltas are applied to the whole chunk they are based on
+        if restore_info.is_delta(chunk_name) || self.restore_cache.lock().unwrap().contains(&format!("{}/{}", data_path, chunk_name)) {
+            return Ok(None);
+        }
+        let stored_name = restore_info.stored_chunk_name(chunk_name);
+        if storage.chunk_size(&stored_name)? + restore_info.chunk_len(chunk_name) <= limit {
+            return Ok(None);
+        }
+        tracing::debug!(chunk_name = %chunk_name, "Streaming chunk");
+        let stored = storage.read_chunk(&stored_name)?;
+        let mut decoder = restore_info.chunk_codec(chunk_name).decoder(ChunkHeader::split(&stored)?.1)?;
+        io::copy(&mut (&mut decoder).take(start_end.start), &mut io::sink())?;
+        let len = start_end.end.saturating_sub(start_end.start);
+        let written = io::copy(&mut decoder.take(len), writer)?;
+        if written != len {
+            return Err(invalid_chunk(format!("range {}..{} is out of bounds for the chunk", start_end.start, start_end.end)));
+        }
+        Ok(Some(written))
+    }
+}
+
+pub(crate) fn restoring(filename: &str) -> String {
+    format!("while restoring file '{}'", filename)
+}
+
+pub(crate) fn restoring_chunk(filename: &str, chunk_name: &str, offset: u64) -> String {
+    format!("while restoring file '{}', reading chunk '{}', at offset {}", filename, chunk_name, offset)
+}
+
+/// Returns the part of a decompressed chunk covered by `start_end`, failing if the manifest
+/// points past its end.
+pub(crate) fn chunk_range<'a>(chunk_bytes: &'a [u8], start_end: &StartEndTuple) -> std::io::Result<&'a [u8]> {
+    chunk_bytes
+        .get(start_end.start as usize..start_end.end as usize)
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "range {}..{} is out of bounds for a chunk of {} bytes",
+                    start_end.start,
+                    start_end.end,
+                    chunk_bytes.len()
+                ),
+            )
+        })
+}
+
+/// Reorders `paths` so that files with similar content are next to each other.
+///
+/// Unchanged files are skipped during chunking anyway and are not read.
+fn sort_by_similarity<F, B>(paths: Vec<String>, unchanged: &HashSet<String>, read: &mut F) -> Result<Vec<String>>
+where
+    F: FnMut(&str) -> Result<B>,
+    B: Deref<Target = [u8]>,
+{
+    let (skipped, to_chunk): (Vec<String>, Vec<String>) =
+        paths.into_iter().partition(|path| unchanged.contains(path));
+    let signatures: Vec<similarity::Signature> = to_chunk
+        .iter()
+        .map(|path| Ok(similarity::signature(&read(path)?)))
+        .collect::<Result<_>>()?;
+    let order = similarity::order_by_similarity(&signatures);
+    Ok(skipped
+        .into_iter()
+        .chain(order.into_iter().map(|index| to_chunk[index].clone()))
+        .collect())
+}
+
+/// Copies the manifest of the previous run in `output_path` into the manifest history, named by
+/// its BLAKE3 hash, and returns that hash. Returns `None` on the first run.
+fn archive_previous_manifest(output_path: &str) -> Result<Option<String>> {
+    let Some((path, ext)) = find_manifest(output_path, "restore_info") else {
+        return Ok(None);
+    };
+    let bytes = fs::read(&path).with_context(|| format!("Reading {}", path))?;
+    let hash = blake3::hash(&bytes).to_hex().to_ascii_lowercase();
+    let history_dir = format!("{}/{}", output_path, MANIFEST_HISTORY_DIR);
+    let archived = format!("{}/{}.{}", history_dir, hash, ext);
+    fs::create_dir_all(&history_dir)
+        .and_then(|()| fs::write(&archived, bytes))
+        .with_context(|| format!("Archiving {}", path))?;
+    Ok(Some(hash))
+}
+
+/// Returns the HMAC-SHA256 state of `manifest` under `secret`, see [`Chunker::verify_hmac`].
+fn manifest_hmac(secret: &[u8], manifest: &[u8]) -> hmac::Hmac<sha2::Sha256> {
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(manifest);
+    mac
+}
+
+/// Reads the manifest archived in `manifest_dir` under `expected`, its BLAKE3 hash.
+///
+/// Returns `None` if its content no longer matches that hash.
+fn load_archived_manifest(manifest_dir: &str, expected: &str) -> Result<Option<RestoreInformation>> {
+    let (path, ext) = find_manifest(manifest_dir, expected).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Archived manifest not found: {}", expected),
+        )
+    })?;
+    let bytes = fs::read(path)?;
+    if blake3::hash(&bytes).to_hex().to_ascii_lowercase() != expected {
+        tracing::warn!(manifest_hash = %expected, "Manifest chain broken");
+        return Ok(None);
+    }
+    Ok(Some(RestoreInformation::parse(&bytes, ext)?))
+}
+
+/// Replaces the metrics file at `path` with `text` through a rename, so it is never read half-written.
+fn write_metrics(path: &Path, text: &str) -> Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    fs::write(&temp, text)
+        .and_then(|_| fs::rename(&temp, path))
+        .with_context(|| format!("Writing metrics to {}", path.display()))
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, path: &str) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &str, path: &str) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(target, path)
+}
+
+#[cfg(unix)]
+fn read_xattrs(path: &str) -> std::io::Result<HashMap<String, Vec<u8>>> {
+    let mut attrs = HashMap::new();
+    for name in xattr::list(path)? {
+        // Attributes can vanish between listing and reading them
+        if let Some(value) = xattr::get(path, &name)? {
+            attrs.insert(name.to_string_lossy().into_owned(), value);
+        }
+    }
+    Ok(attrs)
+}
+
+#[cfg(not(unix))]
+fn read_xattrs(_path: &str) -> std::io::Result<HashMap<String, Vec<u8>>> {
+    Ok(HashMap::new())
+}
+
+#[cfg(unix)]
+fn write_xattrs(path: &str, attrs: &HashMap<String, Vec<u8>>) -> std::io::Result<()> {
+    for (name, value) in attrs.iter() {
+        xattr::set(path, name, value)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_xattrs(_path: &str, _attrs: &HashMap<String, Vec<u8>>) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::cdc::{CdcAlgorithm, SplitStrategy};
+    use crate::stats::WindowDedupStats;
+    use crate::test_util::xorshift_bytes;
+
+    fn fresh_dir(path: &str) -> String {
+        let _ = fs::remove_dir_all(path);
+        fs::create_dir_all(path).unwrap();
+        path.to_string()
+    }
+
+
+    #[test]
+    fn test_chunking() {
+        // Scan ./tests/data for files
+        let mut paths: Vec<String> = Vec::new();
+
+        for entry in fs::read_dir("./tests/data").unwrap() {
+            let entry = entry.unwrap();
+            let path = entry.path();
+            let path_str = path.to_str().unwrap();
+            paths.push(path_str.to_string());
+        }
+        // Cleanup output folder
+        let _ = fs::remove_dir_all("./tests/chunks");
+        fs::create_dir("./tests/chunks").unwrap();
+
+        let chunker = Chunker::new();
+        chunker.add_files(paths, "./tests/chunks").unwrap();
+
+        // Attempt restore
+        let _ = fs::remove_dir_all("./tests/restored");
+        fs::create_dir("./tests/restored").unwrap();
+
+        let chunker_restore = Chunker::new();
+
+        // Check if restored files is the same as original
+        for entry in fs::read_dir("./tests/data").unwrap() {
+            let path = format!(
+                "./tests/data/{}",
+                entry.unwrap().file_name().to_str().unwrap()
+            );
+            let restored_path = format!("./tests/restored/{}", path);
+
+            chunker_restore.restore_file(&path, "./tests/chunks", "./tests/restored").unwrap();
+
+            let original = fs::read(&path).unwrap();
+            let restored = fs::read(&restored_path).unwrap();
+
+            assert_eq!(original, restored);
+        }
+    }
+
+    #[test]
+    fn test_verify_chunk_detects_tampering() {
+        let output = fresh_dir("./tests/tmp/verify_chunk");
+        let paths = vec![
+            "./tests/data/A.txt".to_string(),
+            "./tests/data/CSmall.txt".to_string(),
+        ];
+        Chunker::new().add_files(paths, &output).unwrap();
+
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        let chunk_name = restore_info.chunk_hashes.keys().next().unwrap().clone();
+        let chunker = Chunker::new();
+        assert!(chunker.verify_chunk(&chunk_name, &output).unwrap());
+
+        // Flip a byte inside the compressed chunk
+        let chunk_path = format!("{}/{}.chunk", output, chunk_name);
+        let mut chunk_bytes = fs::read(&chunk_path).unwrap();
+        let last = chunk_bytes.len() - 1;
+        chunk_bytes[last] ^= 0xff;
+        fs::write(&chunk_path, chunk_bytes).unwrap();
+
+        assert!(!chunker.verify_chunk(&chunk_name, &output).unwrap());
+        assert!(chunker.verify_chunk("0", &output).is_err());
+    }
+
+    #[test]
+    fn test_rebuild_manifest_from_chunk_headers() {
+        let output = fresh_dir("./tests/tmp/rebuild_manifest");
+        let random = format!("{}/random.bin", output);
+        let data = xorshift_bytes(0x0123_4567_89AB_CDEF, 256 * 1024);
+        fs::write(&random, &data).unwrap();
+        let paths = vec![
+            "./tests/data/A.txt".to_string(),
+            "./tests/data/A2.txt".to_string(),
+            "./tests/data/CSmall.txt".to_string(),
+            random.clone(),
+        ];
+        let chunker = || {
+            Chunker::builder()
+                .cdc_algorithm(CdcAlgorithm::FastCdc { min: 4096, avg: 16 * 1024, max: 64 * 1024 })
+                .chunk_name_prefix(Some("backup".to_string()))
+                .build()
+        };
+        chunker().add_files(paths.clone(), &output).unwrap();
+        let original = RestoreInformation::load(&output).unwrap();
+        let header = Chunk::read_header(&format!("{}/backup_{}.chunk", output, original.files[&paths[0]].keys().next().unwrap())).unwrap();
+        assert_eq!(header.base.files[0].filename, paths[0]);
+        fs::remove_file(format!("{}/restore_info.yaml", output)).unwrap();
+
+        let recovered = chunker().rebuild_manifest(&output).unwrap();
+        // A2.txt is a duplicate of A.txt, it never had chunks of its own
+        assert_eq!(recovered, vec![paths[0].clone(), paths[2].clone(), random.clone()]);
+        let rebuilt = RestoreInformation::load(&output).unwrap();
+        assert!(rebuilt.files[&random].len() > 2);
+        assert_eq!(rebuilt.files[&random], original.files[&random]);
+        assert_eq!(rebuilt.chunk_hashes, original.chunk_hashes);
+        for path in recovered.iter() {
+            assert_eq!(rebuilt.hashes[path], original.hashes[path]);
+            let mut restored = vec![];
+            chunker().restore_file_to_writer(path, &output, &mut restored).unwrap();
+            assert_eq!(restored, fs::read(path).unwrap());
+        }
+        assert!(chunker().verify_all(&output).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_dedup_report() {
+        let output = fresh_dir("./tests/tmp/dedup_report");
+        // Both files end in the same content, so the boundaries in it line up again
+        let common = xorshift_bytes(0x1357_9BDF_2468_ACE0, 128 * 1024);
+        let first = format!("{}/first.bin", output);
+        let second = format!("{}/second.bin", output);
+        fs::write(&first, [xorshift_bytes(1, 3000), common.clone()].concat()).unwrap();
+        fs::write(&second, [xorshift_bytes(2, 5000), common].concat()).unwrap();
+
+        Chunker::builder()
+            .cdc_algorithm(CdcAlgorithm::FastCdc { min: 1024, avg: 4096, max: 16 * 1024 })
+            .build()
+            .add_files(vec![first.clone(), second.clone()], &output)
+            .unwrap();
+        let report = Chunker::new().dedup_report(&output).unwrap();
+        assert!(!report.shared_chunks.is_empty());
+        for shared in report.shared_chunks.iter() {
+            assert_eq!(shared.referenced_by, vec![first.clone(), second.clone()]);
+            let info = Chunker::new().chunk_info(&shared.chunk_name, &output).unwrap();
+            assert_eq!(shared.savings_bytes, info.on_disk_bytes);
+        }
+        // Chunks holding the end of one file and the start of the next are not shared
+        let spanning = fresh_dir("./tests/tmp/dedup_report_spanning");
+        Chunker::new()
+            .add_files(vec!["./tests/data/A.txt".to_string(), "./tests/data/CSmall.txt".to_string()], &spanning)
+            .unwrap();
+        assert!(Chunker::new().dedup_report(&spanning).unwrap().shared_chunks.is_empty());
+    }
+
+    #[test]
+    fn test_parallel_boundary_scan() {
+        let data = xorshift_bytes(0x9E37_79B9_7F4A_7C15, 3 * 1024 * 1024);
+        let large = "./tests/tmp/parallel_scan.bin".to_string();
+        fs::write(&large, &data).unwrap();
+        // The first chunk of the large file starts with the end of the small one
+        let paths = vec!["./tests/data/CSmall.txt".to_string(), large.clone()];
+
+        let backup = |output: &str, threshold: u64| {
+            let output = fresh_dir(output);
+            Chunker::builder()
+                .cdc_algorithm(CdcAlgorithm::FastCdc { min: 4096, avg: 16 * 1024, max: 64 * 1024 })
+                .parallel_scan_threshold_bytes(threshold)
+                .build()
+                .add_files(paths.clone(), &output)
+                .unwrap();
+            let mut chunks: Vec<(String, Vec<u8>)> = fs::read_dir(&output)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "chunk"))
+                .map(|path| (path.file_name().unwrap().to_string_lossy().into_owned(), fs::read(&path).unwrap()))
+                .collect();
+            chunks.sort_unstable();
+            (RestoreInformation::load(&output).unwrap().files, chunks)
+        };
+        let (scanned_files, scanned_chunks) = backup("./tests/tmp/parallel_scan/scanned", 0);
+        let (pushed_files, pushed_chunks) = backup("./tests/tmp/parallel_scan/pushed", u64::MAX);
+        assert!(scanned_files[&large].len() > 10);
+        assert_eq!(scanned_files, pushed_files);
+        assert_eq!(scanned_chunks, pushed_chunks);
+
+        let mut restored = vec![];
+        Chunker::new().restore_file_to_writer(&large, "./tests/tmp/parallel_scan/scanned", &mut restored).unwrap();
+        assert_eq!(restored, data);
+    }
+
+    #[test]
+    fn test_gc_removes_unreferenced_chunks() {
+        let output = fresh_dir("./tests/tmp/gc");
+        Chunker::new()
+            .add_files(vec!["./tests/data/CSmall.txt".to_string()], &output)
+            .unwrap();
+        let stray = format!("{}/123.chunk", output);
+        fs::write(&stray, b"stray").unwrap();
+        // Not ours: another prefix, and no chunk name at all
+        fs::write(format!("{}/other_5.chunk", output), b"other").unwrap();
+        fs::write(format!("{}/notes.txt", output), b"notes").unwrap();
+
+        let chunker = Chunker::new();
+        assert_eq!(chunker.gc(&output, None).unwrap(), vec![stray.clone()]);
+        assert!(fs::metadata(&stray).is_err());
+        assert!(fs::metadata(format!("{}/other_5.chunk", output)).is_ok());
+        assert!(fs::metadata(format!("{}/notes.txt", output)).is_ok());
+        assert!(chunker.gc(&output, None).unwrap().is_empty());
+        assert!(chunker.verify_all(&output).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_chunk_info() {
+        let output = fresh_dir("./tests/tmp/chunk_info");
+        let paths = vec![
+            "./tests/data/A.txt".to_string(),
+            "./tests/data/A2.txt".to_string(),
+            "./tests/data/CSmall.txt".to_string(),
+        ];
+        Chunker::new().add_files(paths, &output).unwrap();
+
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        let chunk_name = restore_info.chunk_hashes.keys().next().unwrap().clone();
+        let info = Chunker::new().chunk_info(&chunk_name, &output).unwrap();
+
+        let a_len = fs::metadata("./tests/data/A.txt").unwrap().len();
+        let c_len = fs::metadata("./tests/data/CSmall.txt").unwrap().len();
+        assert_eq!(info.name, chunk_name);
+        assert_eq!(info.codec, "snappy");
+        assert_eq!(info.uncompressed_bytes, a_len + c_len);
+        let chunk_path = format!("{}/{}.chunk", output, chunk_name);
+        assert_eq!(info.on_disk_bytes, fs::metadata(chunk_path).unwrap().len());
+        assert_eq!(
+            info.referenced_by,
+            vec![
+                ("./tests/data/A.txt".to_string(), StartEndTuple { start: 0, end: a_len }),
+                ("./tests/data/A2.txt".to_string(), StartEndTuple { start: 0, end: a_len }),
+                (
+                    "./tests/data/CSmall.txt".to_string(),
+                    StartEndTuple { start: a_len, end: a_len + c_len }
+                ),
+            ]
+        );
+        assert!(matches!(
+            Chunker::new().chunk_info("0", &output),
+            Err(ChunkError::UnknownChunk(_))
+        ));
+    }
+
+    #[test]
+    fn test_restore_error_context() {
+        let output = fresh_dir("./tests/tmp/restore_error_context/chunks");
+        let restored = fresh_dir("./tests/tmp/restore_error_context/restored");
+        let path = "./tests/data/CSmall.txt";
+        Chunker::new().add_files(vec![path.to_string()], &output).unwrap();
+
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        let chunk_name = restore_info.chunk_hashes.keys().next().unwrap().clone();
+        fs::remove_file(restore_info.stored_chunk_path(&output, &chunk_name)).unwrap();
+
+        let err = Chunker::new().restore_file(path, &output, &restored).unwrap_err();
+        let expected = format!(
+            "while restoring file '{}', reading chunk '{}', at offset 0: I/O error: ",
+            path, chunk_name
+        );
+        assert!(err.to_string().starts_with(&expected), "{}", err);
+        match err {
+            ChunkError::Context { source, .. } => assert!(matches!(*source, ChunkError::Io(_))),
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        let err = Chunker::new().restore_file("missing.txt", &output, &restored).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "while restoring file 'missing.txt': File not found in manifest: missing.txt"
+        );
+    }
+
+    #[test]
+    fn test_restore_auto_decompress() {
+        let source = fresh_dir("./tests/tmp/auto_decompress/source");
+        let output = fresh_dir("./tests/tmp/auto_decompress/chunks");
+        let restored = fresh_dir("./tests/tmp/auto_decompress/restored");
+
+        let original = fs::read("./tests/data/A.txt").unwrap();
+        let gz_path = format!("{}/A.txt.gz", source);
+        let mut encoder = flate2::write::GzEncoder::new(
+            fs::File::create(&gz_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(&original).unwrap();
+        encoder.finish().unwrap();
+
+        Chunker::new().add_files(vec![gz_path.clone()], &output).unwrap();
+
+        let chunker = Chunker::builder().auto_decompress(true).build();
+        chunker.restore_file(&gz_path, &output, &restored).unwrap();
+
+        let restored_path = format!("{}/{}", restored, source.trim_start_matches("./"));
+        let decompressed = fs::read(format!("{}/A.txt", restored_path)).unwrap();
+        assert_eq!(original, decompressed);
+        assert!(!std::path::Path::new(&format!("{}/A.txt.gz", restored_path)).exists());
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let source = fresh_dir("./tests/tmp/rate_limit/source");
+        let output = fresh_dir("./tests/tmp/rate_limit/chunks");
+
+        // Incompressible data so the compressed size is close to the input size
+        let data = fs::read("./tests/data/B100MB.bin").unwrap();
+        let path = format!("{}/B2MB.bin", source);
+        fs::write(&path, &data[..2 * 1024 * 1024]).unwrap();
+
+        let limit = 1024 * 1024;
+        let now = std::time::Instant::now();
+        Chunker::builder()
+            .rate_limit_bytes_per_sec(Some(limit))
+            .build()
+            .add_files(vec![path], &output).unwrap();
+        let elapsed = now.elapsed().as_secs_f64();
+
+        let written: u64 = fs::read_dir(&output)
+            .unwrap()
+            .map(|e| e.unwrap().metadata().unwrap().len())
+            .sum();
+        let throughput = written as f64 / elapsed;
+        assert!(throughput <= limit as f64 * 1.1, "throughput {} exceeds limit", throughput);
+    }
+
+    #[test]
+    fn test_rate_limit_skips_reused_chunks() {
+        let source = fresh_dir("./tests/tmp/rate_limit_reused/source");
+        let output = fresh_dir("./tests/tmp/rate_limit_reused/chunks");
+        let data = fs::read("./tests/data/B100MB.bin").unwrap();
+        let chunker = || Chunker::builder().split_strategy(SplitStrategy::MaxFileSize { max: 64 * 1024 });
+        let first = format!("{}/first.bin", source);
+        fs::write(&first, &data[..1024 * 1024]).unwrap();
+        chunker().build().add_files(vec![first], &output).unwrap();
+
+        // All but the last chunk are on disk already and not written again. Charging the limiter
+        // for the 1 MiB of reused chunks would take a minute.
+        let second = format!("{}/second.bin", source);
+        fs::write(&second, &data[..1024 * 1024 + 1]).unwrap();
+        let now = std::time::Instant::now();
+        let stats = chunker()
+            .rate_limit_bytes_per_sec(Some(16 * 1024))
+            .build()
+            .add_files(vec![second], &output)
+            .unwrap();
+        assert_eq!(stats.chunks_reused, 16);
+        assert_eq!(stats.chunks_written, 1);
+        assert!(now.elapsed().as_secs() < 30);
+    }
+
+    #[test]
+    fn test_add_files_resumes_previous_run() {
+        let source = fresh_dir("./tests/tmp/resume/source");
+        let data = fs::read("./tests/data/B100MB.bin").unwrap();
+        let mut paths = vec![];
+        for i in 0..4 {
+            let path = format!("{}/file{}.bin", source, i);
+            fs::write(&path, &data[i * 1024 * 1024..(i + 1) * 1024 * 1024]).unwrap();
+            paths.push(path);
+        }
+
+        // Backup interrupted after three files, then re-run with the full list
+        let output = fresh_dir("./tests/tmp/resume/chunks");
+        Chunker::new().add_files(paths[..3].to_vec(), &output).unwrap();
+        let resumed = Chunker::new().add_files(paths.clone(), &output).unwrap();
+        // Only the fourth file is chunked, the others are taken over from the manifest
+        assert_eq!(resumed.files_processed, 1);
+        assert_eq!(resumed.bytes_read, 1024 * 1024);
+        assert_eq!(resumed.chunks_written, 1);
+
+        // Change one file, which must replace its old manifest entries
+        fs::write(&paths[1], &data[..1024]).unwrap();
+        Chunker::new().add_files(paths.clone(), &output).unwrap();
+
+        let restored = fresh_dir("./tests/tmp/resume/restored");
+        let chunker = Chunker::new();
+        for path in paths.iter() {
+            chunker.restore_file(path, &output, &restored).unwrap();
+            let original = fs::read(path).unwrap();
+            let restored = fs::read(format!("{}/{}", restored, path.trim_start_matches("./"))).unwrap();
+            assert_eq!(original, restored);
+        }
+    }
+
+    #[test]
+    fn test_add_files_checkpointed() {
+        let source = fresh_dir("./tests/tmp/checkpointed/source");
+        let output = fresh_dir("./tests/tmp/checkpointed/chunks");
+        let checkpoint = "./tests/tmp/checkpointed/checkpoint.txt";
+        let _ = fs::remove_file(checkpoint);
+        let paths: Vec<String> = (0..5).map(|i| format!("{}/file{}.txt", source, i)).collect();
+        for (i, path) in paths.iter().enumerate() {
+            // The fourth file is a directory, which can't be read, failing the run after three
+            match i {
+                3 => fs::create_dir(path).unwrap(),
+                _ => fs::write(path, format!("Lorem ipsum {}", i)).unwrap(),
+            }
+        }
+
+        let result = Chunker::new().add_files_checkpointed(paths.clone(), &output, checkpoint);
+        assert!(result.unwrap_err().to_string().contains(&paths[3]));
+        assert_eq!(fs::read_to_string(checkpoint).unwrap().lines().collect::<Vec<_>>(), paths[..3]);
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert_eq!(restore_info.hashes.len(), 3);
+        assert_eq!(restore_info.metadata.len(), 3);
+
+        fs::remove_dir(&paths[3]).unwrap();
+        fs::write(&paths[3], "Lorem ipsum 3").unwrap();
+        let stats = Chunker::new().add_files_checkpointed(paths.clone(), &output, checkpoint).unwrap();
+        assert_eq!(stats.files_processed, 2);
+        assert_eq!(fs::read_to_string(checkpoint).unwrap().lines().collect::<Vec<_>>(), paths);
+        // One manifest per run, the first one archived by the second
+        assert_eq!(fs::read_dir(format!("{}/{}", output, MANIFEST_HISTORY_DIR)).unwrap().count(), 1);
+        for path in paths.iter() {
+            let mut restored = vec![];
+            Chunker::new().restore_file_to_writer(path, &output, &mut restored).unwrap();
+            assert_eq!(restored, fs::read(path).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_compress_manifest() {
+        let output = fresh_dir("./tests/tmp/compress_manifest");
+        let paths = vec![
+            "./tests/data/A.txt".to_string(),
+            "./tests/data/A2.txt".to_string(),
+            "./tests/data/CSmall.txt".to_string(),
+        ];
+        Chunker::builder()
+            .compress_manifest(true)
+            .build()
+            .add_files(paths.clone(), &output).unwrap();
+
+        assert!(!std::path::Path::new(&format!("{}/restore_info.yaml", output)).exists());
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert_eq!(restore_info.hashes.len(), 3);
+
+        let restored = fresh_dir("./tests/tmp/compress_manifest_restored");
+        for path in paths.iter() {
+            Chunker::new().restore_file(path, &output, &restored).unwrap();
+            let original = fs::read(path).unwrap();
+            let restored = fs::read(format!("{}/{}", restored, path.trim_start_matches("./"))).unwrap();
+            assert_eq!(original, restored);
+        }
+
+        // JSON manifests are picked up as well
+        fs::remove_file(format!("{}/restore_info.yaml.zst", output)).unwrap();
+        let json = serde_json::to_vec(&restore_info).unwrap();
+        let compressed = zstd::encode_all(&json[..], MANIFEST_ZSTD_LEVEL).unwrap();
+        fs::write(format!("{}/restore_info.json.zst", output), compressed).unwrap();
+        assert_eq!(RestoreInformation::load(&output).unwrap().hashes, restore_info.hashes);
+    }
+
+    #[test]
+    fn test_chunking_edge_cases() {
+        let source = fresh_dir("./tests/tmp/edge_cases/source");
+        let output = fresh_dir("./tests/tmp/edge_cases/chunks");
+        let restored = fresh_dir("./tests/tmp/edge_cases/restored");
+
+        let near = (CHUNK_MODULUS / 256) as usize;
+        let mut sizes = vec![0, 1, CHUNK_MODULUS as usize];
+        sizes.extend((1..=10).map(|i| near - i));
+        sizes.extend((1..=10).map(|i| near + i));
+        // Plenty of small files, so chunks span file boundaries
+        sizes.extend((0..80).map(|i| i * 97 % 4096));
+
+        let mut paths = vec![];
+        for (i, size) in sizes.iter().enumerate() {
+            let bytes = xorshift_bytes(0x2545_F491_4F6C_DD1D + i as u64, *size);
+            let path = format!("{}/{:03}_{}.bin", source, i, size);
+            fs::write(&path, bytes).unwrap();
+            paths.push(path);
+        }
+
+        Chunker::new().add_files(paths.clone(), &output).unwrap();
+
+        let chunker = Chunker::new();
+        for path in paths.iter() {
+            chunker.restore_file(path, &output, &restored).unwrap();
+            let original = fs::read(path).unwrap();
+            let restored = fs::read(format!("{}/{}", restored, path.trim_start_matches("./"))).unwrap();
+            assert_eq!(blake3::hash(&original), blake3::hash(&restored), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_file_spanning_three_chunks() {
+        let output = fresh_dir("./tests/tmp/three_chunks");
+        // 5 * 256^3 and 10 * 256^3 are multiples of CHUNK_MODULUS, so the fingerprint of a chunk
+        // hits a boundary right after [5, 0, 0, 0] or [10, 0, 0, 0]
+        let first = vec![5u8, 0, 0];
+        let second = vec![0u8, 10, 0, 0, 0, 1, 2, 3];
+        let files = HashMap::from([
+            ("first.bin".to_string(), first.clone()),
+            ("second.bin".to_string(), second.clone()),
+        ]);
+        Chunker::new().add_file_map(files, &output).unwrap();
+
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        let file_map = restore_info.file_map("second.bin").unwrap();
+        let ranges: Vec<(u64, u64)> = file_map.values().map(|r| (r.start, r.end)).collect();
+        assert_eq!(ranges, vec![(3, 4), (0, 4), (0, 3)]);
+
+        let mut reassembled = vec![];
+        for (chunk_name, start_end) in file_map.iter() {
+            let chunk_bytes = restore_info.read_chunk(&LocalStorage::new(&output), chunk_name).unwrap();
+            reassembled.extend_from_slice(chunk_range(&chunk_bytes, start_end).unwrap());
+        }
+        assert_eq!(reassembled, second);
+
+        let first_map = restore_info.file_map("first.bin").unwrap();
+        assert_eq!(first_map.values().map(|r| (r.start, r.end)).collect::<Vec<_>>(), vec![(0, 3)]);
+    }
+
+    #[test]
+    fn test_delta_chunks() {
+        let source = fresh_dir("./tests/tmp/delta/source");
+        let output = fresh_dir("./tests/tmp/delta/chunks");
+        let restored = fresh_dir("./tests/tmp/delta/restored");
+        let path = format!("{}/big.bin", source);
+
+        // Incompressible data, so a full chunk costs about as much as the data itself
+        let mut original = xorshift_bytes(0x9E37_79B9_7F4A_7C15, 10 * 1000 * 1000);
+        fs::write(&path, &original).unwrap();
+        let chunker = || Chunker::builder().delta_chunks(true).build();
+        chunker().add_files(vec![path.clone()], &output).unwrap();
+
+        for byte in original[5_000_000..5_001_024].iter_mut() {
+            *byte = !*byte;
+        }
+        fs::write(&path, &original).unwrap();
+        chunker().add_files(vec![path.clone()], &output).unwrap();
+
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        let (chunk_name, base) = restore_info.deltas.iter().next().unwrap();
+        let delta_len = fs::metadata(restore_info.stored_chunk_path(&output, chunk_name)).unwrap().len();
+        let full_len = Compression::Snappy.compress(&restore_info.read_chunk(&LocalStorage::new(&output), chunk_name).unwrap()).len() as u64;
+        assert!(delta_len < full_len, "delta: {}, full chunk: {}", delta_len, full_len);
+        assert!(fs::metadata(chunk_file_path(&output, None, base)).is_ok());
+        assert!(fs::metadata(chunk_file_path(&output, None, chunk_name)).is_err());
+        assert!(chunker().verify_chunk(chunk_name, &output).unwrap());
+
+        chunker().restore_file(&path, &output, &restored).unwrap();
+        let restored = fs::read(format!("{}/{}", restored, path.trim_start_matches("./"))).unwrap();
+        assert!(restored == original);
+    }
+
+    #[test]
+    fn test_manifest_chain() {
+        let source = fresh_dir("./tests/tmp/manifest_chain/source");
+        let output = fresh_dir("./tests/tmp/manifest_chain/chunks");
+        let history = format!("{}/manifests", output);
+        let path = format!("{}/file.txt", source);
+
+        for content in ["first", "second", "third"] {
+            fs::write(&path, content).unwrap();
+            Chunker::new().add_files(vec![path.clone()], &output).unwrap();
+        }
+        let chunker = Chunker::new();
+        assert!(chunker.verify_chain(&output, &history).unwrap());
+        assert_eq!(fs::read_dir(&history).unwrap().count(), 2);
+
+        // Rewrite one of the archived manifests
+        let head = fs::read_to_string(format!("{}/restore_info.yaml", output)).unwrap();
+        let head: RestoreInformation = serde_yaml::from_str(&head).unwrap();
+        let archived = format!("{}/{}.yaml", history, head.previous_manifest_hash.unwrap());
+        let tampered = fs::read_to_string(&archived).unwrap().replace("./tests/tmp", "./forged");
+        fs::write(&archived, tampered).unwrap();
+        assert!(!chunker.verify_chain(&output, &history).unwrap());
+    }
+
+    #[test]
+    fn test_audit_chain() {
+        let source = fresh_dir("./tests/tmp/audit_chain/source");
+        let output = fresh_dir("./tests/tmp/audit_chain/chunks");
+        let path = format!("{}/file.txt", source);
+        for content in ["first", "second", "third"] {
+            fs::write(&path, content).unwrap();
+            Chunker::new().add_files(vec![path.clone()], &output).unwrap();
+        }
+        let chunker = Chunker::new();
+        assert!(chunker.audit_chain(&output).unwrap());
+
+        // The current manifest claims a chunk it did not add
+        let manifest = format!("{}/restore_info.yaml", output);
+        let genuine = fs::read_to_string(&manifest).unwrap();
+        let mut head: RestoreInformation = serde_yaml::from_str(&genuine).unwrap();
+        head.chunk_hashes.insert("42".to_string(), "0".repeat(64));
+        fs::write(&manifest, serde_yaml::to_string(&head).unwrap()).unwrap();
+        assert!(!chunker.audit_chain(&output).unwrap());
+        fs::write(&manifest, &genuine).unwrap();
+
+        // An archived manifest went missing
+        let head: RestoreInformation = serde_yaml::from_str(&genuine).unwrap();
+        let archived = format!("{}/manifests/{}.yaml", output, head.previous_manifest_hash.unwrap());
+        fs::remove_file(&archived).unwrap();
+        assert!(chunker.audit_chain(&output).is_err());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypt_filenames() {
+        let output = fresh_dir("./tests/tmp/encrypt_filenames/chunks");
+        let restored = fresh_dir("./tests/tmp/encrypt_filenames/restored");
+        let key = [42; 32];
+        let paths = vec!["./tests/data/A.txt".to_string(), "./tests/data/CSmall.txt".to_string()];
+        assert!(matches!(
+            Chunker::builder().encrypt_filenames(true).build().add_files(paths.clone(), &output),
+            Err(ChunkError::MissingFilenameKey)
+        ));
+        Chunker::builder()
+            .encrypt_filenames(true)
+            .filename_key(key)
+            .build()
+            .add_files(paths.clone(), &output)
+            .unwrap();
+
+        let manifest = fs::read_to_string(format!("{}/restore_info.yaml", output)).unwrap();
+        assert!(!manifest.contains("tests") && !manifest.contains("A.txt"));
+        let encrypted: Vec<String> = Chunker::new().list_files(&output).unwrap().into_iter().map(|(name, _)| name).collect();
+        assert!(encrypted.iter().all(|name| !paths.contains(name)));
+        let decrypted: Vec<String> = Chunker::new()
+            .list_files_decrypted(&output, &key)
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(decrypted, paths);
+        assert!(Chunker::new().list_files_decrypted(&output, &[0; 32]).is_err());
+
+        // With the key, files are restored under their plain paths
+        Chunker::builder().filename_key(key).build().restore_all_files(&output, &restored).unwrap();
+        for path in paths.iter() {
+            let restored = fs::read(format!("{}/{}", restored, path.trim_start_matches("./"))).unwrap();
+            assert_eq!(restored, fs::read(path).unwrap());
+        }
+
+        // Chunk headers name the files of every chunk as well
+        for entry in fs::read_dir(&output).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "chunk") {
+                let stored = fs::read(&path).unwrap();
+                assert!(!stored.windows(b"A.txt".len()).any(|window| window == b"A.txt"), "{:?}", path);
+                assert!(!stored.windows(b"tests".len()).any(|window| window == b"tests"), "{:?}", path);
+            }
+        }
+        fs::remove_file(format!("{}/restore_info.yaml", output)).unwrap();
+        let chunker = || Chunker::builder().encrypt_filenames(true).filename_key(key).build();
+        assert_eq!(chunker().rebuild_manifest(&output).unwrap(), paths);
+        let manifest = fs::read_to_string(format!("{}/restore_info.yaml", output)).unwrap();
+        assert!(!manifest.contains("tests") && !manifest.contains("A.txt"));
+        let mut restored = vec![];
+        chunker().restore_file_to_writer(&paths[1], &output, &mut restored).unwrap();
+        assert_eq!(restored, fs::read(&paths[1]).unwrap());
+    }
+
+    #[test]
+    fn test_simulate_write_failures() {
+        let output = fresh_dir("./tests/tmp/write_failures/chunks");
+        let restored = fresh_dir("./tests/tmp/write_failures/restored");
+        let paths = vec!["./tests/data/A.txt".to_string(), "./tests/data/CSmall.txt".to_string()];
+
+        let result = Chunker::builder()
+            .simulate_write_failures(Some(1.0))
+            .build()
+            .add_files(paths.clone(), &output);
+        match result {
+            Err(ChunkError::Io(e)) => assert_eq!(e.to_string(), "Simulated write failure"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        // Nothing is recorded for a failed run
+        assert!(RestoreInformation::load(&output).is_err());
+
+        Chunker::builder()
+            .simulate_write_failures(Some(0.0))
+            .build()
+            .add_files(paths.clone(), &output)
+            .unwrap();
+        for path in paths.iter() {
+            Chunker::new().restore_file(path, &output, &restored).unwrap();
+            assert_eq!(
+                fs::read(path).unwrap(),
+                fs::read(format!("{}/{}", restored, path.trim_start_matches("./"))).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_prime() {
+        let output = fresh_dir("./tests/tmp/fingerprint_prime/chunks");
+        let restored = fresh_dir("./tests/tmp/fingerprint_prime/restored");
+        let paths = vec!["./tests/data/A.txt".to_string(), "./tests/data/CSmall.txt".to_string()];
+        let chunker = || Chunker::builder().fingerprint_prime(1_000_000_007).build();
+        chunker().add_files(paths.clone(), &output).unwrap();
+
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert_eq!(restore_info.fingerprint_prime, Some(1_000_000_007));
+        for chunk_name in restore_info.chunk_hashes.keys() {
+            assert!(chunk_name.parse::<u64>().unwrap() < 1_000_000_007);
+            assert!(Chunker::new().verify_chunk(chunk_name, &output).unwrap());
+        }
+        for path in paths.iter() {
+            chunker().restore_file(path, &output, &restored).unwrap();
+            assert_eq!(
+                fs::read(path).unwrap(),
+                fs::read(format!("{}/{}", restored, path.trim_start_matches("./"))).unwrap()
+            );
+        }
+
+        let result = Chunker::builder().fingerprint_prime(1_000_000_008).build().add_files(paths, &output);
+        assert!(matches!(result, Err(ChunkError::InvalidPrime(1_000_000_008))));
+    }
+
+    #[test]
+    fn test_pre_sort_by_similarity() {
+        let output = fresh_dir("./tests/tmp/pre_sort");
+        let text: Vec<u8> = (0..2000).flat_map(|i| format!("line {} of the log\n", i).into_bytes()).collect();
+        let mut edited = text.clone();
+        edited[1000..1010].copy_from_slice(b"----------");
+        let files = HashMap::from([
+            ("a.log".to_string(), text),
+            ("b.bin".to_string(), (0..40_000u32).map(|i| (i * 7919 % 251) as u8).collect()),
+            ("c.log".to_string(), edited),
+        ]);
+        Chunker::builder()
+            .pre_sort_by_similarity(true)
+            .build()
+            .add_file_map(files.clone(), &output)
+            .unwrap();
+
+        // The two logs end up next to each other in the single chunk
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        let (_, a) = restore_info.file_map("a.log").unwrap().first().unwrap();
+        let (_, c) = restore_info.file_map("c.log").unwrap().first().unwrap();
+        assert_eq!(a.end, c.start);
+        for (name, bytes) in files.iter() {
+            let mut restored = vec![];
+            Chunker::new().restore_file_to_writer(name, &output, &mut restored).unwrap();
+            assert_eq!(bytes, &restored);
+        }
+    }
+
+    #[test]
+    fn test_hash_seed() {
+        let output = fresh_dir("./tests/tmp/hash_seed/chunks");
+        let restored = fresh_dir("./tests/tmp/hash_seed/restored");
+        let other = fresh_dir("./tests/tmp/hash_seed/other");
+        let path = "./tests/data/CSmall.txt".to_string();
+        let seeded = || Chunker::builder().hash_seed(Some(42)).build();
+        seeded().add_files(vec![path.clone()], &output).unwrap();
+        Chunker::new().add_files(vec![path.clone()], &other).unwrap();
+
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        let unseeded = RestoreInformation::load(&other).unwrap();
+        assert_eq!(restore_info.hash_seed, Some(42));
+        assert_eq!(restore_info.hashes[&path], content_hash(Some(42), &fs::read(&path).unwrap()));
+        assert_ne!(restore_info.hashes[&path], unseeded.hashes[&path]);
+        // Same seed, same hashes
+        let again = fresh_dir("./tests/tmp/hash_seed/again");
+        seeded().add_files(vec![path.clone()], &again).unwrap();
+        assert_eq!(RestoreInformation::load(&again).unwrap().hashes, restore_info.hashes);
+
+        let chunk_name = restore_info.chunk_hashes.keys().next().unwrap();
+        assert!(Chunker::new().verify_chunk(chunk_name, &output).unwrap());
+        seeded().restore_file(&path, &output, &restored).unwrap();
+        Chunker::new().restore_file(&path, &output, &restored).unwrap();
+
+        let wrong_seed = Chunker::builder().hash_seed(Some(7)).build();
+        let err = wrong_seed.restore_file(&path, &output, &restored).unwrap_err();
+        match err {
+            ChunkError::Context { source, .. } => assert!(matches!(
+                *source,
+                ChunkError::HashSeedMismatch { manifest: Some(42), chunker: Some(7) }
+            )),
+            other => panic!("unexpected error: {:?}", other),
+        }
+        let result = wrong_seed.add_files(vec![path], &output);
+        assert!(matches!(result, Err(ChunkError::HashSeedMismatch { .. })));
+    }
+
+    #[test]
+    fn test_compression_stats() {
+        let source = fresh_dir("./tests/tmp/compression_stats/source");
+        let output = fresh_dir("./tests/tmp/compression_stats/chunks");
+        let text: Vec<u8> = (0..5000).flat_map(|i| format!("line {} of the log\n", i % 50).into_bytes()).collect();
+        let log = format!("{}/app.log", source);
+        fs::write(&log, &text).unwrap();
+        let paths = vec![log.clone(), "./tests/data/CSmall.txt".to_string()];
+        Chunker::new().add_files(paths.clone(), &output).unwrap();
+
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert_eq!(restore_info.compression_stats.len(), 2);
+        let stats = restore_info.compression_stats[&log];
+        assert_eq!(stats.original_bytes, text.len() as u64);
+        assert!(stats.compressed_bytes <= stats.original_bytes);
+        assert!(stats.compressed_bytes > 0);
+
+        // Unchanged files keep their stats on the next run
+        Chunker::new().add_files(paths, &output).unwrap();
+        let resumed = RestoreInformation::load(&output).unwrap();
+        assert_eq!(resumed.compression_stats, restore_info.compression_stats);
+    }
+
+    #[test]
+    fn test_logs_saved_chunks() {
+        use std::sync::{Arc, Mutex};
+        use tracing_subscriber::layer::{Context, SubscriberExt};
+
+        /// Level and field names of a captured event.
+        type Event = (tracing::Level, Vec<String>);
+        /// Collects every event.
+        struct Capture(Arc<Mutex<Vec<Event>>>);
+
+        impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Capture {
+            fn on_event(&self, event: &tracing::Event<'_>, _: Context<'_, S>) {
+                let fields = event.metadata().fields().iter().map(|f| f.name().to_string()).collect();
+                self.0.lock().unwrap().push((*event.metadata().level(), fields));
+            }
+        }
+
+        let output = fresh_dir("./tests/tmp/logs_saved_chunks");
+        let events = Arc::new(Mutex::new(vec![]));
+        let subscriber = tracing_subscriber::registry().with(Capture(events.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            Chunker::new()
+                .add_files(vec!["./tests/data/CSmall.txt".to_string()], &output)
+                .unwrap();
+        });
+
+        let events = events.lock().unwrap();
+        assert!(events
+            .iter()
+            .any(|(level, fields)| *level == tracing::Level::INFO && fields.iter().any(|f| f == "chunk_name")));
+    }
+
+    #[cfg(feature = "mime-detection")]
+    #[test]
+    fn test_detect_mime_types() {
+        let output = fresh_dir("./tests/tmp/detect_mime_types");
+        // A ZIP archive whose extension says otherwise
+        let mut archive = b"PK\x03\x04".to_vec();
+        archive.extend(b"Not really an archive. ".repeat(2000));
+        let files = HashMap::from([("./docs/archive.txt".to_string(), archive.clone())]);
+        Chunker::builder()
+            .detect_mime_types(true)
+            .per_file_compression(vec![("*.txt".to_string(), Compression::Zstd)])
+            .build()
+            .add_file_map(files.clone(), &output)
+            .unwrap();
+
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert_eq!(restore_info.detected_mime_types["./docs/archive.txt"], "application/zip");
+        let chunk_name = restore_info.chunk_hashes.keys().next().unwrap().clone();
+        assert_eq!(restore_info.chunk_codec(&chunk_name), Compression::None);
+        let mut restored = vec![];
+        Chunker::new().restore_file_to_writer("./docs/archive.txt", &output, &mut restored).unwrap();
+        assert_eq!(restored, archive);
+
+        // The type of a file that no longer has magic bytes is forgotten
+        let files = HashMap::from([("./docs/archive.txt".to_string(), b"Plain text now".to_vec())]);
+        Chunker::builder().detect_mime_types(true).build().add_file_map(files, &output).unwrap();
+        assert!(RestoreInformation::load(&output).unwrap().detected_mime_types.is_empty());
+    }
+
+    #[test]
+    fn test_streaming_compression() {
+        let output = fresh_dir("./tests/tmp/streaming_compression");
+        let files = HashMap::from([
+            ("./a.txt".to_string(), b"Lorem ipsum dolor sit amet. ".repeat(2000)),
+            ("./b.txt".to_string(), b"Consectetur adipiscing elit. ".repeat(1000)),
+        ]);
+        let chunker = || {
+            Chunker::builder()
+                .streaming_compression(true)
+                .per_file_compression(vec![("*".to_string(), Compression::Zstd)])
+                .build()
+        };
+        chunker().add_file_map(files.clone(), &output).unwrap();
+        assert!(fs::read_dir(&output).unwrap().all(|entry| !entry.unwrap().file_name().to_string_lossy().ends_with(".partial")));
+
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        let reader = Chunker::new();
+        for chunk_name in restore_info.chunk_hashes.keys() {
+            assert_eq!(reader.chunk_info(chunk_name, &output).unwrap().codec, "zstd");
+            assert!(reader.verify_chunk(chunk_name, &output).unwrap());
+        }
+        for (path, bytes) in files.iter() {
+            let mut restored = vec![];
+            reader.restore_file_to_writer(path, &output, &mut restored).unwrap();
+            assert_eq!(&restored, bytes);
+        }
+
+        // With the manifest, the chunk files on disk are reused with their known hash
+        let stats = chunker().add_file_map(files.clone(), &output).unwrap();
+        assert_eq!(stats.chunks_written, 0);
+        assert_eq!(RestoreInformation::load(&output).unwrap().chunk_hashes, restore_info.chunk_hashes);
+
+        // Without the manifest, they are reused with the same hash
+        fs::remove_file(format!("{}/restore_info.yaml", output)).unwrap();
+        let stats = chunker().add_file_map(files, &output).unwrap();
+        assert_eq!(stats.chunks_written, 0);
+        assert_eq!(RestoreInformation::load(&output).unwrap().chunk_hashes, restore_info.chunk_hashes);
+    }
+
+    #[test]
+    fn test_per_file_compression() {
+        let output = fresh_dir("./tests/tmp/per_file_compression");
+        let text: Vec<u8> = b"Lorem ipsum dolor sit amet. ".repeat(2000);
+        let blob: Vec<u8> = (1..=1000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let files = HashMap::from([
+            ("./docs/notes.txt".to_string(), text.clone()),
+            ("./blobs/data.bin".to_string(), blob.clone()),
+        ]);
+        let patterns = vec![
+            ("*.bin".to_string(), Compression::None),
+            ("docs/**".to_string(), Compression::Zstd),
+        ];
+        Chunker::builder()
+            .per_file_compression(patterns)
+            .build()
+            .add_file_map(files.clone(), &output)
+            .unwrap();
+
+        // Both files share a chunk, which follows the larger text file
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        let chunk_name = restore_info.chunk_hashes.keys().next().unwrap().clone();
+        assert_eq!(restore_info.chunk_codec(&chunk_name), Compression::Zstd);
+        let chunker = Chunker::new();
+        assert_eq!(chunker.chunk_info(&chunk_name, &output).unwrap().codec, "zstd");
+        assert!(chunker.verify_chunk(&chunk_name, &output).unwrap());
+        for (path, bytes) in files.iter() {
+            let mut restored = vec![];
+            chunker.restore_file_to_writer(path, &output, &mut restored).unwrap();
+            assert_eq!(&restored, bytes);
+        }
+
+        // Later runs keep the codec the chunk is stored with
+        Chunker::new().add_file_map(files, &output).unwrap();
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert_eq!(restore_info.chunk_codec(&chunk_name), Compression::Zstd);
+    }
+
+    #[test]
+    fn test_fast_cdc_chunking() {
+        let output = fresh_dir("./tests/tmp/fast_cdc_chunking");
+        let path = format!("{}/random.bin", output);
+        let data = xorshift_bytes(0x9E37_79B9_7F4A_7C15, 1024 * 1024);
+        fs::write(&path, &data).unwrap();
+
+        let algorithm = CdcAlgorithm::FastCdc { min: 16 * 1024, avg: 64 * 1024, max: 128 * 1024 };
+        Chunker::builder()
+            .cdc_algorithm(algorithm)
+            .build()
+            .add_files(vec![path.clone()], &output)
+            .unwrap();
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        let ranges = &restore_info.files[&path];
+        assert!(ranges.len() > 4);
+        assert!(ranges.values().all(|range| range.end - range.start <= 128 * 1024));
+        for name in ranges.keys() {
+            assert!(Chunker::new().verify_chunk(name, &output).unwrap());
+        }
+        let mut restored = vec![];
+        Chunker::new().restore_file_to_writer(&path, &output, &mut restored).unwrap();
+        assert_eq!(restored, data);
+
+        let invalid = CdcAlgorithm::FastCdc { min: 8, avg: 4, max: 16 };
+        let result = Chunker::builder().cdc_algorithm(invalid).build().add_files(vec![path], &output);
+        assert!(matches!(result, Err(ChunkError::InvalidChunkSizes { .. })));
+    }
+
+    #[test]
+    fn test_window_dedup_stats() {
+        let pattern: Vec<u8> = (0..64u8).map(|i| i.wrapping_mul(37) ^ 0x5a).collect();
+        let mut bytes = b"Header before the pattern".to_vec();
+        for _ in 0..10 {
+            bytes.extend_from_slice(&pattern);
+        }
+        bytes.extend_from_slice(b"and a trailer");
+        let files = HashMap::from([("./patterns.bin".to_string(), bytes.clone())]);
+
+        let plain = fresh_dir("./tests/tmp/window_dedup_stats/plain");
+        let stats = Chunker::new().add_file_map(files.clone(), &plain).unwrap();
+        assert_eq!(stats.window_dedup, WindowDedupStats::default());
+
+        let counted = fresh_dir("./tests/tmp/window_dedup_stats/counted");
+        let stats = Chunker::builder().window_dedup_stats(true).build().add_file_map(files, &counted).unwrap();
+        // Every window from the second copy of the pattern on repeats one of the first copy
+        let windows = bytes.len() as u64 - 63;
+        assert_eq!(stats.window_dedup.windows, windows);
+        assert_eq!(stats.window_dedup.repeated_windows, 64 * 9 - 63);
+        // Chunks are cut the same
+        assert_eq!(RestoreInformation::load(&counted).unwrap().files, RestoreInformation::load(&plain).unwrap().files);
+        let mut restored = vec![];
+        Chunker::new().restore_file_to_writer("./patterns.bin", &counted, &mut restored).unwrap();
+        assert_eq!(restored, bytes);
+    }
+
+    #[test]
+    fn test_split_strategy() {
+        let root = "./tests/tmp/split_strategy";
+        let data = xorshift_bytes(0x9E37_79B9_7F4A_7C15, 1024 * 1024);
+        let files = HashMap::from([("./random.bin".to_string(), data.clone())]);
+        let chunk_lens = |strategy: SplitStrategy| {
+            let output = fresh_dir(&format!("{}/{:?}", root, strategy));
+            Chunker::builder().split_strategy(strategy).build().add_file_map(files.clone(), &output).unwrap();
+            let mut restored = vec![];
+            Chunker::new().restore_file_to_writer("./random.bin", &output, &mut restored).unwrap();
+            assert_eq!(restored, data);
+            let restore_info = RestoreInformation::load(&output).unwrap();
+            restore_info.files["./random.bin"].values().map(|range| range.end - range.start).collect::<Vec<u64>>()
+        };
+
+        // No 10 MiB boundary in 1 MiB of data
+        assert_eq!(chunk_lens(SplitStrategy::BoundaryOnly), vec![1024 * 1024]);
+        assert_eq!(chunk_lens(SplitStrategy::MaxFileSize { max: 64 * 1024 }), vec![64 * 1024; 16]);
+        let hybrid = chunk_lens(SplitStrategy::Hybrid { min: 16 * 1024, max: 100 * 1024 });
+        let (last, full) = hybrid.split_last().unwrap();
+        assert!(full.iter().all(|len| (16 * 1024..=100 * 1024).contains(len)), "{:?}", hybrid);
+        assert!(*last <= 100 * 1024);
+
+        let invalid = SplitStrategy::Hybrid { min: 8, max: 4 };
+        let result = Chunker::builder().split_strategy(invalid).build().add_file_map(files, &fresh_dir(root));
+        assert!(matches!(result, Err(ChunkError::InvalidSplitSizes { min: 8, max: 4 })));
+    }
+
+    #[test]
+    fn test_min_chunk_bytes_falls_back_to_fixed_size() {
+        let root = fresh_dir("./tests/tmp/min_chunk_bytes");
+        // Every four bytes make a multiple of the Rabin modulus, so each ends a chunk
+        let data: Vec<u8> = (2..=409u32)
+            .filter(|&k| k != 256)
+            .flat_map(|k| (k * CHUNK_MODULUS as u32).to_be_bytes())
+            .collect();
+        let files = HashMap::from([("./pathological.bin".to_string(), data.clone())]);
+        let rabin = Chunker::new().add_file_map(files.clone(), &fresh_dir(&format!("{}/rabin", root))).unwrap();
+        assert_eq!(rabin.chunks_written, data.len() / 4);
+
+        let output = fresh_dir(&format!("{}/fixed", root));
+        let stats = Chunker::builder()
+            .min_chunk_bytes(Some(256))
+            .build()
+            .add_file_map(files, &output)
+            .unwrap();
+        assert_eq!(stats.chunks_written, 17);
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        let lens: Vec<u64> = restore_info.files["./pathological.bin"].values().map(|r| r.end - r.start).collect();
+        assert_eq!(lens[..10], [4; 10]);
+        assert_eq!(lens[10..16], [256; 6]);
+        assert_eq!(lens[16], 52);
+        let mut restored = vec![];
+        Chunker::new().restore_file_to_writer("./pathological.bin", &output, &mut restored).unwrap();
+        assert_eq!(restored, data);
+    }
+
+    #[test]
+    fn test_progress_path() {
+        let output = fresh_dir("./tests/tmp/progress_path");
+        let progress_path = std::path::PathBuf::from(format!("{}/progress.json", output));
+        let paths = vec![
+            "./tests/data/CSmall.txt".to_string(),
+            "./tests/data/A.txt".to_string(),
+            "./tests/data/A2.txt".to_string(),
+        ];
+        let bytes_total: u64 = paths.iter().map(|path| fs::metadata(path).unwrap().len()).sum();
+        Chunker::builder()
+            .progress_path(Some(progress_path.clone()))
+            .build()
+            .add_files(paths, &output)
+            .unwrap();
+
+        let updates: Vec<crate::Progress> = fs::read_to_string(&progress_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let done: Vec<(usize, &str)> = updates.iter().map(|p| (p.files_done, p.current_file.as_str())).collect();
+        assert_eq!(done, vec![(1, "./tests/data/A.txt"), (2, "./tests/data/A2.txt"), (3, "./tests/data/CSmall.txt")]);
+        let last = updates.last().unwrap();
+        assert_eq!((last.files_total, last.bytes_done, last.bytes_total), (3, bytes_total, bytes_total));
+        assert!(updates.windows(2).all(|w| w[0].bytes_done <= w[1].bytes_done && w[0].elapsed_sec <= w[1].elapsed_sec));
+    }
+
+    #[test]
+    fn test_max_chunks_per_file() {
+        let output = fresh_dir("./tests/tmp/max_chunks_per_file");
+        let path = format!("{}/random.bin", output);
+        let data = xorshift_bytes(0x2545_F491_4F6C_DD1D, 512 * 1024);
+        fs::write(&path, &data).unwrap();
+
+        let stats = Chunker::builder()
+            .cdc_algorithm(CdcAlgorithm::FastCdc { min: 1024, avg: 4096, max: 16 * 1024 })
+            .max_chunks_per_file(Some(3))
+            .build()
+            .add_files(vec![path.clone(), "./tests/data/A.txt".to_string()], &output)
+            .unwrap();
+        // Three chunks up to the boundaries, the first shared with A.txt, and one with the rest
+        assert_eq!(stats.chunks_written, 4);
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        let ranges: Vec<&StartEndTuple> = restore_info.files[&path].values().collect();
+        assert_eq!(ranges.len(), 4);
+        assert!(ranges[3].end > 16 * 1024);
+        for path in [path.as_str(), "./tests/data/A.txt"] {
+            let mut restored = vec![];
+            Chunker::new().restore_file_to_writer(path, &output, &mut restored).unwrap();
+            assert_eq!(restored, fs::read(path).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_restore_cache_reads_shared_chunks_once() {
+        /// Counts the chunk reads going to a local directory.
+        #[derive(Debug)]
+        struct CountingStorage {
+            inner: LocalStorage,
+            reads: Arc<Mutex<HashMap<String, usize>>>,
+        }
+
+        impl Storage for CountingStorage {
+            fn write_chunk(&self, name: &str, data: &[u8]) -> Result<()> {
+                self.inner.write_chunk(name, data)
+            }
+            fn read_chunk(&self, name: &str) -> Result<Vec<u8>> {
+                *self.reads.lock().unwrap().entry(name.to_string()).or_default() += 1;
+                self.inner.read_chunk(name)
+            }
+            fn write_manifest(&self, data: &[u8]) -> Result<()> {
+                self.inner.write_manifest(data)
+            }
+            fn read_manifest(&self) -> Result<Vec<u8>> {
+                self.inner.read_manifest()
+            }
+        }
+
+        let output = fresh_dir("./tests/tmp/restore_cache/chunks");
+        let restored = fresh_dir("./tests/tmp/restore_cache/restored");
+        let paths = vec![
+            "./tests/data/A.txt".to_string(),
+            "./tests/data/CSmall.txt".to_string(),
+        ];
+        Chunker::new().add_files(paths.clone(), &output).unwrap();
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert_eq!(restore_info.chunk_hashes.len(), 1, "both files should share their chunk");
+
+        let reads = Arc::new(Mutex::new(HashMap::new()));
+        let storage = CountingStorage {
+            inner: LocalStorage::new(&output),
+            reads: reads.clone(),
+        };
+        let chunker = Chunker::builder().storage(storage).build();
+        chunker.restore_all_files(&output, &restored).unwrap();
+        for path in paths.iter() {
+            chunker.restore_file(path, &output, &restored).unwrap();
+        }
+        let reads = reads.lock().unwrap();
+        assert_eq!(reads.len(), 1);
+        assert!(reads.values().all(|&count| count == 1), "{:?}", reads);
+
+        // Without a cache, every restored file reads the chunk again
+        let reads = Arc::new(Mutex::new(HashMap::new()));
+        let storage = CountingStorage {
+            inner: LocalStorage::new(&output),
+            reads: reads.clone(),
+        };
+        Chunker::builder()
+            .storage(storage)
+            .restore_cache_bytes(0)
+            .build()
+            .restore_all_files(&output, &restored)
+            .unwrap();
+        assert!(reads.lock().unwrap().values().all(|&count| count == 2));
+    }
+
+    #[test]
+    fn test_restore_cache_evicts_least_recently_used() {
+        let mut cache = RestoreCache::new();
+        cache.insert("a".to_string(), Arc::new(vec![0; 4]), 10);
+        cache.insert("b".to_string(), Arc::new(vec![0; 4]), 10);
+        assert!(cache.get("a").is_some());
+        cache.insert("c".to_string(), Arc::new(vec![0; 4]), 10);
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+        assert_eq!(cache.bytes, 8);
+        // Too large to be cached at all
+        cache.insert("d".to_string(), Arc::new(vec![0; 11]), 10);
+        assert!(cache.get("d").is_none());
+        assert_eq!(cache.bytes, 8);
+    }
+
+    #[test]
+    fn test_add_files_parallel_io() {
+        let serial = fresh_dir("./tests/tmp/parallel_io/serial");
+        let parallel = fresh_dir("./tests/tmp/parallel_io/parallel");
+        let paths: Vec<String> = ["A.txt", "A2.txt", "CSmall.txt"].iter().map(|name| format!("./tests/data/{}", name)).collect();
+        Chunker::new().add_files(paths.clone(), &serial).unwrap();
+        let stats = Chunker::builder()
+            .prefetch_limit_bytes(60)
+            .build()
+            .add_files_parallel_io(paths.clone(), &parallel)
+            .unwrap();
+        assert_eq!(stats.files_processed, paths.len());
+
+        let (expected, actual) = (RestoreInformation::load(&serial).unwrap(), RestoreInformation::load(&parallel).unwrap());
+        assert_eq!(actual.files, expected.files);
+        assert_eq!(actual.hashes, expected.hashes);
+        for path in paths.iter() {
+            let mut restored = vec![];
+            Chunker::new().restore_file_to_writer(path, &parallel, &mut restored).unwrap();
+            assert_eq!(restored, fs::read(path).unwrap());
+        }
+
+        // Nothing is read ahead for files unchanged since the first run
+        let stats = Chunker::new().add_files_parallel_io(paths.clone(), &parallel).unwrap();
+        assert_eq!(stats.chunks_written, 0);
+        assert_eq!(RestoreInformation::load(&parallel).unwrap().files, expected.files);
+    }
+
+    #[test]
+    fn test_max_manifest_entries() {
+        let output = fresh_dir("./tests/tmp/max_manifest_entries");
+        let files: HashMap<String, Vec<u8>> = (0..5)
+            .map(|i| (format!("./file{}.txt", i), format!("Content of file {}", i).into_bytes()))
+            .collect();
+        let stats = Chunker::builder()
+            .max_manifest_entries(Some(3))
+            .build()
+            .add_file_map(files.clone(), &output)
+            .unwrap();
+        assert_eq!(stats.files_processed, 3);
+        assert_eq!(stats.deferred_files, vec!["./file3.txt".to_string(), "./file4.txt".to_string()]);
+        assert_eq!(RestoreInformation::load(&output).unwrap().hashes.len(), 3);
+
+        // Another run adds the rest, keeping the files already there
+        let rest = stats.deferred_files.iter().map(|path| (path.clone(), files[path].clone())).collect();
+        let stats = Chunker::builder()
+            .max_manifest_entries(Some(3))
+            .build()
+            .add_file_map(rest, &output)
+            .unwrap();
+        assert!(stats.deferred_files.is_empty());
+        let chunker = Chunker::new();
+        for (path, bytes) in files.iter() {
+            let mut restored = vec![];
+            chunker.restore_file_to_writer(path, &output, &mut restored).unwrap();
+            assert_eq!(&restored, bytes);
+        }
+
+        // Nothing is recorded of the deferred files on disk either
+        let output = fresh_dir("./tests/tmp/max_manifest_entries");
+        let paths: Vec<String> = ["A.txt", "A2.txt", "B100MB.bin", "CSmall.txt"].iter().map(|name| format!("./tests/data/{}", name)).collect();
+        let stats = Chunker::builder().max_manifest_entries(Some(2)).build().add_files(paths.clone(), &output).unwrap();
+        assert_eq!(stats.deferred_files, paths[2..]);
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        let mut recorded: Vec<&String> = restore_info.metadata.keys().collect();
+        recorded.sort_unstable();
+        assert_eq!(recorded, paths[..2].iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_truncated_files() {
+        let root = fresh_dir("./tests/tmp/truncated_files");
+        let output = fresh_dir("./tests/tmp/truncated_files/output");
+        let (shrunk, grown) = (format!("{}/shrunk.txt", root), format!("{}/grown.txt", root));
+        fs::write(&shrunk, "A line that is cut short later").unwrap();
+        fs::write(&grown, "Short").unwrap();
+        let stats = Chunker::new().add_files(vec![shrunk.clone(), grown.clone()], &output).unwrap();
+        assert!(stats.truncated_files.is_empty());
+
+        fs::write(&shrunk, "A line").unwrap();
+        fs::write(&grown, "Short no more").unwrap();
+        let stats = Chunker::new().add_files(vec![shrunk.clone(), grown], &output).unwrap();
+        assert_eq!(
+            stats.truncated_files,
+            vec![TruncatedFile {
+                path: shrunk,
+                previous_size: 30,
+                current_size: 6,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_atomic_backup() {
+        let output = fresh_dir("./tests/tmp/atomic_backup/chunks");
+        let path = "./tests/data/CSmall.txt".to_string();
+        let pending = format!("{}/restore_info.yaml.pending", output);
+
+        let result = Chunker::builder()
+            .atomic_backup(true)
+            .simulate_write_failures(Some(1.0))
+            .build()
+            .add_files(vec![path.clone()], &output);
+        assert!(result.is_err());
+        // The crashed run left its journal behind, but no manifest
+        let journal = fs::read_to_string(&pending).unwrap();
+        assert_eq!(journal.lines().count(), 1);
+        assert!(RestoreInformation::load(&output).is_err());
+
+        Chunker::builder()
+            .atomic_backup(true)
+            .build()
+            .add_files(vec![path.clone()], &output)
+            .unwrap();
+        assert!(fs::metadata(&pending).is_err());
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert!(restore_info.chunk_hashes.contains_key(journal.trim()));
+        let mut restored_bytes = vec![];
+        Chunker::new().restore_file_to_writer(&path, &output, &mut restored_bytes).unwrap();
+        assert_eq!(restored_bytes, fs::read(&path).unwrap());
+    }
+
+    #[test]
+    fn test_failed_manifest_write_is_an_error() {
+        let output = fresh_dir("./tests/tmp/manifest_write_failure");
+        let paths = vec!["./tests/data/CSmall.txt".to_string()];
+        Chunker::new().add_files(paths.clone(), &output).unwrap();
+        // Nothing can be written or renamed where a directory is in the way
+        fs::create_dir_all(format!("{}/restore_info.yaml.zst/blocked", output)).unwrap();
+        for atomic in [false, true] {
+            let result = Chunker::builder()
+                .compress_manifest(true)
+                .atomic_backup(atomic)
+                .build()
+                .add_files(paths.clone(), &output);
+            assert!(result.unwrap_err().to_string().contains("restore_info.yaml.zst"));
+        }
+
+        // Nor can the previous manifest be archived
+        let output = fresh_dir("./tests/tmp/manifest_archive_failure");
+        Chunker::new().add_files(paths.clone(), &output).unwrap();
+        fs::write(format!("{}/{}", output, MANIFEST_HISTORY_DIR), "").unwrap();
+        assert!(Chunker::new().add_files(vec!["./tests/data/A.txt".to_string()], &output).is_err());
+    }
+
+    #[test]
+    fn test_estimate_chunks() {
+        let root = fresh_dir("./tests/tmp/estimate_chunks");
+        let paths = vec![
+            "./tests/data/A.txt".to_string(),
+            "./tests/data/A2.txt".to_string(),
+            "./tests/data/CSmall.txt".to_string(),
+        ];
+        let estimate = Chunker::new().estimate_chunks(&paths).unwrap();
+        assert_eq!(estimate.estimated_files, 3);
+        // A2.txt is a copy of A.txt
+        assert_eq!(estimate.estimated_duplicate_bytes, fs::metadata(&paths[1]).unwrap().len());
+        assert_eq!(estimate.estimated_unique_bytes, 27 + 29);
+        assert!(fs::read_dir(&root).unwrap().next().is_none());
+
+        let stats = Chunker::new().add_files(paths, &root).unwrap();
+        assert_eq!(estimate.estimated_chunks, stats.chunks_written);
+    }
+
+    #[test]
+    fn test_mirror_paths() {
+        let output = fresh_dir("./tests/tmp/mirror_paths/output");
+        let mirror = fresh_dir("./tests/tmp/mirror_paths/mirror");
+        // A file where a directory should be takes no chunks
+        let broken = "./tests/tmp/mirror_paths/broken".to_string();
+        fs::write(&broken, "").unwrap();
+        let mirrors = vec![mirror.clone(), broken.clone()];
+        let path = "./tests/data/CSmall.txt".to_string();
+        Chunker::builder().mirror_paths(mirrors.clone()).build().add_files(vec![path.clone()], &output).unwrap();
+
+        let chunker = Chunker::builder().mirror_paths(mirrors).build();
+        let restore_info = RestoreInformation::load(&mirror).unwrap();
+        let chunk_names: Vec<&str> = restore_info.referenced_chunks().into_iter().collect();
+        let mut expected: Vec<(String, String)> = chunk_names.iter().map(|name| (name.to_string(), broken.clone())).collect();
+        expected.sort_unstable();
+        assert_eq!(chunker.verify_mirrors(&output).unwrap(), expected);
+
+        // Chunks missing in the output are read from the mirror
+        let lost = chunk_names[0];
+        fs::remove_file(format!("{}/{}", output, restore_info.stored_chunk_name(lost))).unwrap();
+        let mut restored = vec![];
+        chunker.restore_file_to_writer(&path, &output, &mut restored).unwrap();
+        assert_eq!(restored, fs::read(&path).unwrap());
+        assert!(chunker.verify_mirrors(&output).unwrap().contains(&(lost.to_string(), output.clone())));
+        assert!(Chunker::new().restore_file_to_writer(&path, &output, &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn test_mirror_paths_with_reflink() {
+        let output = fresh_dir("./tests/tmp/mirror_reflink/output");
+        let mirror = fresh_dir("./tests/tmp/mirror_reflink/mirror");
+        let path = "./tests/data/CSmall.txt".to_string();
+        let chunker = Chunker::builder().mirror_paths(vec![mirror.clone()]).enable_reflink(true).build();
+        chunker.add_files(vec![path.clone()], &output).unwrap();
+
+        // Falls back to copying where the file system cannot clone
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        for chunk in restore_info.referenced_chunks() {
+            let name = restore_info.stored_chunk_name(chunk);
+            assert_eq!(fs::read(format!("{}/{}", mirror, name)).unwrap(), fs::read(format!("{}/{}", output, name)).unwrap());
+        }
+        assert!(Chunker::new().verify_all(&mirror).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_compact() {
+        let source = fresh_dir("./tests/tmp/compact/source");
+        let output = fresh_dir("./tests/tmp/compact/output");
+        let compacted = fresh_dir("./tests/tmp/compact/compacted");
+        let data = xorshift_bytes(0x1234_5678_9ABC_DEF1, 256 * 1024);
+        // Fixed-size chunks of the same content shifted by a byte have nothing in common
+        let first = format!("{}/first.bin", source);
+        let second = format!("{}/second.bin", source);
+        fs::write(&first, &data).unwrap();
+        fs::write(&second, [b"x".as_slice(), &data].concat()).unwrap();
+        Chunker::builder()
+            .split_strategy(SplitStrategy::MaxFileSize { max: 16 * 1024 })
+            .build()
+            .add_files(vec![first.clone(), second.clone()], &output)
+            .unwrap();
+
+        let stats = Chunker::builder()
+            .cdc_algorithm(CdcAlgorithm::FastCdc { min: 1024, avg: 4096, max: 16 * 1024 })
+            .build()
+            .compact(&output, &compacted)
+            .unwrap();
+        assert_eq!(stats.files_processed, 2);
+        assert!(stats.bytes_saved > 128 * 1024, "{} bytes saved", stats.bytes_saved);
+        for path in [&first, &second] {
+            let mut restored = vec![];
+            Chunker::new().restore_file_to_writer(path, &compacted, &mut restored).unwrap();
+            assert_eq!(restored, fs::read(path).unwrap());
+        }
+        let metadata = |data_path: &str| RestoreInformation::load(data_path).unwrap().metadata;
+        assert_eq!(metadata(&compacted), metadata(&output));
+        assert!(Chunker::new().compact("./tests/tmp/compact/source", &compacted).is_err());
+
+        // A custom storage would be read from and written to for both directories
+        let manifest = fs::read(format!("{}/restore_info.yaml", output)).unwrap();
+        let recompacted = fresh_dir("./tests/tmp/compact/recompacted");
+        Chunker::builder().storage(LocalStorage::new(&output)).build().compact(&output, &recompacted).unwrap();
+        assert_eq!(fs::read(format!("{}/restore_info.yaml", output)).unwrap(), manifest);
+        let mut restored = vec![];
+        Chunker::new().restore_file_to_writer(&first, &recompacted, &mut restored).unwrap();
+        assert_eq!(restored, data);
+    }
+
+    #[test]
+    fn test_text_manifest() {
+        let output = fresh_dir("./tests/tmp/text_manifest");
+        let paths = vec!["./tests/data/A.txt".to_string(), "./tests/data/CSmall.txt".to_string()];
+        Chunker::builder().text_manifest(true).build().add_files(paths.clone(), &output).unwrap();
+
+        let text = fs::read_to_string(format!("{}/{}", output, TEXT_MANIFEST_FILE)).unwrap();
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("# path\tsize\tblake3\tmodified"));
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        for (path, line) in paths.iter().zip(lines) {
+            let columns: Vec<&str> = line.split('\t').collect();
+            assert_eq!(columns[..3], [path.as_str(), &fs::metadata(path).unwrap().len().to_string(), &restore_info.hashes[path]]);
+            assert!(columns[3].ends_with('Z'), "{}", line);
+        }
+        assert_eq!(text.lines().count(), 3);
+
+        let output = fresh_dir("./tests/tmp/text_manifest_disabled");
+        Chunker::new().add_files(paths, &output).unwrap();
+        assert!(!Path::new(&format!("{}/{}", output, TEXT_MANIFEST_FILE)).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_preserve_metadata() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let source = fresh_dir("./tests/tmp/preserve_metadata/source");
+        let output = fresh_dir("./tests/tmp/preserve_metadata/output");
+        let restored = fresh_dir("./tests/tmp/preserve_metadata/restored");
+        let path = format!("{}/report.txt", source);
+        fs::write(&path, "Lorem ipsum").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_500_000_000);
+        filetime::set_file_mtime(&path, filetime::FileTime::from_system_time(mtime)).unwrap();
+
+        Chunker::new().add_files(vec![path.clone()], &output).unwrap();
+        Chunker::new().restore_file(&path, &output, &restored).unwrap();
+        let metadata = fs::metadata(format!("{}/{}", restored, path)).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o640);
+        let drift = metadata.modified().unwrap().duration_since(mtime).unwrap_or_else(|e| e.duration());
+        assert!(drift <= std::time::Duration::from_secs(1), "{:?}", drift);
+    }
+
+    #[test]
+    fn test_use_mmap() {
+        let source = fresh_dir("./tests/tmp/use_mmap/source");
+        let output = fresh_dir("./tests/tmp/use_mmap/output");
+        let restored = fresh_dir("./tests/tmp/use_mmap/restored");
+        let empty = format!("{}/empty.txt", source);
+        fs::write(&empty, "").unwrap();
+        let paths = vec!["./tests/data/B100MB.bin".to_string(), "./tests/data/CSmall.txt".to_string(), empty];
+        let mapped = Chunker::builder().use_mmap(true).build().add_files(paths.clone(), &output).unwrap();
+        assert_eq!(mapped.files_processed, 3);
+
+        // Mapping changes nothing about the chunks
+        let read = Chunker::new().estimate_chunks(&paths).unwrap();
+        assert_eq!(mapped.chunks_written, read.estimated_chunks);
+        Chunker::new().restore_all_files(&output, &restored).unwrap();
+        for path in paths.iter() {
+            assert_eq!(fs::read(format!("{}/{}", restored, path)).unwrap(), fs::read(path).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_estimated_compression_ratio() {
+        let root = fresh_dir("./tests/tmp/estimated_compression_ratio");
+        let path = format!("{}/lorem.txt", root);
+        fs::write(&path, "Lorem ipsum dolor sit amet. ".repeat(8 * 1024)).unwrap();
+        let estimate = Chunker::new().estimate_chunks(&[path]).unwrap();
+        assert!(estimate.estimated_compression_ratio > 0.0, "{}", estimate.estimated_compression_ratio);
+        assert!(estimate.estimated_compression_ratio < 1.0, "{}", estimate.estimated_compression_ratio);
+
+        assert_eq!(Chunk::new(RabinFingerprint::new()).estimated_compression_ratio(Compression::Zstd), 1.0);
+    }
+
+    #[test]
+    fn test_add_files_with_filter() {
+        let source = fresh_dir("./tests/tmp/filter/source");
+        let output = fresh_dir("./tests/tmp/filter/output");
+        let mut paths = vec![];
+        for name in ["keep.txt", "scratch.tmp", "notes.md", "build.tmp"] {
+            let path = format!("{}/{}", source, name);
+            fs::write(&path, name).unwrap();
+            paths.push(path);
+        }
+
+        let stats = Chunker::new()
+            .add_files_with_filter(paths, &output, |path| path.extension().is_none_or(|ext| ext != "tmp"))
+            .unwrap();
+        assert_eq!(stats.files_processed, 2);
+        assert_eq!(
+            stats.excluded_files,
+            vec![format!("{}/scratch.tmp", source), format!("{}/build.tmp", source)]
+        );
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert_eq!(restore_info.hashes.len(), 2);
+        assert!(restore_info.hashes.keys().all(|path| !path.ends_with(".tmp")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_content_addressable_links() {
+        use std::os::unix::fs::MetadataExt;
+
+        let output = fresh_dir("./tests/tmp/content_addressable_links");
+        let bytes = fs::read("./tests/data/CSmall.txt").unwrap();
+        let chunker = |prefix: &str| {
+            Chunker::builder()
+                .content_addressable_links(true)
+                .chunk_name_prefix(Some(prefix.to_string()))
+                .build()
+        };
+        // The same file in two source trees
+        let backup = |prefix: &str, path: &str| {
+            chunker(prefix)
+                .add_file_map(HashMap::from([(path.to_string(), bytes.clone())]), &output)
+                .unwrap()
+        };
+        backup("first", "./first/CSmall.txt");
+        assert!(fs::metadata(format!("{}/ca_index.yaml", output)).is_ok());
+        // A new backup into the same directory, whose chunks get other file names
+        fs::remove_file(format!("{}/restore_info.yaml", output)).unwrap();
+        let stats = backup("second", "./second/CSmall.txt");
+        assert_eq!(stats.bytes_written_compressed, 0);
+
+        let chunk_name = RestoreInformation::load(&output).unwrap().files["./second/CSmall.txt"].keys().next().unwrap().clone();
+        let first = fs::metadata(format!("{}/first_{}.chunk", output, chunk_name)).unwrap();
+        let second = fs::metadata(format!("{}/second_{}.chunk", output, chunk_name)).unwrap();
+        assert_eq!(first.ino(), second.ino());
+        assert!(Chunker::new().verify_all(&output).unwrap().is_empty());
+        let mut restored = vec![];
+        Chunker::new().restore_file_to_writer("./second/CSmall.txt", &output, &mut restored).unwrap();
+        assert_eq!(restored, bytes);
+
+        // The linked chunk file names the files of the first backup in its header
+        assert!(chunker("second").rebuild_manifest(&output).unwrap().is_empty());
+        assert_eq!(chunker("first").rebuild_manifest(&output).unwrap(), ["./first/CSmall.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hardlink_identical_chunks() {
+        use std::os::unix::fs::MetadataExt;
+
+        let output = fresh_dir("./tests/tmp/hardlink_identical_chunks");
+        let bytes = fs::read("./tests/data/CSmall.txt").unwrap();
+        // The same file in two source trees, the first backed up without the option
+        let backup = |prefix: &str, path: &str, enabled: bool| {
+            Chunker::builder()
+                .hardlink_identical_chunks(enabled)
+                .chunk_name_prefix(Some(prefix.to_string()))
+                .build()
+                .add_file_map(HashMap::from([(path.to_string(), bytes.clone())]), &output)
+                .unwrap();
+            RestoreInformation::load(&output).unwrap()
+        };
+        backup("first", "./first/CSmall.txt", false);
+        fs::remove_file(format!("{}/restore_info.yaml", output)).unwrap();
+        let restore_info = backup("second", "./second/CSmall.txt", true);
+
+        let chunk_name = restore_info.files["./second/CSmall.txt"].keys().next().unwrap().clone();
+        let first = fs::metadata(format!("{}/first_{}.chunk", output, chunk_name)).unwrap();
+        let second = fs::metadata(format!("{}/second_{}.chunk", output, chunk_name)).unwrap();
+        assert_eq!(first.ino(), second.ino());
+        let index: HashMap<String, IndexedChunk> = serde_yaml::from_slice(&fs::read(format!("{}/{}", output, CA_INDEX_FILE)).unwrap()).unwrap();
+        let indexed = index.values().find(|indexed| indexed.chunk_file == format!("first_{}.chunk", chunk_name)).unwrap();
+        assert_eq!(indexed.links, [format!("second_{}.chunk", chunk_name)]);
+        let mut restored = vec![];
+        assert!(Chunker::new().verify_all(&output).unwrap().is_empty());
+        Chunker::new().restore_file_to_writer("./second/CSmall.txt", &output, &mut restored).unwrap();
+        assert_eq!(restored, bytes);
+    }
+
+    #[test]
+    fn test_preflight_check() {
+        let output = fresh_dir("./tests/tmp/preflight_check");
+        let paths = vec![
+            "./tests/data/A.txt".to_string(),
+            "./tests/data/missing.bin".to_string(),
+            "./tests/data/missing2.bin".to_string(),
+        ];
+        let result = Chunker::new().add_files(paths.clone(), &output);
+        let Err(ChunkError::PreflightFailure(failures)) = result else {
+            panic!("expected a preflight failure, got {:?}", result);
+        };
+        let failed: Vec<&str> = failures.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(failed, vec!["./tests/data/missing.bin", "./tests/data/missing2.bin"]);
+        assert!(failures.iter().all(|(_, e)| e.kind() == io::ErrorKind::NotFound));
+        assert_eq!(fs::read_dir(&output).unwrap().count(), 0);
+
+        let result = Chunker::builder().preflight_check(false).build().add_files(paths, &output);
+        assert!(!matches!(result, Err(ChunkError::PreflightFailure(_))));
+    }
+
+    #[test]
+    fn test_unreadable_source_fails_the_run() {
+        // A directory opens fine, but cannot be read
+        let paths = vec!["./tests/data/A.txt".to_string(), "./tests/data".to_string()];
+        for use_mmap in [false, true] {
+            let output = fresh_dir("./tests/tmp/unreadable_source");
+            let result = Chunker::builder().use_mmap(use_mmap).build().add_files(paths.clone(), &output);
+            assert!(result.unwrap_err().to_string().contains("./tests/data"));
+        }
+        let output = fresh_dir("./tests/tmp/unreadable_source");
+        assert!(Chunker::new().add_files_parallel_io(paths, &output).is_err());
+
+        // Uploads queued before the failure are waited for all the same
+        let output = fresh_dir("./tests/tmp/unreadable_source");
+        let mut chunker = Chunker::builder()
+            .storage(LocalStorage::new(&output))
+            .split_strategy(SplitStrategy::MaxFileSize { max: 1024 })
+            .concurrent_chunk_uploads(2)
+            .build();
+        let read = |path: &str| match path {
+            "first.bin" => Ok(xorshift_bytes(1, 8 * 1024)),
+            _ => Err(ChunkError::UnknownFile(path.to_string())),
+        };
+        let result = chunker.add_sources(vec!["first.bin".to_string(), "second.bin".to_string()], "", read, |_| 0);
+        assert!(matches!(result, Err(ChunkError::UnknownFile(_))));
+        assert!(chunker.uploads.is_none());
+        assert!(fs::read_dir(&output).unwrap().count() > 1);
+    }
+
+    #[test]
+    fn test_min_free_space() {
+        let output = fresh_dir("./tests/tmp/min_free_space");
+        let paths = vec!["./tests/data/A.txt".to_string()];
+        let result = Chunker::builder()
+            .min_free_space_bytes(Some(u64::MAX))
+            .build()
+            .add_files(paths.clone(), &output);
+        assert!(matches!(
+            result,
+            Err(ChunkError::InsufficientDiskSpace { required: u64::MAX, .. })
+        ));
+        assert_eq!(fs::read_dir(&output).unwrap().count(), 0);
+
+        Chunker::builder()
+            .min_free_space_bytes(Some(1))
+            .build()
+            .add_files(paths, &output)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_chunk_stats() {
+        let output = fresh_dir("./tests/tmp/chunk_stats");
+        // Large enough for the chunk header not to outweigh what the duplicate saves
+        let text = fs::read_to_string("./tests/data/A.txt").unwrap().repeat(40);
+        let paths = vec![format!("{}/A.txt", output), format!("{}/A2.txt", output)];
+        for path in paths.iter() {
+            fs::write(path, &text).unwrap();
+        }
+        let stats = Chunker::new().add_files(paths, &output).unwrap();
+
+        assert_eq!(stats.files_processed, 2);
+        assert_eq!(stats.bytes_read, 2 * 27 * 40);
+        assert_eq!(stats.chunks_written, 1);
+        assert!(stats.chunks_reused > 0);
+        assert!(stats.deduplication_ratio > 0.0);
+    }
+
+    #[test]
+    fn test_chunk_name_prefix() {
+        let output = fresh_dir("./tests/tmp/chunk_name_prefix");
+        let restored = fresh_dir("./tests/tmp/chunk_name_prefix_restored");
+        let path = "./tests/data/CSmall.txt".to_string();
+        Chunker::builder()
+            .chunk_name_prefix(Some("tenant1".to_string()))
+            .build()
+            .add_files(vec![path.clone()], &output).unwrap();
+
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert_eq!(restore_info.chunk_name_prefix.as_deref(), Some("tenant1"));
+        for chunk_name in restore_info.chunk_hashes.keys() {
+            let chunk_path = format!("{}/tenant1_{}.chunk", output, chunk_name);
+            assert!(std::path::Path::new(&chunk_path).exists());
+            assert!(Chunker::new().verify_chunk(chunk_name, &output).unwrap());
+        }
+
+        Chunker::new().restore_file(&path, &output, &restored).unwrap();
+        let original = fs::read(&path).unwrap();
+        let restored = fs::read(format!("{}/{}", restored, path.trim_start_matches("./"))).unwrap();
+        assert_eq!(original, restored);
+    }
+
+    #[test]
+    fn test_compare_and_skip() {
+        let output = fresh_dir("./tests/tmp/compare_and_skip/chunks");
+        let restored = fresh_dir("./tests/tmp/compare_and_skip/restored");
+        let path = "./tests/data/CSmall.txt".to_string();
+        Chunker::new().add_files(vec![path.clone()], &output).unwrap();
+        // Fresh chunkers, as the restore cache would hold the chunk
+        let chunker = || Chunker::builder().compare_and_skip(true).build();
+        chunker().restore_file(&path, &output, &restored).unwrap();
+        let restored_path = format!("{}/tests/data/CSmall.txt", restored);
+        assert_eq!(fs::read(&restored_path).unwrap(), fs::read(&path).unwrap());
+
+        // With the chunk gone, only a file that is restored already can be skipped
+        let chunk = fs::read_dir(&output)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "chunk"))
+            .unwrap();
+        fs::remove_file(chunk).unwrap();
+        chunker().restore_file(&path, &output, &restored).unwrap();
+        assert!(Chunker::new().restore_file(&path, &output, &restored).is_err());
+        fs::write(&restored_path, "Changed since").unwrap();
+        assert!(chunker().restore_file(&path, &output, &restored).is_err());
+    }
+
+    #[test]
+    fn test_restore_file_atomic() {
+        /// Panics on the first chunk read, like a crash in the middle of a restore.
+        #[derive(Debug)]
+        struct PanickingStorage(LocalStorage);
+
+        impl Storage for PanickingStorage {
+            fn write_chunk(&self, name: &str, data: &[u8]) -> Result<()> {
+                self.0.write_chunk(name, data)
+            }
+            fn read_chunk(&self, _name: &str) -> Result<Vec<u8>> {
+                panic!("simulated crash while restoring");
+            }
+            fn write_manifest(&self, data: &[u8]) -> Result<()> {
+                self.0.write_manifest(data)
+            }
+            fn read_manifest(&self) -> Result<Vec<u8>> {
+                self.0.read_manifest()
+            }
+        }
+
+        let output = fresh_dir("./tests/tmp/restore_atomic/chunks");
+        let restored = fresh_dir("./tests/tmp/restore_atomic/restored");
+        let path = "./tests/data/CSmall.txt";
+        Chunker::new().add_files(vec![path.to_string()], &output).unwrap();
+        let target = format!("{}/tests/data/CSmall.txt", restored);
+
+        let chunker = Chunker::builder().storage(PanickingStorage(LocalStorage::new(&output))).build();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| chunker.restore_file_atomic(path, &output, &restored)));
+        assert!(result.is_err());
+        assert!(fs::metadata(&target).is_err());
+        assert!(fs::metadata(format!("{}.tmp", target)).is_err());
+
+        Chunker::new().restore_file_atomic(path, &output, &restored).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), fs::read(path).unwrap());
+        assert!(fs::metadata(format!("{}.tmp", target)).is_err());
+    }
+
+    #[test]
+    fn test_restore_file_verified() {
+        let output = fresh_dir("./tests/tmp/restore_file_verified/chunks");
+        let restored = fresh_dir("./tests/tmp/restore_file_verified/restored");
+        let path = "./tests/data/CSmall.txt".to_string();
+        Chunker::new().add_files(vec![path.clone()], &output).unwrap();
+        let chunker = Chunker::new();
+        chunker.restore_file_verified(&path, &output, &restored).unwrap();
+        let restored_path = format!("{}/tests/data/CSmall.txt", restored);
+        assert_eq!(fs::read(&restored_path).unwrap(), fs::read(&path).unwrap());
+
+        // Point the file at the wrong bytes of its chunk
+        let manifest = format!("{}/restore_info.yaml", output);
+        let mut restore_info: RestoreInformation = serde_yaml::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+        for start_end in restore_info.files.get_mut(&path).unwrap().values_mut() {
+            start_end.start += 1;
+        }
+        fs::write(&manifest, serde_yaml::to_string(&restore_info).unwrap()).unwrap();
+        match chunker.restore_file_verified(&path, &output, &restored) {
+            Err(ChunkError::IntegrityMismatch { filename, .. }) => assert_eq!(filename, path),
+            other => panic!("expected an integrity mismatch, got {:?}", other),
+        }
+        assert!(fs::metadata(&restored_path).is_err());
+        // Without verification the corrupted content is restored
+        chunker.restore_file(&path, &output, &restored).unwrap();
+        assert_ne!(fs::read(&restored_path).unwrap(), fs::read(&path).unwrap());
+    }
+
+    #[test]
+    fn test_restore_file_to_writer() {
+        let output = fresh_dir("./tests/tmp/restore_to_writer");
+        let paths = vec!["./tests/data/A.txt".to_string(), "./tests/data/A2.txt".to_string()];
+        Chunker::new().add_files(paths.clone(), &output).unwrap();
+
+        for path in paths.iter() {
+            let mut restored = vec![];
+            let written = Chunker::new()
+                .restore_file_to_writer(path, &output, &mut restored)
+                .unwrap();
+            assert_eq!(written, restored.len() as u64);
+            assert_eq!(fs::read(path).unwrap(), restored);
+        }
+
+        let mut restored = vec![];
+        assert!(Chunker::new()
+            .restore_file_to_writer("./tests/data/missing.txt", &output, &mut restored)
+            .is_err());
+    }
+
+    #[test]
+    fn test_multi_chunk_file_is_recorded_once_per_chunk() {
+        let output = fresh_dir("./tests/tmp/multi_chunk_bases");
+        let size = 32 * 1024;
+        let data = xorshift_bytes(0x2545_F491_4F6C_DD1D, 3 * size as usize);
+        let mut chunker = Chunker::builder()
+            .cdc_algorithm(CdcAlgorithm::FastCdc { min: size, avg: size, max: size })
+            .build();
+        chunker
+            .add_sources(vec!["./big.bin".to_string()], &output, |_| Ok(data.clone()), |_| data.len() as u64)
+            .unwrap();
+        assert_eq!(chunker.bases["./big.bin"].len(), 3);
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        let ranges: Vec<(u64, u64)> = restore_info.files["./big.bin"].values().map(|r| (r.start, r.end)).collect();
+        assert_eq!(ranges, vec![(0, size); 3]);
+        let mut restored = vec![];
+        Chunker::new().restore_file_to_writer("./big.bin", &output, &mut restored).unwrap();
+        assert_eq!(restored, data);
+
+        // A file listed twice by one chunk gets one base and one range
+        let mut chunk = chunker.first_chunk().unwrap();
+        chunk.add_file("./twice.txt", 0, b"Lorem ");
+        chunk.add_file("./twice.txt", 6, b"ipsum");
+        chunker.seal_chunk(chunk);
+        assert_eq!(chunker.bases["./twice.txt"].len(), 1);
+        chunker.dump_restore_info(&output).unwrap();
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        let ranges: Vec<(u64, u64)> = restore_info.files["./twice.txt"].values().map(|r| (r.start, r.end)).collect();
+        assert_eq!(ranges, vec![(0, 11)]);
+    }
+
+    #[test]
+    fn test_merge() {
+        let first = fresh_dir("./tests/tmp/merge_first");
+        let second = fresh_dir("./tests/tmp/merge_second");
+        let output = fresh_dir("./tests/tmp/merge_output");
+        let size = 32 * 1024;
+        let shared = xorshift_bytes(0x2545_F491_4F6C_DD1D, 3 * size as usize);
+        let chunker = || {
+            Chunker::builder()
+                .cdc_algorithm(CdcAlgorithm::FastCdc { min: size, avg: size, max: size })
+                .build()
+        };
+        let first_files = HashMap::from([
+            ("./a_shared.bin".to_string(), shared.clone()),
+            ("./z_first.txt".to_string(), b"Only in the first backup".to_vec()),
+        ]);
+        let second_files = HashMap::from([
+            ("./a_shared.bin".to_string(), shared.clone()),
+            ("./b_copy.bin".to_string(), shared.clone()),
+            ("./z_second.txt".to_string(), b"Only in the second backup".to_vec()),
+        ]);
+        chunker().add_file_map(first_files.clone(), &first).unwrap();
+        chunker().add_file_map(second_files.clone(), &second).unwrap();
+
+        let chunk_files = |dir: &str| {
+            fs::read_dir(dir)
+                .unwrap()
+                .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().ends_with(".chunk"))
+                .count()
+        };
+        Chunker::new().merge(&[&first, &second], &output).unwrap();
+        // Three chunks of the shared file and one of the small file each, the shared ones are stored once
+        assert_eq!(chunk_files(&first) + chunk_files(&second), 8);
+        assert_eq!(chunk_files(&output), 5);
+
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert_eq!(restore_info.hashes.len(), 4);
+        assert_eq!(restore_info.duplicates.len(), 1);
+        assert!(Chunker::new().verify_all(&output).unwrap().is_empty());
+        for (path, data) in first_files.into_iter().chain(second_files) {
+            let mut restored = vec![];
+            Chunker::new().restore_file_to_writer(&path, &output, &mut restored).unwrap();
+            assert_eq!(restored, data, "{}", path);
+        }
+
+        let seeded = Chunker::builder().hash_seed(Some(7)).build();
+        assert!(matches!(
+            seeded.merge(&[&first], &output),
+            Err(ChunkError::HashSeedMismatch { manifest: None, chunker: Some(7) })
+        ));
+    }
+
+    #[test]
+    fn test_deduplicate_within_file() {
+        let root = "./tests/tmp/deduplicate_within_file";
+        let size = 32 * 1024;
+        let (a, b) = (xorshift_bytes(0x5a, size as usize), xorshift_bytes(0xc3, size as usize));
+        // The chunk the file ends in is not compared, later files could add to it
+        let data = [a.clone(), b, a.clone(), a, b"tail".to_vec()].concat();
+        let files = HashMap::from([("./repeated.bin".to_string(), data.clone())]);
+        let chunker = |enabled| {
+            Chunker::builder()
+                .cdc_algorithm(CdcAlgorithm::FastCdc { min: size, avg: size, max: size })
+                .deduplicate_within_file(enabled)
+                .build()
+        };
+
+        let output = fresh_dir(&format!("{}/enabled", root));
+        let stats = chunker(true).add_file_map(files.clone(), &output).unwrap();
+        assert_eq!(stats.chunks_written, 3);
+        assert_eq!(stats.chunks_reused, 2);
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        let first = restore_info.file_map("./repeated.bin").unwrap().get_index(0).unwrap().0.clone();
+        assert_eq!(
+            restore_info.forward_refs["./repeated.bin"],
+            vec![ForwardRef { position: 2, chunk: first.clone() }, ForwardRef { position: 3, chunk: first }]
+        );
+        let mut restored = vec![];
+        Chunker::new().restore_file_to_writer("./repeated.bin", &output, &mut restored).unwrap();
+        assert_eq!(restored, data);
+        let mut range = vec![];
+        Chunker::new().restore_file_range("./repeated.bin", size * 2 + 10, size * 3 + 10, &output, &mut range).unwrap();
+        assert_eq!(range, &data[(size * 2 + 10) as usize..(size * 3 + 10) as usize]);
+
+        // Unchanged files keep their references in the next run
+        chunker(true).add_file_map(files.clone(), &output).unwrap();
+        let mut restored = vec![];
+        Chunker::new().restore_file_to_writer("./repeated.bin", &output, &mut restored).unwrap();
+        assert_eq!(restored, data);
+
+        let output = fresh_dir(&format!("{}/disabled", root));
+        chunker(false).add_file_map(files, &output).unwrap();
+        assert!(RestoreInformation::load(&output).unwrap().forward_refs.is_empty());
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = fresh_dir("./tests/tmp/diff/old");
+        let new = fresh_dir("./tests/tmp/diff/new");
+        let old_files = HashMap::from([
+            ("./kept.txt".to_string(), b"Unchanged between the runs".to_vec()),
+            ("./modified.txt".to_string(), b"First version".to_vec()),
+            ("./removed.txt".to_string(), b"Only in the first run".to_vec()),
+        ]);
+        let new_files = HashMap::from([
+            ("./added.txt".to_string(), b"Only in the second run".to_vec()),
+            ("./kept.txt".to_string(), b"Unchanged between the runs".to_vec()),
+            ("./modified.txt".to_string(), b"Second version".to_vec()),
+        ]);
+        Chunker::new().add_file_map(old_files, &old).unwrap();
+        Chunker::new().add_file_map(new_files, &new).unwrap();
+
+        let diff = Chunker::new().diff(&old, &new).unwrap();
+        assert_eq!(diff.added_files, vec!["./added.txt".to_string()]);
+        assert_eq!(diff.removed_files, vec!["./removed.txt".to_string()]);
+        assert_eq!(diff.modified_files, vec!["./modified.txt".to_string()]);
+        let (old_info, new_info) = (RestoreInformation::load(&old).unwrap(), RestoreInformation::load(&new).unwrap());
+        let (old_chunks, new_chunks) = (old_info.referenced_chunks(), new_info.referenced_chunks());
+        assert!(!diff.new_chunks.is_empty());
+        assert!(diff.new_chunks.iter().all(|chunk| new_chunks.contains(chunk.as_str()) && !old_chunks.contains(chunk.as_str())));
+        assert!(!diff.removed_chunks.is_empty());
+        assert!(diff.removed_chunks.iter().all(|chunk| old_chunks.contains(chunk.as_str()) && !new_chunks.contains(chunk.as_str())));
+
+        assert_eq!(Chunker::new().diff(&new, &new).unwrap(), ManifestDiff::default());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let output = fresh_dir("./tests/tmp/snapshot");
+        let restored = fresh_dir("./tests/tmp/snapshot_restored");
+        let chunker = Chunker::new();
+        Chunker::new().add_bytes("./a.txt", b"Lorem ipsum dolor sit amet", &output).unwrap();
+        chunker.snapshot("v1", &output).unwrap();
+        assert!(chunker.snapshot("v1", &output).is_err());
+        assert!(matches!(chunker.snapshot("../v2", &output), Err(ChunkError::InvalidSnapshotLabel(_))));
+
+        Chunker::new().add_bytes("./a.txt", b"consetetur sadipscing elitr", &output).unwrap();
+        Chunker::new().add_bytes("./b.txt", b"sed diam nonumy eirmod", &output).unwrap();
+        chunker.snapshot("v2", &output).unwrap();
+        let labels: Vec<String> = chunker.list_snapshots(&output).unwrap().into_iter().map(|s| s.label).collect();
+        assert_eq!(labels, vec!["v1", "v2"]);
+
+        // The chunk of the first version of a.txt only lives on in the snapshot
+        assert!(chunker.gc(&output, None).unwrap().is_empty());
+        chunker.restore_file_from_snapshot("./a.txt", Some("v1"), &output, &restored).unwrap();
+        assert_eq!(fs::read(format!("{}/a.txt", restored)).unwrap(), b"Lorem ipsum dolor sit amet");
+        assert!(matches!(
+            chunker.restore_file_from_snapshot("./b.txt", Some("v1"), &output, &restored),
+            Err(ChunkError::Context { source, .. }) if matches!(*source, ChunkError::UnknownFile(_))
+        ));
+        chunker.restore_file_from_snapshot("./a.txt", None, &output, &restored).unwrap();
+        assert_eq!(fs::read(format!("{}/a.txt", restored)).unwrap(), b"consetetur sadipscing elitr");
+        assert!(matches!(
+            chunker.restore_file_from_snapshot("./a.txt", Some("v3"), &output, &restored),
+            Err(ChunkError::Context { source, .. }) if matches!(*source, ChunkError::UnknownSnapshot(_))
+        ));
+    }
+
+    #[derive(Debug, Clone)]
+    struct MockClock(Arc<Mutex<SystemTime>>);
+
+    impl crate::Clock for MockClock {
+        fn now(&self) -> SystemTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_gc_older_than() {
+        let output = fresh_dir("./tests/tmp/gc_older_than");
+        let restored = fresh_dir("./tests/tmp/gc_older_than_restored");
+        let start = UNIX_EPOCH + Duration::from_secs(4_000_000_000);
+        let clock = MockClock(Arc::new(Mutex::new(start)));
+        let chunker = || ChunkerBuilder::new().clock(clock.clone()).build();
+
+        chunker().add_bytes("./a.txt", b"Lorem ipsum dolor sit amet", &output).unwrap();
+        let old_chunks: Vec<String> = RestoreInformation::load(&output).unwrap().chunk_timestamps.into_keys().collect();
+        chunker().snapshot("v1", &output).unwrap();
+        chunker().add_bytes("./a.txt", b"consetetur sadipscing elitr", &output).unwrap();
+        assert!(chunker().gc(&output, None).unwrap().is_empty());
+
+        *clock.0.lock().unwrap() = start + Duration::from_secs(86400 * 30);
+        chunker().add_bytes("./b.txt", b"sed diam nonumy eirmod", &output).unwrap();
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        for chunk_name in old_chunks.iter() {
+            assert_eq!(restore_info.chunk_timestamps[chunk_name], 4_000_000_000);
+        }
+        assert_eq!(restore_info.chunk_timestamps[&restore_info.files["./b.txt"].keys().next().unwrap().to_string()], 4_000_000_000 + 86400 * 30);
+
+        // The snapshot was taken long before the cutoff, so it no longer keeps the first a.txt
+        let removed = chunker().gc(&output, Some(Duration::from_secs(86400 * 7))).unwrap();
+        let expected: Vec<String> = old_chunks.iter().map(|chunk_name| format!("{}/{}", output, restore_info.stored_chunk_name(chunk_name))).collect();
+        assert_eq!(removed, expected);
+        chunker().restore_file("./a.txt", &output, &restored).unwrap();
+        assert_eq!(fs::read(format!("{}/a.txt", restored)).unwrap(), b"consetetur sadipscing elitr");
+        chunker().restore_file("./b.txt", &output, &restored).unwrap();
+        assert!(chunker().restore_file_from_snapshot("./a.txt", Some("v1"), &output, &restored).is_err());
+    }
+
+    #[test]
+    fn test_windows_path_separators() {
+        let output = fresh_dir("./tests/tmp/windows_paths");
+        let restored = fresh_dir("./tests/tmp/windows_paths_restored");
+        Chunker::new()
+            .add_file_map(HashMap::from([("foo\\bar\\baz.txt".to_string(), b"Lorem ipsum".to_vec())]), &output)
+            .unwrap();
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert!(restore_info.files.contains_key("foo/bar/baz.txt"));
+        assert!(restore_info.hashes.contains_key("foo/bar/baz.txt"));
+
+        Chunker::new().restore_file("foo\\bar\\baz.txt", &output, &restored).unwrap();
+        assert_eq!(fs::read(format!("{}/foo/bar/baz.txt", restored)).unwrap(), b"Lorem ipsum");
+        let mut bytes = vec![];
+        Chunker::new().restore_file_to_writer("foo/bar\\baz.txt", &output, &mut bytes).unwrap();
+        assert_eq!(bytes, b"Lorem ipsum");
+    }
+
+    #[test]
+    fn test_parallel_hash_matches_serial() {
+        let data = fs::read("./tests/data/B100MB.bin").unwrap();
+        for seed in [None, Some(42)] {
+            assert_eq!(file_hash(seed, &data, 0), content_hash(seed, &data));
+            assert_eq!(file_hash(seed, b"Lorem ipsum", 0), content_hash(seed, b"Lorem ipsum"));
+        }
+    }
+
+    #[test]
+    fn test_tar_round_trip() {
+        let output = fresh_dir("./tests/tmp/tar/chunks");
+        let restored = fresh_dir("./tests/tmp/tar/restored");
+        let tar_path = "./tests/tmp/tar/backup.tar";
+        let paths = vec![
+            "./tests/data/A.txt".to_string(),
+            "./tests/data/A2.txt".to_string(),
+            "./tests/data/CSmall.txt".to_string(),
+        ];
+        Chunker::new().add_files(paths.clone(), &output).unwrap();
+        let chunker = Chunker::new();
+        chunker.snapshot("v1", &output).unwrap();
+        fs::write(format!("{}/notes.txt", output), b"Not part of the backup").unwrap();
+        chunker.export_chunk_files_as_tar(&output, tar_path).unwrap();
+
+        fs::remove_dir_all(&output).unwrap();
+        chunker.import_from_tar(tar_path, &output).unwrap();
+        assert!(fs::metadata(format!("{}/notes.txt", output)).is_err());
+        assert_eq!(chunker.list_snapshots(&output).unwrap().len(), 1);
+        assert!(chunker.verify_all(&output).unwrap().is_empty());
+        chunker.restore_all_files(&output, &restored).unwrap();
+        for path in paths.iter() {
+            let restored_path = format!("{}/{}", restored, path.trim_start_matches("./"));
+            assert_eq!(fs::read(restored_path).unwrap(), fs::read(path).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_metrics_path() {
+        let output = fresh_dir("./tests/tmp/metrics_path");
+        let metrics_path = std::path::PathBuf::from(format!("{}/filechunk.prom", output));
+        let stats = Chunker::builder()
+            .metrics_path(Some(metrics_path.clone()))
+            .build()
+            .add_files(vec!["./tests/data/CSmall.txt".to_string()], &output)
+            .unwrap();
+        let text = fs::read_to_string(&metrics_path).unwrap();
+        let expected = format!("filechunk_bytes_read_total{{job=\"filechunk\",instance=\"{}\"}} {}", output, stats.bytes_read);
+        assert!(text.lines().any(|line| line == expected), "{}", text);
+    }
+
+    #[cfg(feature = "perceptual-hash")]
+    #[test]
+    fn test_find_similar_images() {
+        use img_hash::image::{Rgb, RgbImage};
+
+        let source = fresh_dir("./tests/tmp/similar_images/source");
+        let output = fresh_dir("./tests/tmp/similar_images/output");
+        // Smooth blobs, as flat gradients and hard edges make poor photos
+        let blob = |(cx, cy, r): (f32, f32, f32), x: u32, y: u32| (-((x as f32 - cx).powi(2) + (y as f32 - cy).powi(2)) / (r * r)).exp();
+        let photo = RgbImage::from_fn(256, 192, |x, y| {
+            let v = 40.0 + 180.0 * blob((70.0, 60.0, 40.0), x, y) + 120.0 * blob((190.0, 140.0, 60.0), x, y);
+            Rgb([v as u8, (v * 0.8) as u8, (255.0 - v) as u8])
+        });
+        let other = RgbImage::from_fn(256, 192, |x, y| {
+            let v = 200.0 - 150.0 * blob((128.0, 40.0, 50.0), x, y) - 40.0 * blob((30.0, 170.0, 30.0), x, y);
+            Rgb([v as u8, v as u8, (v * 0.5) as u8])
+        });
+        let paths: Vec<String> = ["photo.png", "photo.jpg", "other.png", "notes.txt"].iter().map(|name| format!("{}/{}", source, name)).collect();
+        photo.save(&paths[0]).unwrap();
+        photo.save(&paths[1]).unwrap();
+        other.save(&paths[2]).unwrap();
+        fs::write(&paths[3], "Lorem ipsum").unwrap();
+
+        // The PNG and JPEG differ in every chunk, but not to the eye
+        Chunker::builder().perceptual_hash_images(true).build().add_files(paths.clone(), &output).unwrap();
+        assert_eq!(RestoreInformation::load(&output).unwrap().phashes.len(), 3);
+        let similar = Chunker::new().find_similar_images(&output, 4).unwrap();
+        assert_eq!(similar.len(), 1, "{:?}", similar);
+        assert_eq!((similar[0].0.as_str(), similar[0].1.as_str()), (paths[1].as_str(), paths[0].as_str()));
+    }
+
+    #[test]
+    fn test_manifest_hmac() {
+        let output = fresh_dir("./tests/tmp/manifest_hmac");
+        Chunker::builder()
+            .manifest_hmac_secret(Some(b"shared secret".to_vec()))
+            .build()
+            .add_bytes("./a.txt", b"Lorem ipsum", &output)
+            .unwrap();
+        let chunker = Chunker::new();
+        assert!(chunker.verify_hmac(&output, b"shared secret").unwrap());
+        assert!(!chunker.verify_hmac(&output, b"other secret").unwrap());
+
+        let manifest_path = format!("{}/restore_info.yaml", output);
+        let manifest = fs::read_to_string(&manifest_path).unwrap();
+        fs::write(&manifest_path, manifest.replace("./a.txt", "./b.txt")).unwrap();
+        assert!(!chunker.verify_hmac(&output, b"shared secret").unwrap());
+
+        fs::write(&manifest_path, manifest).unwrap();
+        fs::remove_file(format!("{}/{}", output, MANIFEST_SIG_FILE)).unwrap();
+        assert!(!chunker.verify_hmac(&output, b"shared secret").unwrap());
+    }
+
+    #[test]
+    fn test_manifest_format_version() {
+        let output = fresh_dir("./tests/tmp/format_version");
+        Chunker::new().add_bytes("./a.txt", b"Lorem ipsum", &output).unwrap();
+        let mut restore_info = RestoreInformation::load(&output).unwrap();
+        assert_eq!(restore_info.format_version, MANIFEST_FORMAT_VERSION);
+
+        let chunker = Chunker::new();
+        let write = |restore_info: &RestoreInformation| {
+            fs::write(format!("{}/restore_info.yaml", output), serde_yaml::to_string(restore_info).unwrap()).unwrap();
+        };
+        restore_info.format_version = "1.7.0".to_string();
+        write(&restore_info);
+        let mut restored = vec![];
+        chunker.restore_file_to_writer("./a.txt", &output, &mut restored).unwrap();
+        assert_eq!(restored, b"Lorem ipsum");
+
+        for version in ["2.0.0", "next"] {
+            restore_info.format_version = version.to_string();
+            write(&restore_info);
+            let error = chunker.restore_file_to_writer("./a.txt", &output, &mut vec![]).unwrap_err();
+            assert!(matches!(
+                error,
+                ChunkError::Context { source, .. }
+                    if matches!(&*source, ChunkError::IncompatibleManifestVersion { found, .. } if found == version)
+            ));
+        }
+
+        // Manifests from before the version was recorded
+        let yaml = fs::read_to_string(format!("{}/restore_info.yaml", output)).unwrap();
+        let legacy: String = yaml.lines().filter(|line| !line.starts_with("formatVersion")).map(|line| format!("{}\n", line)).collect();
+        fs::write(format!("{}/restore_info.yaml", output), legacy).unwrap();
+        chunker.restore_file_to_writer("./a.txt", &output, &mut vec![]).unwrap();
+    }
+
+    #[test]
+    fn test_restore_file_range() {
+        let output = fresh_dir("./tests/tmp/restore_file_range");
+        let mib = 1024 * 1024;
+        let mut data = fs::read("./tests/data/B100MB.bin").unwrap();
+        data.truncate(30 * mib);
+        Chunker::new()
+            .add_file_map(HashMap::from([("./big.bin".to_string(), data.clone())]), &output)
+            .unwrap();
+        // The range has to span a chunk boundary
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert!(restore_info.files["./big.bin"].len() > 1);
+
+        let chunker = Chunker::new();
+        for (start, end) in [(10 * mib, 20 * mib), (0, 1), (29 * mib, 40 * mib), (5, 5)] {
+            let mut restored = vec![];
+            chunker
+                .restore_file_range("./big.bin", start as u64, end as u64, &output, &mut restored)
+                .unwrap();
+            assert!(restored == data[start..end.min(data.len())], "{}..{}", start, end);
+        }
+    }
+
+    #[test]
+    fn test_max_memory_per_restore() {
+        let pattern: Vec<u8> = (0..256 * 1024u32).flat_map(|i| (i % 251).to_le_bytes()).collect();
+        let files = HashMap::from([
+            ("./a.txt".to_string(), b"Lorem ipsum dolor sit amet".to_vec()),
+            ("./b.bin".to_string(), pattern),
+        ]);
+        for codec in [Compression::None, Compression::Snappy, Compression::Zstd] {
+            let output = fresh_dir(&format!("./tests/tmp/max_memory_per_restore/{}", codec.name()));
+            Chunker::builder()
+                .per_file_compression(vec![("*".to_string(), codec)])
+                .build()
+                .add_file_map(files.clone(), &output)
+                .unwrap();
+            // Both files share one chunk, so b.bin starts in the middle of it
+            assert_eq!(RestoreInformation::load(&output).unwrap().chunk_hashes.len(), 1);
+
+            let streaming = Chunker::builder().max_memory_per_restore(Some(64 * 1024)).build();
+            for (path, bytes) in files.iter() {
+                let mut restored = vec![];
+                let written = streaming.restore_file_to_writer(path, &output, &mut restored).unwrap();
+                assert_eq!(written, bytes.len() as u64);
+                assert_eq!(&restored, bytes, "{} with {}", path, codec.name());
+            }
+            // Streamed chunks are not cached
+            assert!(streaming.restore_cache.lock().unwrap().chunks.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_add_file_map_matches_add_files() {
+        let source = fresh_dir("./tests/tmp/file_map/source");
+        let mut files = HashMap::new();
+        files.insert(format!("{}/a.txt", source), b"Lorem ipsum dolor sit amet".to_vec());
+        files.insert(format!("{}/b.txt", source), b"Lorem ipsum dolor sit amet".to_vec());
+        files.insert(format!("{}/c.bin", source), vec![7u8; 4096]);
+        for (path, bytes) in files.iter() {
+            fs::write(path, bytes).unwrap();
+        }
+
+        let from_disk = fresh_dir("./tests/tmp/file_map/from_disk");
+        Chunker::new().add_files(files.keys().cloned().collect(), &from_disk).unwrap();
+        let from_map = fresh_dir("./tests/tmp/file_map/from_map");
+        Chunker::new().add_file_map(files.clone(), &from_map).unwrap();
+
+        let mut restore_info = RestoreInformation::load(&from_disk).unwrap();
+        assert_eq!(restore_info.metadata.len(), 3);
+        restore_info.metadata.clear();
+        assert_eq!(restore_info, RestoreInformation::load(&from_map).unwrap());
+        for (path, bytes) in files.iter() {
+            let mut restored = vec![];
+            Chunker::new().restore_file_to_writer(path, &from_map, &mut restored).unwrap();
+            assert_eq!(bytes, &restored);
+        }
+    }
+
+    #[test]
+    fn test_parallel_compression_matches_sequential() {
+        let paths = vec![
+            "./tests/data/A.txt".to_string(),
+            "./tests/data/A2.txt".to_string(),
+            "./tests/data/B100MB.bin".to_string(),
+            "./tests/data/CSmall.txt".to_string(),
+        ];
+        // Both runs stamp their chunks with the same time
+        let clock = MockClock(Arc::new(Mutex::new(SystemTime::now())));
+        let sequential = fresh_dir("./tests/tmp/parallel_compression/sequential");
+        Chunker::builder().clock(clock.clone()).build().add_files(paths.clone(), &sequential).unwrap();
+        let parallel = fresh_dir("./tests/tmp/parallel_compression/parallel");
+        let stats = Chunker::builder()
+            .clock(clock)
+            .parallel_compression_threads(4)
+            .build()
+            .add_files(paths.clone(), &parallel)
+            .unwrap();
+        assert!(stats.chunks_written > 1);
+
+        assert_eq!(RestoreInformation::load(&sequential).unwrap(), RestoreInformation::load(&parallel).unwrap());
+        let mut restored = vec![];
+        Chunker::new().restore_file_to_writer(&paths[3], &parallel, &mut restored).unwrap();
+        assert_eq!(restored, fs::read(&paths[3]).unwrap());
+    }
+
+    #[test]
+    fn test_add_bytes() {
+        let output = fresh_dir("./tests/tmp/add_bytes");
+        let message: Vec<u8> = (1..=10_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        Chunker::new().add_bytes("messages/0001.pb", &message, &output).unwrap();
+        Chunker::new().add_bytes("messages/0002.pb", b"second", &output).unwrap();
+
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert_eq!(
+            restore_info.hashes["messages/0001.pb"],
+            blake3::hash(&message).to_hex().to_ascii_lowercase()
+        );
+        let mut restored = vec![];
+        Chunker::new().restore_file_to_writer("messages/0001.pb", &output, &mut restored).unwrap();
+        assert_eq!(restored, message);
+        let mut restored = vec![];
+        Chunker::new().restore_file_to_writer("messages/0002.pb", &output, &mut restored).unwrap();
+        assert_eq!(restored, b"second");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks() {
+        let source = fresh_dir("./tests/tmp/symlinks/source");
+        let output = fresh_dir("./tests/tmp/symlinks/chunks");
+        let restored = fresh_dir("./tests/tmp/symlinks/restored");
+
+        let target = format!("{}/target.txt", source);
+        let link = format!("{}/link.txt", source);
+        fs::write(&target, b"Lorem ipsum dolor sit amet").unwrap();
+        std::os::unix::fs::symlink("target.txt", &link).unwrap();
+
+        Chunker::new().add_files(vec![target.clone(), link.clone()], &output).unwrap();
+
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert_eq!(restore_info.symlinks.get(&link).map(String::as_str), Some("target.txt"));
+        assert!(!restore_info.hashes.contains_key(&link));
+
+        Chunker::new().restore_all_files(&output, &restored).unwrap();
+        let restored_link = format!("{}/{}", restored, link.trim_start_matches("./"));
+        assert_eq!(fs::read_link(&restored_link).unwrap(), std::path::Path::new("target.txt"));
+        assert_eq!(fs::read(&restored_link).unwrap(), b"Lorem ipsum dolor sit amet");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_preserve_xattrs() {
+        let source = fresh_dir("./tests/tmp/xattrs/source");
+        let output = fresh_dir("./tests/tmp/xattrs/chunks");
+        let restored = fresh_dir("./tests/tmp/xattrs/restored");
+        let tagged = format!("{}/tagged.txt", source);
+        let plain = format!("{}/plain.txt", source);
+        fs::write(&tagged, b"Lorem ipsum dolor sit amet").unwrap();
+        fs::write(&plain, b"consetetur sadipscing elitr").unwrap();
+        xattr::set(&tagged, "user.filechunk.tag", b"blue").unwrap();
+
+        Chunker::builder()
+            .preserve_xattrs(true)
+            .build()
+            .add_files(vec![tagged.clone(), plain.clone()], &output)
+            .unwrap();
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert_eq!(restore_info.xattrs[&tagged]["user.filechunk.tag"], b"blue");
+        assert!(!restore_info.xattrs.contains_key(&plain));
+
+        Chunker::new().restore_all_files(&output, &restored).unwrap();
+        let restored_tagged = format!("{}/{}", restored, tagged.trim_start_matches("./"));
+        assert_eq!(xattr::get(&restored_tagged, "user.filechunk.tag").unwrap(), Some(b"blue".to_vec()));
+
+        // Removed attributes are not carried over from the previous run
+        xattr::remove(&tagged, "user.filechunk.tag").unwrap();
+        Chunker::builder()
+            .preserve_xattrs(true)
+            .build()
+            .add_files(vec![tagged.clone()], &output)
+            .unwrap();
+        assert!(RestoreInformation::load(&output).unwrap().xattrs.is_empty());
+    }
+
+    #[cfg(all(feature = "posix-acl", target_os = "linux"))]
+    #[test]
+    fn test_posix_acls() {
+        use posix_acl::{PosixACL, Qualifier, ACL_READ};
+        let source = fresh_dir("./tests/tmp/acls/source");
+        let output = fresh_dir("./tests/tmp/acls/chunks");
+        let restored = fresh_dir("./tests/tmp/acls/restored");
+        let shared = format!("{}/shared.txt", source);
+        let plain = format!("{}/plain.txt", source);
+        fs::write(&shared, b"Lorem ipsum dolor sit amet").unwrap();
+        fs::write(&plain, b"consetetur sadipscing elitr").unwrap();
+        let mut acl = PosixACL::read_acl(&shared).unwrap();
+        acl.set(Qualifier::User(12345), ACL_READ);
+        acl.write_acl(&shared).unwrap();
+
+        Chunker::new().add_files(vec![shared.clone(), plain.clone()], &output).unwrap();
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert!(restore_info.file_acls[&shared].contains("user:12345:r--"));
+        assert!(!restore_info.file_acls.contains_key(&plain));
+
+        Chunker::new().restore_all_files(&output, &restored).unwrap();
+        let restored_acl = PosixACL::read_acl(format!("{}/{}", restored, shared.trim_start_matches("./"))).unwrap();
+        assert_eq!(restored_acl.get(Qualifier::User(12345)), Some(ACL_READ));
+    }
+
+    #[test]
+    fn test_file_info_chunks_count() {
+        let output = fresh_dir("./tests/tmp/file_info");
+        let mut files = HashMap::new();
+        files.insert("a.txt".to_string(), b"Lorem ipsum dolor sit amet".to_vec());
+        files.insert("b.txt".to_string(), b"Lorem ipsum dolor sit amet".to_vec());
+        files.insert("c.txt".to_string(), b"consetetur sadipscing elitr".to_vec());
+        Chunker::new().add_file_map(files, &output).unwrap();
+
+        let restore_info = RestoreInformation::load(&output).unwrap();
+        assert_eq!(restore_info.file_info["a.txt"].chunks_count, 1);
+
+        let listed = Chunker::new().list_files(&output).unwrap();
+        let names: Vec<&str> = listed.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["a.txt", "b.txt", "c.txt"]);
+        assert!(listed.iter().all(|(_, info)| info.chunks_count == 1));
+
+        let stats = Chunker::new().stats(&output).unwrap();
+        assert_eq!(stats.files, 3);
+        assert_eq!(stats.chunks, 1);
+        assert_eq!(stats.average_chunks_per_file, 1.0);
+    }
+
+    #[test]
+    fn test_manifest_is_deterministic() {
+        let paths = vec![
+            "./tests/data/A.txt".to_string(),
+            "./tests/data/A2.txt".to_string(),
+            "./tests/data/CSmall.txt".to_string(),
+        ];
+        let clock = MockClock(Arc::new(Mutex::new(SystemTime::now())));
+        let backup = |dir: &str| {
+            let output = fresh_dir(dir);
+            Chunker::builder().clock(clock.clone()).build().add_files(paths.clone(), &output).unwrap();
+            fs::read(format!("{}/restore_info.yaml", output)).unwrap()
+        };
+        let first = backup("./tests/tmp/deterministic_manifest/first");
+        let second = backup("./tests/tmp/deterministic_manifest/second");
+        assert_eq!(String::from_utf8(first).unwrap(), String::from_utf8(second).unwrap());
+    }
+
+    #[test]
+    fn test_export_manifest_json() {
+        let output = fresh_dir("./tests/tmp/export_json");
+        let paths = vec!["./tests/data/A.txt".to_string(), "./tests/data/A2.txt".to_string()];
+        Chunker::new().add_files(paths, &output).unwrap();
+
+        let json_path = format!("{}/restore_info.json", output);
+        Chunker::new().export_manifest_json(&output, &json_path).unwrap();
+        let json = fs::read_to_string(&json_path).unwrap();
+        assert!(json.contains("\"chunkHashes\""));
+        let exported: RestoreInformation = serde_json::from_str(&json).unwrap();
+        assert_eq!(exported, RestoreInformation::load(&output).unwrap());
+    }
+
+    #[test]
+    fn test_chunk_buffer_len() {
+        let mut chunk = Chunk::new(RabinFingerprint::new());
+        assert!(chunk.is_empty());
+        assert_eq!(chunk.buffer_len(), 0);
+
+        let rest = chunk.add_file("a.txt", 0, b"Lorem ipsum dolor sit amet");
+        assert!(rest.is_empty());
+        assert!(!chunk.is_boundary());
+        assert!(!chunk.is_empty());
+        assert_eq!(chunk.buffer_len(), 26);
+        assert!(chunk.next().is_empty());
+    }
+
+    #[test]
+    fn test_inverted_ranges_have_no_length() {
+        let file = ChunkFile {
+            name: "1".to_string(),
+            start: 10,
+            end: 4,
+            filename: "a.txt".to_string(),
+            forward_ref: None,
+        };
+        assert_eq!(file.byte_len(), 0);
+
+        let mut restore_info = RestoreInformation::default();
+        let mut file_map = IndexMap::new();
+        file_map.insert("1".to_string(), StartEndTuple { start: 10, end: 4 });
+        restore_info.files.insert("a.txt".to_string(), file_map);
+        restore_info.hashes.insert("a.txt".to_string(), "hash".to_string());
+        assert_eq!(restore_info.file_len("a.txt"), Some(0));
+        assert!(restore_info.text_manifest().contains("a.txt\t0\t"));
+    }
+
+    /// Fails if the serialized form of chunk bases changes, such as through a new `PRIME` or
+    /// `WINDOW_SIZE`. Add a `chunk_base_v2.yaml` then, and keep reading this one.
+    #[test]
+    fn test_chunk_base_golden_file() {
+        let mut chunk = Chunk::new(RabinFingerprint::new());
+        chunk.add_file("a.txt", 0, b"Lorem ipsum dolor sit amet");
+        chunk.add_file("b.txt", 0, b", consetetur sadipscing elitr");
+        chunk.repair();
+        let expected = chunk.base;
+
+        let golden = fs::read_to_string("./tests/golden/chunk_base_v1.yaml").unwrap();
+        let parsed: ChunkBase = serde_yaml::from_str(&golden).expect("chunk_base_v1.yaml no longer parses");
+        assert_eq!(parsed, expected, "chunk_base_v1.yaml no longer matches the current format");
+        assert_eq!(serde_yaml::to_string(&expected).unwrap(), golden);
+    }
+
+    #[test]
+    fn test_manifest_snake_case_keys_still_load() {
+        let yaml = "files: {}\nhashes: {}\nduplicates: {}\nchunk_hashes:\n  '1': abc\nfile_info:\n  a.txt:\n    chunks_count: 2\n";
+        let restore_info: RestoreInformation = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(restore_info.chunk_hashes["1"], "abc");
+        assert_eq!(restore_info.file_info["a.txt"].chunks_count, 2);
+    }
+
+    fn restore_information_strategy() -> impl proptest::strategy::Strategy<Value = RestoreInformation> {
+        use proptest::collection::{btree_map, hash_map, vec};
+        use proptest::prelude::*;
+
+        let file_map = vec(("[0-9]{1,13}", any::<u64>(), any::<u64>()), 0..4).prop_map(|entries| {
+            entries
+                .into_iter()
+                .map(|(name, start, end)| (name, StartEndTuple { start, end }))
+                .collect::<IndexMap<_, _>>()
+        });
+        (
+            btree_map("[a-z./]{1,16}", file_map, 0..4),
+            hash_map("[a-z./]{1,16}", "[0-9a-f]{64}", 0..4),
+            hash_map("[0-9a-f]{64}", vec("[a-z./]{1,16}", 0..3), 0..3),
+            hash_map("[0-9]{1,13}", "[0-9a-f]{64}", 0..4),
+            proptest::option::of("[a-z0-9]{1,8}"),
+            hash_map("[a-z./]{1,16}", "[a-z./]{1,16}", 0..3),
+            hash_map("[a-z./]{1,16}", any::<u32>(), 0..3),
+            hash_map("[0-9]{1,13}", "[0-9]{1,13}", 0..3),
+            proptest::option::of("[0-9a-f]{64}"),
+            proptest::option::of(any::<u64>()),
+            proptest::option::of(any::<u64>()),
+            (
+                proptest::option::of("[0-9a-f]{64}"),
+                any::<bool>(),
+                hash_map("[a-z./]{1,16}", (any::<u64>(), any::<u64>()), 0..3),
+                hash_map(
+                    "[0-9]{1,13}",
+                    prop_oneof![Just(Compression::None), Just(Compression::Snappy), Just(Compression::Zstd)],
+                    0..3,
+                ),
+                hash_map("[a-z./]{1,16}", hash_map("user\\.[a-z]{1,8}", vec(any::<u8>(), 0..8), 0..3), 0..3),
+                hash_map("[a-z./]{1,16}", "user::rw-\nuser:[a-z]{1,8}:r--\n", 0..3),
+                "[0-9]{1,2}\\.[0-9]{1,2}\\.[0-9]{1,2}",
+                hash_map("[a-z./]{1,16}", any::<u64>(), 0..3),
+                hash_map("[a-z./]{1,16}", "(video|text)/[a-z0-9]{1,8}", 0..3),
+                hash_map("[a-z./]{1,16}", (any::<u64>(), 0..0o7777u32, any::<u32>(), any::<u32>()), 0..3),
+                hash_map("[a-z./]{1,16}", vec((any::<u64>(), "[0-9]{1,13}"), 0..3), 0..3),
+                hash_map("[0-9]{1,13}", any::<u64>(), 0..3),
+            ),
+        )
+            .prop_map(
+                |(
+                    files,
+                    hashes,
+                    duplicates,
+                    chunk_hashes,
+                    chunk_name_prefix,
+                    symlinks,
+                    counts,
+                    deltas,
+                    previous_manifest_hash,
+                    fingerprint_prime,
+                    hash_seed,
+                    (manifest_hash, encrypted_filenames, compression_stats, chunk_codecs, xattrs, file_acls, format_version, phashes, detected_mime_types, metadata, forward_refs, chunk_timestamps),
+                )| {
+                RestoreInformation {
+                    format_version,
+                    files,
+                    hashes,
+                    duplicates,
+                    chunk_hashes,
+                    chunk_name_prefix,
+                    symlinks,
+                    file_info: counts
+                        .into_iter()
+                        .map(|(name, chunks_count)| (name, FileInfo { chunks_count }))
+                        .collect(),
+                    deltas,
+                    previous_manifest_hash,
+                    manifest_hash,
+                    fingerprint_prime,
+                    hash_seed,
+                    compression_stats: compression_stats
+                        .into_iter()
+                        .map(|(name, (original_bytes, compressed_bytes))| {
+                            (name, CompressionStats { original_bytes, compressed_bytes })
+                        })
+                        .collect(),
+                    chunk_codecs,
+                    chunk_timestamps,
+                    encrypted_filenames,
+                    xattrs,
+                    file_acls,
+                    phashes,
+                    detected_mime_types,
+                    metadata: metadata
+                        .into_iter()
+                        .map(|(name, (mtime, mode, uid, gid))| (name, FileMetadata { mtime, mode, uid, gid }))
+                        .collect(),
+                    forward_refs: forward_refs
+                        .into_iter()
+                        .map(|(name, refs)| {
+                            (name, refs.into_iter().map(|(position, chunk)| ForwardRef { position, chunk }).collect())
+                        })
+                        .collect(),
+                }
+            },
+            )
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_restore_information_json_round_trip(restore_info in restore_information_strategy()) {
+            let json = serde_json::to_string(&restore_info).unwrap();
+            let decoded: RestoreInformation = serde_json::from_str(&json).unwrap();
+            proptest::prop_assert_eq!(decoded, restore_info);
         }
     }
 }
@@ -0,0 +1,24 @@
+use std::fmt;
+use std::time::SystemTime;
+
+/// Tells the time a [`Chunker`](crate::Chunker) goes by, such as for the creation times of chunks
+/// and the cutoff of [`Chunker::gc`](crate::Chunker::gc). Set through
+/// [`ChunkerBuilder::clock`](crate::ChunkerBuilder::clock), to test time-based policies.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The system's wall clock, used unless another [`Clock`] is set.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Seconds since the Unix epoch of `time`, zero before it.
+pub(crate) fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(std::time::UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
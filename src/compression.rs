@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+
+const ZSTD_LEVEL: i32 = 3;
+
+thread_local! {
+    // Setting up a Zstd context adds noticeably to compressing a small chunk, so every
+    // thread keeps its own around
+    static ZSTD_COMPRESSOR: RefCell<Option<zstd::bulk::Compressor<'static>>> = const { RefCell::new(None) };
+}
+
+/// Codec a chunk is stored with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Compression {
+    None,
+    #[default]
+    Snappy,
+    Zstd,
+}
+
+impl Compression {
+    pub(crate) fn compress(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::None => bytes.to_vec(),
+            Compression::Snappy => {
+                let mut encoder = snap::write::FrameEncoder::new(vec![]);
+                encoder.write_all(bytes).unwrap();
+                encoder.into_inner().unwrap()
+            }
+            Compression::Zstd => with_zstd_compressor(|compressor| compressor.compress(bytes)).unwrap(),
+        }
+    }
+
+    pub(crate) fn decompress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(bytes.to_vec()),
+            Compression::Snappy => {
+                let mut decompressed = vec![];
+                snap::read::FrameDecoder::new(bytes).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+            Compression::Zstd => zstd::decode_all(bytes),
+        }
+    }
+
+    /// Returns a reader decompressing `bytes` as it goes, for chunks too large to decompress at once.
+    pub(crate) fn decoder<'a>(&self, bytes: &'a [u8]) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::None => Box::new(bytes),
+            Compression::Snappy => Box::new(snap::read::FrameDecoder::new(bytes)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(bytes)?),
+        })
+    }
+
+    /// Compresses `bytes` with Zstd into `writer` as they are compressed, at the level chunks are
+    /// compressed with, and returns `writer`.
+    pub(crate) fn zstd_compress_into<W: Write>(writer: W, bytes: &[u8]) -> io::Result<W> {
+        with_zstd_compressor(|compressor| {
+            let context = compressor.context_mut();
+            // A previous frame may have been left unfinished by a failed write
+            context.reset(zstd::zstd_safe::ResetDirective::SessionOnly).map_err(zstd_error)?;
+            let mut encoder = zstd::stream::zio::Writer::new(writer, zstd::stream::raw::Encoder::with_context(context));
+            encoder.write_all(bytes)?;
+            encoder.finish()?;
+            Ok(encoder.into_inner().0)
+        })
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Snappy => "snappy",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+/// Runs `f` with the Zstd compressor of the current thread, set up on first use.
+fn with_zstd_compressor<R>(f: impl FnOnce(&mut zstd::bulk::Compressor<'static>) -> io::Result<R>) -> io::Result<R> {
+    ZSTD_COMPRESSOR.with(|compressor| {
+        let mut compressor = compressor.borrow_mut();
+        if compressor.is_none() {
+            *compressor = Some(zstd::bulk::Compressor::new(ZSTD_LEVEL)?);
+        }
+        f(compressor.as_mut().expect("the compressor is set above"))
+    })
+}
+
+fn zstd_error(code: usize) -> io::Error {
+    io::Error::other(zstd::zstd_safe::get_error_name(code))
+}
+
+/// Matches `path` against a glob `pattern`, where `*` matches within a path component, `**`
+/// across components and `?` a single character.
+///
+/// Patterns without a `/` only look at the file name, others at the whole path without its
+/// leading `./`.
+pub(crate) fn glob_matches(pattern: &str, path: &str) -> bool {
+    let path = path.trim_start_matches("./");
+    let subject = if pattern.contains('/') {
+        path
+    } else {
+        path.rsplit('/').next().unwrap_or(path)
+    };
+    matches_from(pattern.as_bytes(), subject.as_bytes())
+}
+
+fn matches_from(pattern: &[u8], subject: &[u8]) -> bool {
+    match pattern {
+        [] => subject.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            // `**/` also matches no directory at all
+            let rest_without_slash = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=subject.len()).any(|i| matches_from(rest, &subject[i..]) || matches_from(rest_without_slash, &subject[i..]))
+        }
+        [b'*', rest @ ..] => {
+            let component_len = subject.iter().position(|&b| b == b'/').unwrap_or(subject.len());
+            (0..=component_len).any(|i| matches_from(rest, &subject[i..]))
+        }
+        [b'?', rest @ ..] => matches!(subject, [c, tail @ ..] if *c != b'/' && matches_from(rest, tail)),
+        [p, rest @ ..] => matches!(subject, [c, tail @ ..] if c == p && matches_from(rest, tail)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data: Vec<u8> = (0..10_000u32).flat_map(|i| (i % 251).to_le_bytes()).collect();
+        for compression in [Compression::None, Compression::Snappy, Compression::Zstd] {
+            let compressed = compression.compress(&data);
+            assert_eq!(compression.decompress(&compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_zstd_context_is_reused() {
+        // The second chunk must not carry anything over from the first one
+        let first = vec![7; 4096];
+        let second: Vec<u8> = (0..4096u32).map(|i| (i % 13) as u8).collect();
+        for data in [&first, &second, &first] {
+            let compressed = Compression::Zstd.compress(data);
+            assert_eq!(Compression::Zstd.decompress(&compressed).unwrap(), *data);
+            let streamed = Compression::zstd_compress_into(vec![], data).unwrap();
+            assert_eq!(Compression::Zstd.decompress(&streamed).unwrap(), *data);
+        }
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("*.txt", "./tests/data/A.txt"));
+        assert!(!glob_matches("*.txt", "./tests/data/B100MB.bin"));
+        assert!(glob_matches("?.txt", "A.txt"));
+        assert!(!glob_matches("?.txt", "A2.txt"));
+        assert!(glob_matches("tests/*/A.txt", "./tests/data/A.txt"));
+        assert!(!glob_matches("tests/*.txt", "./tests/data/A.txt"));
+        assert!(glob_matches("tests/**/*.bin", "./tests/data/B100MB.bin"));
+        assert!(glob_matches("**/*.bin", "B100MB.bin"));
+        assert!(glob_matches("tests/**", "tests/data/A.txt"));
+    }
+}
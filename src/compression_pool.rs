@@ -0,0 +1,113 @@
+use crate::chunkstream::{Chunk, SavedChunk};
+use crate::compression::Compression;
+use crate::error::Result;
+use crossbeam_channel::{Receiver, Sender};
+use std::collections::BTreeMap;
+use std::thread::JoinHandle;
+
+/// A chunk with its compressed bytes, ready to be stored, or why it could not be compressed.
+pub(crate) type CompressedChunk = (Chunk, Result<(Vec<u8>, SavedChunk)>);
+
+/// Compresses chunks on worker threads, handing them back in the order they were submitted.
+pub(crate) struct CompressionPool {
+    jobs: Option<Sender<(usize, Chunk, Compression)>>,
+    results: Receiver<(usize, CompressedChunk)>,
+    workers: Vec<JoinHandle<()>>,
+    submitted: usize,
+    returned: usize,
+    /// Chunks that were compressed before an earlier one.
+    out_of_order: BTreeMap<usize, CompressedChunk>,
+}
+
+impl CompressionPool {
+    pub(crate) fn new(threads: usize, hash_seed: Option<u64>) -> CompressionPool {
+        // Keep a few chunks queued per worker, so reading does not run ahead too far
+        let (jobs, queue) = crossbeam_channel::bounded::<(usize, Chunk, Compression)>(threads * 2);
+        let (done, results) = crossbeam_channel::unbounded();
+        let workers = (0..threads)
+            .map(|_| {
+                let queue = queue.clone();
+                let done = done.clone();
+                std::thread::spawn(move || {
+                    for (index, chunk, codec) in queue.iter() {
+                        let compressed = chunk.compress(codec, hash_seed);
+                        if done.send((index, (chunk, compressed))).is_err() {
+                            return;
+                        }
+                    }
+                })
+            })
+            .collect();
+        CompressionPool {
+            jobs: Some(jobs),
+            results,
+            workers,
+            submitted: 0,
+            returned: 0,
+            out_of_order: BTreeMap::new(),
+        }
+    }
+
+    /// Queues `chunk` for compression with `codec`, waiting while the queue is full.
+    ///
+    /// Returns the chunks that are done and next in line.
+    pub(crate) fn submit(&mut self, chunk: Chunk, codec: Compression) -> Vec<CompressedChunk> {
+        let jobs = self.jobs.as_ref().expect("Pool already finished");
+        jobs.send((self.submitted, chunk, codec)).expect("Compression workers stopped");
+        self.submitted += 1;
+        while let Ok((index, compressed)) = self.results.try_recv() {
+            self.out_of_order.insert(index, compressed);
+        }
+        self.take_in_order()
+    }
+
+    /// Waits for all submitted chunks and returns the ones not returned yet.
+    pub(crate) fn finish(mut self) -> Vec<CompressedChunk> {
+        self.jobs = None;
+        while self.returned + self.out_of_order.len() < self.submitted {
+            let (index, compressed) = self.results.recv().expect("Compression worker panicked");
+            self.out_of_order.insert(index, compressed);
+        }
+        for worker in self.workers.drain(..) {
+            worker.join().expect("Compression worker panicked");
+        }
+        self.take_in_order()
+    }
+
+    fn take_in_order(&mut self) -> Vec<CompressedChunk> {
+        let mut ready = vec![];
+        while let Some(compressed) = self.out_of_order.remove(&self.returned) {
+            ready.push(compressed);
+            self.returned += 1;
+        }
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RabinFingerprint;
+
+    #[test]
+    fn test_chunks_come_back_in_order() {
+        let mut pool = CompressionPool::new(4, None);
+        let mut names = vec![];
+        for i in 0..32u32 {
+            let mut chunk = Chunk::new(RabinFingerprint::new());
+            // Sizes vary, so later chunks tend to be done first
+            chunk.add_file(&format!("{}.txt", i), 0, &vec![i as u8; ((32 - i) * 4096) as usize]);
+            names.extend(pool.submit(chunk, Compression::Zstd).into_iter().map(|(chunk, _)| chunk.name()));
+        }
+        names.extend(pool.finish().into_iter().map(|(chunk, _)| chunk.name()));
+
+        let expected: Vec<String> = (0..32u32)
+            .map(|i| {
+                let mut chunk = Chunk::new(RabinFingerprint::new());
+                chunk.add_file(&format!("{}.txt", i), 0, &vec![i as u8; ((32 - i) * 4096) as usize]);
+                chunk.name()
+            })
+            .collect();
+        assert_eq!(names, expected);
+    }
+}
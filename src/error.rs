@@ -0,0 +1,146 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum ChunkError {
+    Io(io::Error),
+    Manifest(serde_yaml::Error),
+    ManifestJson(serde_json::Error),
+    UnknownChunk(String),
+    UnknownFile(String),
+    UnknownSnapshot(String),
+    /// A snapshot label that is empty or not a plain file name.
+    InvalidSnapshotLabel(String),
+    InsufficientDiskSpace { available: u64, required: u64 },
+    InvalidPrime(u64),
+    /// Sizes of [`CdcAlgorithm::FastCdc`](crate::CdcAlgorithm::FastCdc) that are not `0 < min <= avg <= max`.
+    InvalidChunkSizes { min: u64, avg: u64, max: u64 },
+    /// Sizes of a [`SplitStrategy`](crate::SplitStrategy) that are not `0 < min <= max`.
+    InvalidSplitSizes { min: u64, max: u64 },
+    HashSeedMismatch { manifest: Option<u64>, chunker: Option<u64> },
+    /// Restored content whose hash differs from the one recorded in the manifest.
+    IntegrityMismatch { filename: String, expected: String, actual: String },
+    /// A manifest whose major format version differs from the one this crate writes.
+    IncompatibleManifestVersion { found: String, required: String },
+    /// Backups made with another `fingerprint_prime` than the chunker's.
+    FingerprintPrimeMismatch { manifest: Option<u64>, chunker: Option<u64> },
+    /// `encrypt_filenames` was enabled without a `filename_key`.
+    MissingFilenameKey,
+    /// An encrypted path of the manifest could not be decrypted with the key given.
+    FilenameDecryption(String),
+    /// Input files that could not be found or read, see [`ChunkerBuilder::preflight_check`](crate::ChunkerBuilder::preflight_check).
+    PreflightFailure(Vec<(String, io::Error)>),
+    /// A [`Storage`](crate::Storage) backend failed.
+    Storage(Box<dyn std::error::Error + Send + Sync>),
+    /// `source` happened while doing what `context` describes.
+    Context { context: String, source: Box<ChunkError> },
+}
+
+pub type Result<T> = std::result::Result<T, ChunkError>;
+
+impl fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChunkError::Io(e) => write!(f, "I/O error: {}", e),
+            ChunkError::Manifest(e) => write!(f, "Invalid manifest: {}", e),
+            ChunkError::ManifestJson(e) => write!(f, "Invalid JSON manifest: {}", e),
+            ChunkError::UnknownChunk(name) => write!(f, "Chunk not found in manifest: {}", name),
+            ChunkError::UnknownFile(name) => write!(f, "File not found in manifest: {}", name),
+            ChunkError::UnknownSnapshot(label) => write!(f, "Snapshot not found: {}", label),
+            ChunkError::InvalidSnapshotLabel(label) => write!(f, "Invalid snapshot label: {:?}", label),
+            ChunkError::InsufficientDiskSpace { available, required } => write!(
+                f,
+                "Insufficient disk space: {} bytes available, {} bytes required",
+                available, required
+            ),
+            ChunkError::InvalidPrime(prime) => write!(f, "Not a usable fingerprint prime: {}", prime),
+            ChunkError::InvalidChunkSizes { min, avg, max } => {
+                write!(f, "Invalid chunk sizes: min {}, avg {}, max {}", min, avg, max)
+            }
+            ChunkError::InvalidSplitSizes { min, max } => write!(f, "Invalid split sizes: min {}, max {}", min, max),
+            ChunkError::HashSeedMismatch { manifest, chunker } => write!(
+                f,
+                "Hash seed mismatch: manifest uses {:?}, chunker uses {:?}",
+                manifest, chunker
+            ),
+            ChunkError::IntegrityMismatch { filename, expected, actual } => write!(
+                f,
+                "Integrity mismatch for {}: expected hash {}, restored content hashes to {}",
+                filename, expected, actual
+            ),
+            ChunkError::IncompatibleManifestVersion { found, required } => write!(
+                f,
+                "Incompatible manifest format version {}, {} required",
+                found, required
+            ),
+            ChunkError::FingerprintPrimeMismatch { manifest, chunker } => write!(
+                f,
+                "Fingerprint prime mismatch: manifest uses {:?}, chunker uses {:?}",
+                manifest, chunker
+            ),
+            ChunkError::MissingFilenameKey => write!(f, "Encrypting file names requires a filename key"),
+            ChunkError::FilenameDecryption(path) => write!(f, "Could not decrypt file name: {}", path),
+            ChunkError::PreflightFailure(failures) => {
+                write!(f, "Cannot read {} input files:", failures.len())?;
+                for (path, e) in failures.iter() {
+                    write!(f, " {} ({})", path, e)?;
+                }
+                Ok(())
+            }
+            ChunkError::Storage(e) => write!(f, "Storage error: {}", e),
+            ChunkError::Context { context, source } => write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+impl ChunkError {
+    /// Wraps the error with a description of what was being done when it happened.
+    pub fn context(self, context: impl Into<String>) -> ChunkError {
+        ChunkError::Context {
+            context: context.into(),
+            source: Box::new(self),
+        }
+    }
+}
+
+pub(crate) trait ResultExt<T> {
+    /// Adds the context built by `f` to the error, if there is one.
+    fn with_context<F: FnOnce() -> String>(self, f: F) -> Result<T>;
+}
+
+impl<T, E: Into<ChunkError>> ResultExt<T> for std::result::Result<T, E> {
+    fn with_context<F: FnOnce() -> String>(self, f: F) -> Result<T> {
+        self.map_err(|e| e.into().context(f()))
+    }
+}
+
+impl std::error::Error for ChunkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChunkError::Io(e) => Some(e),
+            ChunkError::Manifest(e) => Some(e),
+            ChunkError::ManifestJson(e) => Some(e),
+            ChunkError::Storage(e) => Some(e.as_ref()),
+            ChunkError::Context { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ChunkError {
+    fn from(e: io::Error) -> Self {
+        ChunkError::Io(e)
+    }
+}
+
+impl From<serde_yaml::Error> for ChunkError {
+    fn from(e: serde_yaml::Error) -> Self {
+        ChunkError::Manifest(e)
+    }
+}
+
+impl From<serde_json::Error> for ChunkError {
+    fn from(e: serde_json::Error) -> Self {
+        ChunkError::ManifestJson(e)
+    }
+}
@@ -0,0 +1,102 @@
+use crate::error::{ChunkError, Result};
+use aes_siv::aead::{Aead, KeyInit};
+use aes_siv::{Aes256SivAead, Key, Nonce};
+
+/// BLAKE3 key derivation context for the AES-256-SIV key, which is twice as long as the master key.
+const KEY_CONTEXT: &str = "fileChunk 2026-10-14 manifest filename encryption";
+const NONCE_LEN: usize = 16;
+
+fn cipher(key: &[u8; 32]) -> Aes256SivAead {
+    let mut siv_key = [0; 64];
+    blake3::Hasher::new_derive_key(KEY_CONTEXT)
+        .update(key)
+        .finalize_xof()
+        .fill(&mut siv_key);
+    Aes256SivAead::new(&Key::<Aes256SivAead>::from(siv_key))
+}
+
+/// Encrypts every component of `path` on its own, keeping the separators as well as `.` and
+/// `..`, so files in the same directory keep sharing its encrypted name.
+///
+/// Components are stored as the hex encoded nonce followed by the ciphertext. The nonce is the
+/// keyed BLAKE3 hash of the component, so encrypting the same path again gives the same result.
+pub(crate) fn encrypt_path(key: &[u8; 32], path: &str) -> String {
+    let cipher = cipher(key);
+    let components: Vec<String> = path
+        .split('/')
+        .map(|component| match component {
+            "" | "." | ".." => component.to_string(),
+            _ => {
+                let hash = blake3::keyed_hash(key, component.as_bytes());
+                let nonce: [u8; NONCE_LEN] = hash.as_bytes()[..NONCE_LEN].try_into().unwrap();
+                let ciphertext = cipher
+                    .encrypt(&Nonce::from(nonce), component.as_bytes())
+                    .expect("AES-SIV encrypts messages of any length");
+                to_hex(&nonce) + &to_hex(&ciphertext)
+            }
+        })
+        .collect();
+    components.join("/")
+}
+
+/// Reverses [`encrypt_path`], failing if `path` was not encrypted with `key`.
+pub(crate) fn decrypt_path(key: &[u8; 32], path: &str) -> Result<String> {
+    let cipher = cipher(key);
+    let components = path
+        .split('/')
+        .map(|component| match component {
+            "" | "." | ".." => Ok(component.to_string()),
+            _ => decrypt_component(&cipher, component).ok_or_else(|| ChunkError::FilenameDecryption(path.to_string())),
+        })
+        .collect::<Result<Vec<String>>>()?;
+    Ok(components.join("/"))
+}
+
+fn decrypt_component(cipher: &Aes256SivAead, component: &str) -> Option<String> {
+    let bytes = from_hex(component)?;
+    if bytes.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    let nonce: [u8; NONCE_LEN] = nonce.try_into().ok()?;
+    let plaintext = cipher.decrypt(&Nonce::from(nonce), ciphertext).ok()?;
+    String::from_utf8(plaintext).ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_path_round_trip() {
+        let key = [7; 32];
+        let path = "./tests/data/A.txt";
+        let encrypted = encrypt_path(&key, path);
+        assert!(!encrypted.contains("tests") && !encrypted.contains("A.txt"));
+        assert!(encrypted.starts_with("./"));
+        assert_eq!(encrypted.matches('/').count(), 3);
+        assert_eq!(decrypt_path(&key, &encrypted).unwrap(), path);
+
+        // Deterministic, and shared directories encrypt the same
+        assert_eq!(encrypt_path(&key, path), encrypted);
+        let sibling = encrypt_path(&key, "./tests/data/A2.txt");
+        assert_eq!(sibling.rsplit_once('/').unwrap().0, encrypted.rsplit_once('/').unwrap().0);
+
+        assert!(decrypt_path(&[8; 32], &encrypted).is_err());
+        assert!(decrypt_path(&key, "./not-hex").is_err());
+    }
+}
@@ -0,0 +1,16 @@
+use crate::error::{ChunkError, Result};
+
+pub(crate) fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Downloads the body of `url`, failing on non-success statuses.
+pub(crate) fn fetch(url: &str) -> Result<Vec<u8>> {
+    tracing::debug!(url = %url, "Fetching");
+    let response = reqwest::blocking::get(url).and_then(|response| response.error_for_status()).map_err(http_error)?;
+    Ok(response.bytes().map_err(http_error)?.to_vec())
+}
+
+fn http_error(e: reqwest::Error) -> ChunkError {
+    ChunkError::from(std::io::Error::other(e)).context("Fetching over HTTP")
+}
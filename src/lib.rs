@@ -1,3 +1,86 @@
+// Without `std`, only the fingerprint and its modular arithmetic are built
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(all(feature = "posix-acl", target_os = "linux"))]
+mod acl;
+#[cfg(feature = "async")]
+mod async_chunker;
 mod bigmath;
+#[cfg(feature = "std")]
+mod builder;
+#[cfg(feature = "std")]
+mod cdc;
+#[cfg(feature = "std")]
 mod chunkstream;
+#[cfg(feature = "std")]
+mod clock;
+#[cfg(feature = "std")]
+mod compression;
+#[cfg(feature = "std")]
+mod compression_pool;
+#[cfg(feature = "std")]
+mod disk_space;
+#[cfg(feature = "std")]
+mod error;
+#[cfg(feature = "encryption")]
+mod filename_crypto;
+#[cfg(feature = "reqwest")]
+mod http;
+#[cfg(feature = "mime-detection")]
+mod mime;
+#[cfg(all(feature = "std", unix))]
+mod mmap;
+#[cfg(feature = "std")]
+mod paths;
+#[cfg(feature = "perceptual-hash")]
+mod phash;
+#[cfg(feature = "std")]
+mod prefetch;
+#[cfg(feature = "std")]
+mod progress;
+#[cfg(feature = "std")]
+mod rate_limit;
+#[cfg(feature = "std")]
+mod reflink;
 mod rolling_hash;
+#[cfg(feature = "s3")]
+mod s3_storage;
+#[cfg(feature = "std")]
+mod similarity;
+#[cfg(feature = "std")]
+mod stats;
+#[cfg(feature = "std")]
+mod storage;
+// Shared with the benches, which see the crate from outside
+#[cfg(all(any(test, feature = "testing"), feature = "std"))]
+#[doc(hidden)]
+pub mod test_util;
+#[cfg(feature = "std")]
+mod text_manifest;
+#[cfg(feature = "std")]
+mod upload_queue;
+
+#[cfg(feature = "async")]
+pub use async_chunker::AsyncChunker;
+pub use bigmath::{is_prime, mod_pow, multiply_mod};
+#[cfg(feature = "std")]
+pub use builder::ChunkerBuilder;
+#[cfg(feature = "std")]
+pub use cdc::{CdcAlgorithm, SplitStrategy};
+#[cfg(feature = "std")]
+pub use clock::{Clock, SystemClock};
+#[cfg(feature = "std")]
+pub use chunkstream::{ChunkFile, ChunkInfo, Chunker, CompressionStats, FileInfo, FileMetadata, SnapshotInfo, StartEndTuple};
+#[cfg(feature = "std")]
+pub use compression::Compression;
+#[cfg(feature = "std")]
+pub use error::{ChunkError, Result};
+#[cfg(feature = "std")]
+pub use progress::Progress;
+pub use rolling_hash::{GearHash, RabinFingerprint};
+#[cfg(feature = "s3")]
+pub use s3_storage::S3Storage;
+#[cfg(feature = "std")]
+pub use stats::{ChunkEstimate, ChunkStats, DedupReport, ManifestDiff, ManifestStats, SharedChunk, TruncatedFile, WindowDedupStats};
+#[cfg(feature = "std")]
+pub use storage::{LocalStorage, Storage};
@@ -0,0 +1,52 @@
+/// Types whose content is compressed already, so compressing it again only costs time. Video
+/// and audio types count as well, except for the uncompressed ones below.
+const COMPRESSED_TYPES: [&str; 17] = [
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "image/heif",
+    "application/zip",
+    "application/gzip",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/zstd",
+    "application/x-7z-compressed",
+    "application/vnd.rar",
+    "application/x-lzip",
+    "application/epub+zip",
+    "application/pdf",
+    "application/wasm",
+];
+
+const UNCOMPRESSED_MEDIA_TYPES: [&str; 2] = ["audio/x-wav", "audio/x-aiff"];
+
+/// Returns the MIME type `bytes` start with the magic bytes of, if any.
+pub(crate) fn detect(bytes: &[u8]) -> Option<String> {
+    infer::get(bytes).map(|kind| kind.mime_type().to_string())
+}
+
+/// Tells whether content of type `mime` gains little from being compressed.
+pub(crate) fn is_compressed(mime: &str) -> bool {
+    if UNCOMPRESSED_MEDIA_TYPES.contains(&mime) {
+        return false;
+    }
+    mime.starts_with("video/") || mime.starts_with("audio/") || COMPRESSED_TYPES.contains(&mime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(b"\x00\x00\x00\x18ftypmp42\x00\x00\x00\x00").as_deref(), Some("video/mp4"));
+        assert_eq!(detect(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR").as_deref(), Some("image/png"));
+        assert_eq!(detect(b"Plain text that only looks like a file"), None);
+        assert!(is_compressed("video/mp4"));
+        assert!(is_compressed("application/zip"));
+        assert!(!is_compressed("audio/x-wav"));
+        assert!(!is_compressed("image/bmp"));
+    }
+}
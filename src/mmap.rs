@@ -0,0 +1,49 @@
+use nix::sys::mman::{madvise, mmap, munmap, MapFlags, MmapAdvise, ProtFlags};
+use std::ffi::c_void;
+use std::fs::File;
+use std::io;
+use std::num::NonZeroUsize;
+use std::ptr::NonNull;
+
+/// A file mapped read-only into memory, unmapped on drop.
+pub(crate) struct MappedFile {
+    ptr: NonNull<c_void>,
+    len: usize,
+}
+
+// The mapping is private and read-only, nothing writes through `ptr`
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    /// Maps `path` and advises the kernel it is read front to back with `MADV_SEQUENTIAL`, so it
+    /// reads ahead. Returns `None` for empty files, which cannot be mapped.
+    pub(crate) fn open_sequential(path: &str) -> io::Result<Option<MappedFile>> {
+        let file = File::open(path)?;
+        let Some(len) = NonZeroUsize::new(file.metadata()?.len() as usize) else {
+            return Ok(None);
+        };
+        // Truncating the file while it is mapped makes reading past its new end fail with SIGBUS,
+        // the same as for every other mmap reader
+        let ptr = unsafe { mmap(None, len, ProtFlags::PROT_READ, MapFlags::MAP_PRIVATE, &file, 0) }.map_err(io::Error::from)?;
+        let mapped = MappedFile { ptr, len: len.get() };
+        unsafe { madvise(ptr, mapped.len, MmapAdvise::MADV_SEQUENTIAL) }.map_err(io::Error::from)?;
+        Ok(Some(mapped))
+    }
+}
+
+impl std::ops::Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr() as *const u8, self.len) }
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { munmap(self.ptr, self.len) } {
+            tracing::warn!(error = %e, "Unable to unmap file");
+        }
+    }
+}
@@ -0,0 +1,7 @@
+/// Returns `path` with forward slashes as separators, the form every path in a manifest has.
+///
+/// Backslashes are taken as separators on every platform, so backups made on Windows restore
+/// anywhere.
+pub(crate) fn normalize_path(path: &str) -> String {
+    path.replace('\\', "/")
+}
@@ -0,0 +1,26 @@
+use img_hash::{HashAlg, HasherConfig};
+
+/// Extensions of the images that get a perceptual hash, compared case-insensitively.
+const IMAGE_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+
+pub(crate) fn is_image(path: &str) -> bool {
+    std::path::Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| IMAGE_EXTENSIONS.iter().any(|image| extension.eq_ignore_ascii_case(image)))
+}
+
+/// Returns the 64 bit DCT perceptual hash of the image at `path`, or `None` if it cannot be
+/// decoded.
+pub(crate) fn perceptual_hash(path: &str) -> Option<u64> {
+    let image = match img_hash::image::open(path) {
+        Ok(image) => image,
+        Err(e) => {
+            tracing::warn!(path = %path, error = %e, "Not hashing undecodable image");
+            return None;
+        }
+    };
+    let hasher = HasherConfig::new().hash_size(8, 8).hash_alg(HashAlg::Mean).preproc_dct().to_hasher();
+    let hash = hasher.hash_image(&image);
+    Some(u64::from_be_bytes(hash.as_bytes().try_into().expect("8x8 hashes are 8 bytes")))
+}
@@ -0,0 +1,128 @@
+use crate::chunkstream::{read_source, source_len};
+use crate::error::Result;
+use rayon::prelude::*;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+/// Files read at once. Reads mostly wait on the disk or network, so this is not tied to the
+/// number of cores.
+const PREFETCH_THREADS: usize = 16;
+
+/// Reads the sources of `add_files_parallel_io` ahead of the chunking loop, several at a time.
+pub(crate) struct Prefetcher {
+    /// Paths to read ahead and not read yet, in the order they are chunked.
+    pending: VecDeque<String>,
+    /// Contents read ahead and not taken yet, or why they could not be read.
+    buffered: HashMap<String, Result<Vec<u8>>>,
+    buffered_bytes: u64,
+    limit: u64,
+    /// Added to every read, to simulate a slow file system.
+    latency: Option<Duration>,
+    pool: rayon::ThreadPool,
+}
+
+impl Prefetcher {
+    pub(crate) fn new(limit: u64, latency: Option<Duration>) -> Prefetcher {
+        Prefetcher {
+            pending: VecDeque::new(),
+            buffered: HashMap::new(),
+            buffered_bytes: 0,
+            limit,
+            latency,
+            pool: rayon::ThreadPoolBuilder::new()
+                .num_threads(PREFETCH_THREADS)
+                .thread_name(|i| format!("prefetch-{}", i))
+                .build()
+                .expect("Unable to start prefetch threads"),
+        }
+    }
+
+    /// Reads `paths` ahead from now on, in that order, dropping what was read ahead before.
+    pub(crate) fn queue(&mut self, paths: impl IntoIterator<Item = String>) {
+        self.pending = paths.into_iter().collect();
+        self.buffered.clear();
+        self.buffered_bytes = 0;
+    }
+
+    /// Returns the content of `path`. Unless it was read ahead, reads it along with the pending
+    /// paths after it in parallel, as many as fit below the limit with what is buffered already.
+    pub(crate) fn read(&mut self, path: &str) -> Result<Vec<u8>> {
+        if let Some(bytes) = self.buffered.remove(path) {
+            self.buffered_bytes -= bytes.as_ref().map_or(0, |bytes| bytes.len() as u64);
+            return bytes;
+        }
+        let Some(position) = self.pending.iter().position(|pending| pending == path) else {
+            // Read before, such as to compare it with the previous run, or not a source at all
+            return self.read_one(path);
+        };
+        self.pending.drain(..position);
+        let mut batch = vec![self.pending.pop_front().unwrap()];
+        let mut batch_bytes = source_len(path);
+        while let Some(next) = self.pending.front() {
+            let len = source_len(next);
+            if self.buffered_bytes + batch_bytes + len > self.limit {
+                break;
+            }
+            batch_bytes += len;
+            batch.push(self.pending.pop_front().unwrap());
+        }
+        tracing::debug!(files = batch.len(), bytes = batch_bytes, "Prefetching files");
+        let mut contents: Vec<(String, Result<Vec<u8>>)> = self.pool.install(|| {
+            batch
+                .into_par_iter()
+                .map(|path| {
+                    let bytes = self.read_one(&path);
+                    (path, bytes)
+                })
+                .collect()
+        });
+        let (_, bytes) = contents.remove(0);
+        for (path, bytes) in contents {
+            self.buffered_bytes += bytes.as_ref().map_or(0, |bytes| bytes.len() as u64);
+            self.buffered.insert(path, bytes);
+        }
+        bytes
+    }
+
+    fn read_one(&self, path: &str) -> Result<Vec<u8>> {
+        if let Some(latency) = self.latency {
+            std::thread::sleep(latency);
+        }
+        read_source(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefetch_respects_limit() {
+        let dir = "./tests/tmp/prefetch";
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        let paths: Vec<String> = (0..4).map(|i| format!("{}/{}.txt", dir, i)).collect();
+        for path in paths.iter() {
+            std::fs::write(path, [b'x'; 10]).unwrap();
+        }
+        let mut prefetcher = Prefetcher::new(25, None);
+        prefetcher.queue(paths.iter().cloned());
+        assert_eq!(prefetcher.read(&paths[0]).unwrap().len(), 10);
+        // The first read took the next path along, the one after did not fit
+        assert_eq!(prefetcher.buffered.len(), 1);
+        assert_eq!(prefetcher.buffered_bytes, 10);
+        assert_eq!(prefetcher.read(&paths[1]).unwrap().len(), 10);
+        assert_eq!(prefetcher.buffered_bytes, 0);
+        // Skipping a path leaves it to be read on its own
+        assert_eq!(prefetcher.read(&paths[3]).unwrap().len(), 10);
+        assert!(prefetcher.pending.is_empty());
+        assert_eq!(prefetcher.read(&paths[2]).unwrap().len(), 10);
+
+        // Paths left out of the queue are never read ahead
+        prefetcher.queue([paths[1].clone(), paths[3].clone()]);
+        assert_eq!(prefetcher.read(&paths[0]).unwrap().len(), 10);
+        assert!(prefetcher.buffered.is_empty());
+        assert_eq!(prefetcher.read(&paths[1]).unwrap().len(), 10);
+        assert_eq!(prefetcher.buffered.keys().collect::<Vec<_>>(), [&paths[3]]);
+    }
+}
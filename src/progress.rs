@@ -0,0 +1,59 @@
+use crate::error::{ChunkError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How far an `add_files` run got, as written to [`ChunkerBuilder::progress_path`](crate::ChunkerBuilder::progress_path).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Progress {
+    pub files_done: usize,
+    pub files_total: usize,
+    /// Bytes of the files read so far. Files unchanged since the previous run count towards
+    /// `files_done` only, they are not read.
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    /// The file done last.
+    pub current_file: String,
+    pub elapsed_sec: f64,
+}
+
+/// Appends a [`Progress`] line of JSON to the progress file after every file.
+pub(crate) struct ProgressLog {
+    path: PathBuf,
+    file: fs::File,
+    progress: Progress,
+}
+
+impl ProgressLog {
+    /// Truncates the progress file at `path`, for a run over `files_total` files of `bytes_total` bytes.
+    pub(crate) fn create(path: &Path, files_total: usize, bytes_total: u64) -> Result<ProgressLog> {
+        let file = fs::File::create(path).map_err(|e| progress_error(path, e.into()))?;
+        Ok(ProgressLog {
+            path: path.to_path_buf(),
+            file,
+            progress: Progress {
+                files_total,
+                bytes_total,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Records that `file` is done, after reading `bytes` of it.
+    pub(crate) fn file_done(&mut self, file: &str, bytes: u64, elapsed: Duration) -> Result<()> {
+        self.progress.files_done += 1;
+        self.progress.bytes_done += bytes;
+        self.progress.current_file = file.to_string();
+        self.progress.elapsed_sec = elapsed.as_secs_f64();
+        let mut line = serde_json::to_vec(&self.progress)?;
+        line.push(b'\n');
+        self.file.write_all(&line).map_err(|e| progress_error(&self.path, e.into()))
+    }
+}
+
+fn progress_error(path: &Path, e: ChunkError) -> ChunkError {
+    e.context(format!("Writing progress to {}", path.display()))
+}
+
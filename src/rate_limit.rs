@@ -0,0 +1,26 @@
+use std::time::{Duration, Instant};
+
+/// Throttles a stream of writes to an average of `bytes_per_sec`.
+pub(crate) struct RateLimiter {
+    bytes_per_sec: u64,
+    start: Instant,
+    consumed: u64,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(bytes_per_sec: u64) -> RateLimiter {
+        RateLimiter {
+            bytes_per_sec: bytes_per_sec.max(1),
+            start: Instant::now(),
+            consumed: 0,
+        }
+    }
+
+    /// Records `bytes` as written and returns how long to wait until the average rate is back
+    /// under the limit.
+    pub(crate) fn consume(&mut self, bytes: u64) -> Duration {
+        self.consumed += bytes;
+        let budget = Duration::from_secs_f64(self.consumed as f64 / self.bytes_per_sec as f64);
+        budget.saturating_sub(self.start.elapsed())
+    }
+}
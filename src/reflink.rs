@@ -0,0 +1,60 @@
+use std::fs;
+use std::io;
+
+#[cfg(target_os = "linux")]
+nix::ioctl_write_int!(ficlone, 0x94, 9);
+
+/// Clones `from` into `to`, sharing its extents instead of copying them, on filesystems with
+/// reflinks such as btrfs and XFS.
+#[cfg(target_os = "linux")]
+fn clone_file(from: &str, to: &str) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let source = fs::File::open(from)?;
+    let target = fs::File::create(to)?;
+    // Both descriptors stay open for the duration of the call
+    unsafe { ficlone(target.as_raw_fd(), source.as_raw_fd() as nix::sys::ioctl::ioctl_param_type) }.map_err(io::Error::from)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn clone_file(_from: &str, _to: &str) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Copies the file `from` to `to`, as a reflink if `reflink` is set and the filesystem allows,
+/// see [`ChunkerBuilder::enable_reflink`](crate::ChunkerBuilder::enable_reflink).
+pub(crate) fn copy_file(from: &str, to: &str, reflink: bool) -> io::Result<()> {
+    if reflink {
+        match clone_file(from, to) {
+            Ok(()) => {
+                tracing::debug!(from = %from, to = %to, "Cloned file");
+                return Ok(());
+            }
+            // Such as across filesystems, or on one without reflinks
+            Err(e) => tracing::debug!(from = %from, to = %to, error = %e, "Unable to clone file, copying it"),
+        }
+    }
+    fs::copy(from, to)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_file_falls_back_to_copying() {
+        let dir = "./tests/tmp/reflink";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let from = format!("{}/from.chunk", dir);
+        fs::write(&from, b"Lorem ipsum dolor sit amet").unwrap();
+        // Cloned or copied, depending on the filesystem the tests run on
+        for (to, reflink) in [("reflinked", true), ("copied", false)] {
+            let to = format!("{}/{}.chunk", dir, to);
+            copy_file(&from, &to, reflink).unwrap();
+            assert_eq!(fs::read(&to).unwrap(), b"Lorem ipsum dolor sit amet");
+        }
+        assert!(copy_file(&format!("{}/missing.chunk", dir), &format!("{}/to.chunk", dir), true).is_err());
+    }
+}
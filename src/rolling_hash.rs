@@ -1,41 +1,177 @@
+use crate::bigmath::{add_mod, sub_mod};
+#[cfg(feature = "std")]
+use crate::error::{ChunkError, Result};
+use core::fmt;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 const PRIME: u64 = 1_099_511_627_791; // A valid large prime
-const WINDOW_SIZE: usize = 64; // Arbitrary window size
+pub(crate) const WINDOW_SIZE: usize = 64; // Arbitrary window size
+/// Primes must stay below this, so `value * 256 + byte` cannot overflow.
+#[cfg(feature = "std")]
+const MAX_PRIME: u64 = 1 << 56;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct RabinFingerprint {
     value: u64,
     base: u64, // This is used to efficiently remove the oldest byte from the fingerprint
+    #[cfg_attr(feature = "serde", serde(default = "default_prime"))]
+    prime: u64,
+}
+
+#[cfg(feature = "serde")]
+fn default_prime() -> u64 {
+    PRIME
+}
+
+impl Default for RabinFingerprint {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RabinFingerprint {
-    pub(crate) fn new() -> Self {
+    pub fn new() -> Self {
         RabinFingerprint {
             value: 0,
             base: crate::bigmath::mod_pow(256, WINDOW_SIZE as u64, PRIME),
+            prime: PRIME,
         }
     }
 
+    /// Creates a fingerprint over a custom window size and prime modulus.
+    ///
+    /// Fails if `prime` is not prime or not below 2^56.
+    #[cfg(feature = "std")]
+    pub fn new_with_params(window_size: usize, prime: u64) -> Result<Self> {
+        if prime >= MAX_PRIME || !crate::bigmath::is_prime(prime) {
+            return Err(ChunkError::InvalidPrime(prime));
+        }
+        Ok(RabinFingerprint {
+            value: 0,
+            base: crate::bigmath::mod_pow(256, window_size as u64, prime),
+            prime,
+        })
+    }
+
     // Add a new byte to the fingerprint
-    pub(crate) fn push_byte(&mut self, byte: u8) {
-        self.value = (self.value * 256 + byte as u64) % PRIME;
+    pub fn push_byte(&mut self, byte: u8) {
+        // value < prime < 2^56, so shifting in a byte cannot overflow
+        self.value = add_mod(self.value * 256 % self.prime, byte as u64, self.prime);
     }
 
     // Remove the oldest byte from the fingerprint
     fn pop_byte(&mut self, byte: u8) {
-        self.value = (self.value + PRIME - (self.base * byte as u64 % PRIME)) % PRIME;
+        self.value = sub_mod(self.value, self.base * byte as u64 % self.prime, self.prime);
     }
 
     // Update the fingerprint with a new byte, pushing out the oldest byte if necessary
-    pub(crate) fn roll_byte(&mut self, old_byte: u8, new_byte: u8) {
-        self.pop_byte(old_byte);
+    pub fn roll_byte(&mut self, old_byte: u8, new_byte: u8) {
+        // `base` is the weight of the oldest byte once the new one is in
         self.push_byte(new_byte);
+        self.pop_byte(old_byte);
+    }
+
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+
+    pub fn prime(&self) -> u64 {
+        self.prime
+    }
+
+    /// Tells whether the fingerprint value is zero, as it is before any byte has been pushed.
+    pub fn is_zero(&self) -> bool {
+        self.value == 0
+    }
+
+    /// Returns a fingerprint with the same parameters and no bytes pushed yet.
+    pub fn reset(&self) -> Self {
+        RabinFingerprint { value: 0, ..*self }
+    }
+
+    /// Returns the fingerprint of `data` as a whole, same as pushing every byte into a new
+    /// fingerprint and reading its value.
+    #[inline(always)]
+    pub fn compute_for_slice(data: &[u8]) -> u64 {
+        data.iter().fold(0, |value, &byte| add_mod(value * 256 % PRIME, byte as u64, PRIME))
+    }
+
+    /// Returns the fingerprint of the whole file at `path`, same as pushing every byte of it
+    /// into a new fingerprint. On Unix the file is mapped into memory instead of read.
+    #[cfg(feature = "std")]
+    pub fn fingerprint_file(path: &str) -> Result<u64> {
+        #[cfg(unix)]
+        let mapped = crate::mmap::MappedFile::open_sequential(path)?;
+        #[cfg(unix)]
+        let bytes: &[u8] = mapped.as_deref().unwrap_or_default();
+        #[cfg(not(unix))]
+        let bytes = std::fs::read(path)?;
+        let mut fingerprint = RabinFingerprint::new();
+        for &byte in bytes.iter() {
+            fingerprint.push_byte(byte);
+        }
+        Ok(fingerprint.value())
+    }
+}
+
+impl fmt::Display for RabinFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RabinFP(value={}, base={})", self.value, self.base)
+    }
+}
+
+/// Gear values for every byte, from splitmix64 with a fixed seed so boundaries are stable.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// The gear hash FastCDC finds boundaries with, see
+/// [`CdcAlgorithm::FastCdc`](crate::CdcAlgorithm::FastCdc).
+///
+/// Every byte shifts the hash left by one and adds a fixed random value for the byte, so there is
+/// no window to roll: a byte has no effect on the hash once 64 more have been pushed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GearHash {
+    value: u64,
+}
+
+impl GearHash {
+    pub fn new() -> Self {
+        GearHash { value: 0 }
     }
 
-    pub(crate) fn value(&self) -> u64 {
+    #[inline(always)]
+    pub fn push_byte(&mut self, byte: u8) {
+        self.value = (self.value << 1).wrapping_add(GEAR[byte as usize]);
+    }
+
+    pub fn value(&self) -> u64 {
         self.value
     }
+
+    /// Returns the hash of `data` as a whole, same as pushing every byte into a new hash.
+    pub fn compute_for_slice(data: &[u8]) -> u64 {
+        let mut hash = GearHash::new();
+        for &byte in data.iter() {
+            hash.push_byte(byte);
+        }
+        hash.value()
+    }
 }
 
 #[cfg(test)]
@@ -61,4 +197,96 @@ mod tests {
             assert_ne!(fingerprint.value(), initial_fingerprint); // The rolled fingerprint should differ from the initial one
         }
     }
+
+    #[test]
+    fn test_roll_byte_fingerprints_window() {
+        let data: Vec<u8> = (0..200u32).map(|i| (i * 31 % 251) as u8).collect();
+        let mut fingerprint = RabinFingerprint::new();
+        for &byte in &data[..WINDOW_SIZE] {
+            fingerprint.push_byte(byte);
+        }
+        for i in 0..(data.len() - WINDOW_SIZE) {
+            fingerprint.roll_byte(data[i], data[i + WINDOW_SIZE]);
+            assert_eq!(fingerprint.value(), RabinFingerprint::compute_for_slice(&data[i + 1..i + 1 + WINDOW_SIZE]));
+        }
+    }
+
+    #[test]
+    fn test_gear_hash_forgets_old_bytes() {
+        let data: Vec<u8> = (0..200u32).map(|i| (i * 31 % 251) as u8).collect();
+        let mut hash = GearHash::new();
+        for (i, &byte) in data.iter().enumerate() {
+            hash.push_byte(byte);
+            let window = &data[(i + 1).saturating_sub(64)..=i];
+            assert_eq!(hash.value(), GearHash::compute_for_slice(window));
+        }
+        assert_ne!(GearHash::compute_for_slice(&data[..64]), GearHash::compute_for_slice(&data[1..65]));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_new_with_params() {
+        let custom = RabinFingerprint::new_with_params(WINDOW_SIZE, 1_000_000_007).unwrap();
+        assert_eq!(custom.prime(), 1_000_000_007);
+
+        let mut default = RabinFingerprint::new();
+        let mut same = RabinFingerprint::new_with_params(WINDOW_SIZE, PRIME).unwrap();
+        for byte in b"Lorem ipsum dolor sit amet".iter() {
+            default.push_byte(*byte);
+            same.push_byte(*byte);
+        }
+        assert_eq!(default.value(), same.value());
+
+        assert!(matches!(
+            RabinFingerprint::new_with_params(WINDOW_SIZE, 1_000_000_008),
+            Err(ChunkError::InvalidPrime(1_000_000_008))
+        ));
+        // Prime, but too large to fingerprint without overflowing
+        assert!(RabinFingerprint::new_with_params(WINDOW_SIZE, 18_446_744_073_709_551_557).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_display_and_is_zero() {
+        let mut fingerprint = RabinFingerprint::new_with_params(WINDOW_SIZE, 1_000_000_007).unwrap();
+        assert!(fingerprint.is_zero());
+        fingerprint.push_byte(42);
+        assert!(!fingerprint.is_zero());
+        let base = crate::bigmath::mod_pow(256, WINDOW_SIZE as u64, 1_000_000_007);
+        assert_eq!(fingerprint.to_string(), format!("RabinFP(value=42, base={})", base));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_fingerprint_file() {
+        let data = std::fs::read("./tests/data/CSmall.txt").unwrap();
+        assert_eq!(RabinFingerprint::fingerprint_file("./tests/data/CSmall.txt").unwrap(), RabinFingerprint::compute_for_slice(&data));
+        assert!(RabinFingerprint::fingerprint_file("./tests/data/missing.bin").is_err());
+    }
+
+    #[cfg(feature = "std")]
+    proptest::proptest! {
+        #[test]
+        fn test_fingerprint_file_matches_push_byte(data in proptest::collection::vec(proptest::prelude::any::<u8>(), 0..=1024)) {
+            let path = "./tests/tmp/fingerprint_file.bin";
+            std::fs::create_dir_all("./tests/tmp").unwrap();
+            std::fs::write(path, &data).unwrap();
+            let mut fingerprint = RabinFingerprint::new();
+            for &byte in data.iter() {
+                fingerprint.push_byte(byte);
+            }
+            proptest::prop_assert_eq!(RabinFingerprint::fingerprint_file(path).unwrap(), fingerprint.value());
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_compute_for_slice_matches_push_byte(data in proptest::collection::vec(proptest::prelude::any::<u8>(), 0..=1024)) {
+            let mut fingerprint = RabinFingerprint::new();
+            for &byte in data.iter() {
+                fingerprint.push_byte(byte);
+            }
+            proptest::prop_assert_eq!(RabinFingerprint::compute_for_slice(&data), fingerprint.value());
+        }
+    }
 }
@@ -0,0 +1,153 @@
+use crate::error::{ChunkError, Result};
+use crate::storage::Storage;
+use object_store::aws::AmazonS3Builder;
+use object_store::path::Path;
+use object_store::{ObjectStore, ObjectStoreExt, PutPayload};
+use std::sync::Arc;
+
+const MANIFEST_KEY: &str = "restore_info";
+
+/// Stores chunks and the manifest as objects below `prefix` in an S3-compatible object store.
+///
+/// Requests are run on a runtime of its own, so it must not be used from within an async context.
+#[derive(Debug)]
+pub struct S3Storage {
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl S3Storage {
+    /// Wraps any [`ObjectStore`], keeping objects below `prefix`.
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: &str) -> Result<S3Storage> {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        Ok(S3Storage {
+            store,
+            prefix: Path::from(prefix),
+            runtime,
+        })
+    }
+
+    /// Connects to `bucket` with the endpoint and credentials from the `AWS_*` environment variables.
+    pub fn from_env(bucket: &str, prefix: &str) -> Result<S3Storage> {
+        let store = AmazonS3Builder::from_env().with_bucket_name(bucket).build().map_err(storage_error)?;
+        S3Storage::new(Arc::new(store), prefix)
+    }
+
+    fn path(&self, name: &str) -> Path {
+        self.prefix.clone().join(name)
+    }
+
+    fn put(&self, name: &str, data: &[u8]) -> Result<()> {
+        let payload = PutPayload::from(data.to_vec());
+        self.runtime.block_on(self.store.put(&self.path(name), payload)).map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Result<Vec<u8>> {
+        self.runtime
+            .block_on(async {
+                let bytes = self.store.get(&self.path(name)).await?.bytes().await?;
+                Ok(bytes.to_vec())
+            })
+            .map_err(storage_error)
+    }
+}
+
+fn storage_error(e: object_store::Error) -> ChunkError {
+    ChunkError::Storage(Box::new(e))
+}
+
+impl Storage for S3Storage {
+    fn write_chunk(&self, name: &str, data: &[u8]) -> Result<()> {
+        self.put(name, data)
+    }
+
+    fn read_chunk(&self, name: &str) -> Result<Vec<u8>> {
+        self.get(name)
+    }
+
+    fn has_chunk(&self, name: &str) -> Result<bool> {
+        match self.runtime.block_on(self.store.head(&self.path(name))) {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(storage_error(e)),
+        }
+    }
+
+    fn chunk_size(&self, name: &str) -> Result<u64> {
+        let meta = self.runtime.block_on(self.store.head(&self.path(name))).map_err(storage_error)?;
+        Ok(meta.size)
+    }
+
+    fn write_manifest(&self, data: &[u8]) -> Result<()> {
+        self.put(MANIFEST_KEY, data)
+    }
+
+    fn read_manifest(&self) -> Result<Vec<u8>> {
+        self.get(MANIFEST_KEY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chunker;
+    use object_store::memory::InMemory;
+
+    #[test]
+    fn test_s3_storage_round_trip() {
+        let store = Arc::new(InMemory::new());
+        let storage = S3Storage::new(store.clone(), "backups/first").unwrap();
+        assert!(!storage.has_chunk("1.chunk").unwrap());
+        storage.write_chunk("1.chunk", b"chunk").unwrap();
+        assert!(storage.has_chunk("1.chunk").unwrap());
+        assert_eq!(storage.read_chunk("1.chunk").unwrap(), b"chunk");
+        assert!(storage.read_manifest().is_err());
+
+        // Objects of another prefix stay apart
+        let other = S3Storage::new(store, "backups/second").unwrap();
+        assert!(!other.has_chunk("1.chunk").unwrap());
+    }
+
+    #[test]
+    fn test_backup_to_object_store() {
+        let store = Arc::new(InMemory::new());
+        let path = "./tests/data/CSmall.txt".to_string();
+        Chunker::builder()
+            .storage(S3Storage::new(store.clone(), "backup").unwrap())
+            .compress_manifest(true)
+            .build()
+            .add_files(vec![path.clone()], "./tests/tmp/unused_s3_output")
+            .unwrap();
+        // Nothing goes to the local output path
+        assert!(std::fs::metadata("./tests/tmp/unused_s3_output").is_err());
+
+        let chunker = Chunker::builder().storage(S3Storage::new(store, "backup").unwrap()).build();
+        let mut restored = vec![];
+        chunker.restore_file_to_writer(&path, "", &mut restored).unwrap();
+        assert_eq!(restored, std::fs::read(&path).unwrap());
+        assert!(chunker.verify_all("").unwrap().is_empty());
+    }
+
+    /// Runs against the MinIO from `tests/docker-compose.yml`, see the instructions there. The
+    /// `s3` CI job starts it and runs this test.
+    #[test]
+    #[ignore]
+    fn test_backup_to_minio() {
+        let bucket = std::env::var("FILECHUNK_S3_BUCKET").unwrap_or_else(|_| "filechunk".to_string());
+        let path = "./tests/data/A.txt".to_string();
+        Chunker::builder()
+            .storage(S3Storage::from_env(&bucket, "test_backup_to_minio").unwrap())
+            .build()
+            .add_files(vec![path.clone()], "")
+            .unwrap();
+
+        let chunker = Chunker::builder()
+            .storage(S3Storage::from_env(&bucket, "test_backup_to_minio").unwrap())
+            .build();
+        let mut restored = vec![];
+        chunker.restore_file_to_writer(&path, "", &mut restored).unwrap();
+        assert_eq!(restored, std::fs::read(&path).unwrap());
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+const SHINGLE_SIZE: usize = 8;
+const NUM_HASHES: usize = 16;
+const ROWS_PER_BAND: usize = 4;
+/// Only shingles whose hash has these bits cleared are sampled, which keeps the cost per byte low
+/// while still picking the same shingles in similar files.
+const SAMPLE_MASK: u64 = 0xf;
+
+pub(crate) type Signature = [u64; NUM_HASHES];
+
+fn mix(mut x: u64) -> u64 {
+    // splitmix64 finalizer
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Computes the MinHash signature of the set of byte shingles in `bytes`.
+pub(crate) fn signature(bytes: &[u8]) -> Signature {
+    let mut signature = [u64::MAX; NUM_HASHES];
+    let mut add_shingle = |shingle: &[u8]| {
+        let hash = mix(shingle.iter().fold(0u64, |h, &b| (h << 8 | b as u64).rotate_left(3)));
+        if hash & SAMPLE_MASK != 0 {
+            return;
+        }
+        for (i, min) in signature.iter_mut().enumerate() {
+            *min = (*min).min(mix(hash ^ (i as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15)));
+        }
+    };
+    if bytes.len() < SHINGLE_SIZE {
+        add_shingle(bytes);
+    } else {
+        bytes.windows(SHINGLE_SIZE).for_each(add_shingle);
+    }
+    signature
+}
+
+/// Returns the order to process the files with `signatures` in.
+///
+/// Files sharing an LSH bucket in any band are put into the same group. Groups keep the order
+/// of their first file and files within a group keep their relative order.
+pub(crate) fn order_by_similarity(signatures: &[Signature]) -> Vec<usize> {
+    let mut group_of = vec![0; signatures.len()];
+    let mut buckets: HashMap<(usize, &[u64]), usize> = HashMap::new();
+    for (index, signature) in signatures.iter().enumerate() {
+        // Bands without any sampled shingle say nothing about similarity
+        let bands: Vec<(usize, &[u64])> = signature
+            .chunks(ROWS_PER_BAND)
+            .enumerate()
+            .filter(|(_, band)| band.iter().any(|&min| min != u64::MAX))
+            .collect();
+        let group = bands.iter().find_map(|band| buckets.get(band).copied()).unwrap_or(index);
+        for band in bands {
+            buckets.entry(band).or_insert(group);
+        }
+        group_of[index] = group;
+    }
+
+    let mut order: Vec<usize> = (0..signatures.len()).collect();
+    // Stable, so files within a group keep their order
+    order.sort_by_key(|&index| group_of[index]);
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::xorshift_bytes;
+
+    #[test]
+    fn test_order_by_similarity() {
+        let text: Vec<u8> = (0..2000).flat_map(|i| format!("line {} of the log\n", i).into_bytes()).collect();
+        let mut edited_text = text.clone();
+        edited_text[1000..1010].copy_from_slice(b"----------");
+        let noise = xorshift_bytes(1, 30_000);
+        let mut edited_noise = noise.clone();
+        edited_noise[20_000..20_100].fill(0);
+
+        let signatures = [
+            signature(&text),
+            signature(&noise),
+            signature(&edited_text),
+            signature(&edited_noise),
+            signature(&xorshift_bytes(2, 30_000)),
+        ];
+        assert_eq!(order_by_similarity(&signatures), vec![0, 2, 1, 3, 4]);
+    }
+
+    #[test]
+    fn test_inputs_without_samples_stay_in_place() {
+        let text: Vec<u8> = (0..2000).flat_map(|i| format!("line {} of the log\n", i).into_bytes()).collect();
+        let signatures = [signature(b""), signature(&text), signature(b"x"), signature(&text)];
+        assert_eq!(order_by_similarity(&signatures), vec![0, 1, 3, 2]);
+        assert_eq!(order_by_similarity(&[]), Vec::<usize>::new());
+    }
+}
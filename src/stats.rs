@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+/// Summary of a single `add_files` run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChunkStats {
+    pub files_processed: usize,
+    pub bytes_read: u64,
+    pub chunks_written: usize,
+    /// Chunks that were already stored, either on disk or as part of an identical file.
+    pub chunks_reused: usize,
+    pub bytes_written_compressed: u64,
+    /// `1.0 - bytes_written_compressed / bytes_read`, zero if nothing was read.
+    pub deduplication_ratio: f64,
+    pub elapsed: Duration,
+    /// Paths skipped by the filter of `add_files_with_filter`.
+    pub excluded_files: Vec<String>,
+    /// Paths left unread for a later run by `max_manifest_entries`, sorted.
+    pub deferred_files: Vec<String>,
+    /// Files that shrank since the previous run into the same output path, sorted by path.
+    pub truncated_files: Vec<TruncatedFile>,
+    /// Repeated fingerprint windows, only counted with `window_dedup_stats`.
+    pub window_dedup: WindowDedupStats,
+    /// How much smaller the chunks of a backup rewritten by `Chunker::compact` are than before,
+    /// zero if they are not.
+    pub bytes_saved: u64,
+}
+
+/// How often the 64 byte windows the Rabin fingerprint rolls over repeat within a run, see
+/// `ChunkerBuilder::window_dedup_stats`. Windows are told apart by their fingerprint, so
+/// colliding ones count as repeated too.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WindowDedupStats {
+    /// Windows of the chunked files, one starting at every byte but the last 63 of a file.
+    pub windows: u64,
+    /// Windows whose content appeared earlier in the run.
+    pub repeated_windows: u64,
+}
+
+/// A file whose content changed and got smaller since the previous run, as found by `add_files`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TruncatedFile {
+    pub path: String,
+    pub previous_size: u64,
+    pub current_size: u64,
+}
+
+impl ChunkStats {
+    /// Formats the stats in the Prometheus text exposition format, every sample labeled with
+    /// `job` and `instance`.
+    pub fn to_prometheus_text(&self, job: &str, instance: &str) -> String {
+        let labels = format!("{{job=\"{}\",instance=\"{}\"}}", escape_label(job), escape_label(instance));
+        let metrics: [(&str, &str, &str, f64); 8] = [
+            ("filechunk_files_processed_total", "counter", "Files chunked or found unchanged.", self.files_processed as f64),
+            ("filechunk_bytes_read_total", "counter", "Bytes of the files read.", self.bytes_read as f64),
+            ("filechunk_chunks_written_total", "counter", "Chunk files written.", self.chunks_written as f64),
+            ("filechunk_chunks_reused_total", "counter", "Chunks that were already stored.", self.chunks_reused as f64),
+            ("filechunk_bytes_written_compressed_total", "counter", "Bytes of the chunk files written.", self.bytes_written_compressed as f64),
+            ("filechunk_files_excluded_total", "counter", "Paths skipped by the filter.", self.excluded_files.len() as f64),
+            ("filechunk_deduplication_ratio", "gauge", "1 - bytes written / bytes read.", self.deduplication_ratio),
+            ("filechunk_elapsed_seconds", "gauge", "Duration of the run.", self.elapsed.as_secs_f64()),
+        ];
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics {
+            text.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n{}{} {}\n", name, help, name, kind, name, labels, value));
+        }
+        text
+    }
+
+    pub(crate) fn finish(mut self, elapsed: Duration) -> ChunkStats {
+        self.elapsed = elapsed;
+        self.deduplication_ratio = if self.bytes_read == 0 {
+            0.0
+        } else {
+            1.0 - (self.bytes_written_compressed as f64 / self.bytes_read as f64)
+        };
+        self
+    }
+}
+
+/// Escapes a Prometheus label value.
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Prediction of what an `add_files` run would store, as returned by `Chunker::estimate_chunks`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChunkEstimate {
+    pub estimated_chunks: usize,
+    /// Bytes that end up in chunks.
+    pub estimated_unique_bytes: u64,
+    /// Bytes of duplicate files and repeated chunks, which take no extra space.
+    pub estimated_duplicate_bytes: u64,
+    pub estimated_files: usize,
+    /// Compressed to uncompressed size of the first 64 KiB of the first chunk of every file,
+    /// averaged. A heuristic, 1.0 when no file was chunked.
+    pub estimated_compression_ratio: f64,
+}
+
+/// Chunks holding content of several files, as returned by `Chunker::dedup_report`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DedupReport {
+    /// Sorted by `savings_bytes`, largest first.
+    pub shared_chunks: Vec<SharedChunk>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SharedChunk {
+    pub chunk_name: String,
+    /// Files using the same bytes of the chunk, sorted.
+    pub referenced_by: Vec<String>,
+    /// `(referenced_by.len() - 1) * ` the stored size of the chunk.
+    pub savings_bytes: u64,
+}
+
+/// Summary of a stored manifest, as returned by `Chunker::stats`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ManifestStats {
+    pub files: usize,
+    pub chunks: usize,
+    pub average_chunks_per_file: f64,
+}
+
+/// What changed between the manifests of two backups, as returned by `Chunker::diff`.
+///
+/// Every list is sorted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ManifestDiff {
+    pub added_files: Vec<String>,
+    pub removed_files: Vec<String>,
+    /// Paths in both manifests whose BLAKE3 hash differs.
+    pub modified_files: Vec<String>,
+    /// Chunks the new manifest references and the old one doesn't.
+    pub new_chunks: Vec<String>,
+    /// Chunks the old manifest references and the new one doesn't, which a `gc` would delete.
+    pub removed_chunks: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prometheus_text() {
+        let stats = ChunkStats {
+            files_processed: 3,
+            bytes_read: 104_857_600,
+            chunks_written: 10,
+            deduplication_ratio: 0.25,
+            elapsed: Duration::from_millis(1500),
+            ..Default::default()
+        };
+        let text = stats.to_prometheus_text("backup", "server \"1\"");
+        let mut types = std::collections::HashMap::new();
+        let mut samples = std::collections::HashMap::new();
+        for line in text.lines() {
+            if let Some(comment) = line.strip_prefix("# ") {
+                let mut parts = comment.splitn(3, ' ');
+                let (keyword, name, rest) = (parts.next().unwrap(), parts.next().unwrap(), parts.next().unwrap());
+                assert!(keyword == "HELP" || keyword == "TYPE", "{}", line);
+                if keyword == "TYPE" {
+                    assert!(rest == "counter" || rest == "gauge", "{}", line);
+                    types.insert(name.to_string(), rest.to_string());
+                }
+                continue;
+            }
+            let (series, value) = line.rsplit_once(' ').unwrap();
+            let (name, labels) = series.split_once('{').unwrap();
+            assert!(name.bytes().all(|b| b.is_ascii_lowercase() || b == b'_'), "{}", line);
+            assert!(types.contains_key(name), "{} has no TYPE", name);
+            assert_eq!(labels, "job=\"backup\",instance=\"server \\\"1\\\"\"}");
+            samples.insert(name.to_string(), value.parse::<f64>().unwrap());
+        }
+        assert_eq!(samples.len(), 8);
+        assert_eq!(samples["filechunk_bytes_read_total"], 104_857_600.0);
+        assert_eq!(samples["filechunk_deduplication_ratio"], 0.25);
+        assert_eq!(samples["filechunk_elapsed_seconds"], 1.5);
+        assert_eq!(types["filechunk_files_processed_total"], "counter");
+    }
+}
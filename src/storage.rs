@@ -0,0 +1,230 @@
+use crate::chunkstream::find_manifest;
+use crate::error::Result;
+use crate::reflink::copy_file;
+use std::fmt;
+use std::fs;
+use std::io;
+
+/// Backend the chunks and the manifest of a backup are kept in.
+///
+/// Chunks are addressed by their file name, such as `<prefix>_<name>.chunk`. The manifest is
+/// stored as written, Zstd compressed or not.
+pub trait Storage: fmt::Debug + Send + Sync {
+    fn write_chunk(&self, name: &str, data: &[u8]) -> Result<()>;
+
+    fn read_chunk(&self, name: &str) -> Result<Vec<u8>>;
+
+    /// Tells whether a chunk is stored under `name`, so it does not need to be written again.
+    fn has_chunk(&self, name: &str) -> Result<bool> {
+        Ok(self.read_chunk(name).is_ok())
+    }
+
+    /// Returns the size of the chunk stored under `name`, as it is stored.
+    fn chunk_size(&self, name: &str) -> Result<u64> {
+        Ok(self.read_chunk(name)?.len() as u64)
+    }
+
+    fn write_manifest(&self, data: &[u8]) -> Result<()>;
+
+    fn read_manifest(&self) -> Result<Vec<u8>>;
+}
+
+/// Stores chunk files and `restore_info.yaml` in a local directory, the default.
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    dir: String,
+}
+
+impl LocalStorage {
+    pub fn new(dir: impl Into<String>) -> LocalStorage {
+        LocalStorage { dir: dir.into() }
+    }
+
+    fn path(&self, name: &str) -> String {
+        format!("{}/{}", self.dir, name)
+    }
+}
+
+impl Storage for LocalStorage {
+    fn write_chunk(&self, name: &str, data: &[u8]) -> Result<()> {
+        Ok(fs::write(self.path(name), data)?)
+    }
+
+    fn read_chunk(&self, name: &str) -> Result<Vec<u8>> {
+        Ok(fs::read(self.path(name))?)
+    }
+
+    fn has_chunk(&self, name: &str) -> Result<bool> {
+        Ok(std::path::Path::new(&self.path(name)).exists())
+    }
+
+    fn chunk_size(&self, name: &str) -> Result<u64> {
+        Ok(fs::metadata(self.path(name))?.len())
+    }
+
+    fn write_manifest(&self, data: &[u8]) -> Result<()> {
+        let (name, stale) = if is_zstd(data) {
+            ("restore_info.yaml.zst", "restore_info.yaml")
+        } else {
+            ("restore_info.yaml", "restore_info.yaml.zst")
+        };
+        fs::write(self.path(name), data)?;
+        // The other variant would be found first, or shadow this one
+        let _ = fs::remove_file(self.path(stale));
+        Ok(())
+    }
+
+    fn read_manifest(&self) -> Result<Vec<u8>> {
+        let (path, _) = find_manifest(&self.dir, "restore_info")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No manifest in {}", self.dir)))?;
+        Ok(fs::read(path)?)
+    }
+}
+
+/// Keeps chunks in several local directories, see [`ChunkerBuilder::mirror_paths`](crate::ChunkerBuilder::mirror_paths).
+///
+/// Writes go to every directory, and only fail if none of them could be written. Reads are
+/// served by the first directory that has the chunk.
+#[derive(Debug, Clone)]
+pub(crate) struct MirroredStorage {
+    locations: Vec<LocalStorage>,
+    /// Clone chunks written to the first location into the others, see
+    /// [`ChunkerBuilder::enable_reflink`](crate::ChunkerBuilder::enable_reflink).
+    reflink: bool,
+}
+
+impl MirroredStorage {
+    pub(crate) fn new(primary: &str, mirrors: &[String], reflink: bool) -> MirroredStorage {
+        let locations = std::iter::once(primary).chain(mirrors.iter().map(String::as_str)).map(LocalStorage::new).collect();
+        MirroredStorage { locations, reflink }
+    }
+
+    /// Runs `write` on every location, warning about the ones that fail.
+    fn write_all(&self, what: &str, mut write: impl FnMut(&LocalStorage) -> Result<()>) -> Result<()> {
+        let mut written = false;
+        let mut first_error = None;
+        for location in self.locations.iter() {
+            match write(location) {
+                Ok(()) => written = true,
+                Err(e) => {
+                    tracing::warn!(dir = %location.dir, error = %e, "Unable to write {} to mirror", what);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match (written, first_error) {
+            (false, Some(e)) => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the result of `read` for the first location it succeeds for, or else the error of
+    /// the last one.
+    fn read_first<T>(&self, read: impl Fn(&LocalStorage) -> Result<T>) -> Result<T> {
+        let mut last_error = None;
+        for location in self.locations.iter() {
+            match read(location) {
+                Ok(value) => return Ok(value),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.expect("A mirrored storage has at least one location"))
+    }
+}
+
+impl Storage for MirroredStorage {
+    fn write_chunk(&self, name: &str, data: &[u8]) -> Result<()> {
+        // The first location written to is cloned into the others
+        let mut written: Option<String> = None;
+        self.write_all(name, |location| match written.as_deref().filter(|_| self.reflink) {
+            Some(from) => Ok(copy_file(from, &location.path(name), true)?),
+            None => {
+                location.write_chunk(name, data)?;
+                written = Some(location.path(name));
+                Ok(())
+            }
+        })
+    }
+
+    fn read_chunk(&self, name: &str) -> Result<Vec<u8>> {
+        self.read_first(|location| location.read_chunk(name))
+    }
+
+    /// Only chunks in every location are not written again, so mirrors added later catch up.
+    fn has_chunk(&self, name: &str) -> Result<bool> {
+        for location in self.locations.iter() {
+            if !location.has_chunk(name)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn chunk_size(&self, name: &str) -> Result<u64> {
+        self.read_first(|location| location.chunk_size(name))
+    }
+
+    fn write_manifest(&self, data: &[u8]) -> Result<()> {
+        self.write_all("the manifest", |location| location.write_manifest(data))
+    }
+
+    fn read_manifest(&self) -> Result<Vec<u8>> {
+        self.read_first(LocalStorage::read_manifest)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct NullStorage;
+
+impl Storage for NullStorage {
+    fn write_chunk(&self, _name: &str, _data: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    fn read_chunk(&self, name: &str) -> Result<Vec<u8>> {
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("{} was not stored", name)).into())
+    }
+
+    fn has_chunk(&self, _name: &str) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn write_manifest(&self, _data: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    fn read_manifest(&self) -> Result<Vec<u8>> {
+        Err(io::Error::new(io::ErrorKind::NotFound, "No manifest was stored").into())
+    }
+}
+
+pub(crate) fn is_zstd(data: &[u8]) -> bool {
+    data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_storage() {
+        let dir = "./tests/tmp/local_storage";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let storage = LocalStorage::new(dir);
+
+        assert!(!storage.has_chunk("1.chunk").unwrap());
+        storage.write_chunk("1.chunk", b"chunk").unwrap();
+        assert!(storage.has_chunk("1.chunk").unwrap());
+        assert_eq!(storage.read_chunk("1.chunk").unwrap(), b"chunk");
+        assert!(storage.read_chunk("2.chunk").is_err());
+
+        assert!(storage.read_manifest().is_err());
+        storage.write_manifest(b"files: {}").unwrap();
+        assert_eq!(storage.read_manifest().unwrap(), b"files: {}");
+        let compressed = zstd::encode_all(&b"files: {}"[..], 3).unwrap();
+        storage.write_manifest(&compressed).unwrap();
+        assert_eq!(storage.read_manifest().unwrap(), compressed);
+        assert!(fs::metadata(format!("{}/restore_info.yaml", dir)).is_err());
+    }
+}
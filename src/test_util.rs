@@ -0,0 +1,13 @@
+/// Returns `len` pseudo-random bytes of a xorshift generator started at `seed`, which must not be
+/// zero. The bytes are incompressible and the same on every run.
+pub fn xorshift_bytes(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 24) as u8
+        })
+        .collect()
+}
@@ -0,0 +1,61 @@
+/// Name of the plain text listing next to the manifest, see
+/// [`ChunkerBuilder::text_manifest`](crate::ChunkerBuilder::text_manifest).
+pub(crate) const TEXT_MANIFEST_FILE: &str = "MANIFEST.txt";
+
+/// A file as listed in `MANIFEST.txt`.
+pub(crate) struct TextManifestEntry<'a> {
+    pub(crate) path: &'a str,
+    pub(crate) size: u64,
+    pub(crate) hash: &'a str,
+    /// Modification time in seconds since the Unix epoch, if it was recorded.
+    pub(crate) mtime: Option<u64>,
+}
+
+/// Lists `entries` sorted by path, one per line with tab-separated path, size in bytes, BLAKE3
+/// hash and modification time in UTC, below a header line naming the columns.
+pub(crate) fn render(mut entries: Vec<TextManifestEntry>) -> String {
+    entries.sort_unstable_by(|a, b| a.path.cmp(b.path));
+    let mut text = String::from("# path\tsize\tblake3\tmodified\n");
+    for entry in entries.iter() {
+        let modified = entry.mtime.map_or_else(|| "-".to_string(), format_utc);
+        text.push_str(&format!("{}\t{}\t{}\t{}\n", entry.path, entry.size, entry.hash, modified));
+    }
+    text
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 UTC timestamp.
+fn format_utc(secs: u64) -> String {
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01, in 400 year eras starting on March 1st
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3_600,
+        secs_of_day % 3_600 / 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_utc(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_utc(1_500_000_000), "2017-07-14T02:40:00Z");
+        assert_eq!(format_utc(4_102_444_799), "2099-12-31T23:59:59Z");
+    }
+}
@@ -0,0 +1,235 @@
+use crate::error::Result;
+use crate::storage::Storage;
+use crossbeam_channel::Sender;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// A chunk file name and its contents.
+type Upload = (String, Arc<Vec<u8>>);
+
+/// Writes chunks to a storage on worker threads, see
+/// [`ChunkerBuilder::concurrent_chunk_uploads`](crate::ChunkerBuilder::concurrent_chunk_uploads).
+///
+/// Writing a chunk only queues it, waiting while as many chunks as there are workers are queued
+/// already. Queued chunks are read back from memory, everything else goes straight to the
+/// storage.
+pub(crate) struct UploadQueue {
+    storage: Arc<dyn Storage>,
+    jobs: Mutex<Option<Sender<Upload>>>,
+    workers: Mutex<Vec<JoinHandle<Result<()>>>>,
+    /// Chunks queued or being written, by name.
+    pending: Arc<Mutex<HashMap<String, Arc<Vec<u8>>>>>,
+}
+
+impl UploadQueue {
+    pub(crate) fn new(storage: Arc<dyn Storage>, uploads: usize) -> UploadQueue {
+        let (jobs, queue) = crossbeam_channel::bounded::<Upload>(uploads);
+        let pending = Arc::new(Mutex::new(HashMap::new()));
+        let workers = (0..uploads)
+            .map(|_| {
+                let queue = queue.clone();
+                let storage = storage.clone();
+                let pending = pending.clone();
+                std::thread::spawn(move || {
+                    for (name, data) in queue.iter() {
+                        storage.write_chunk(&name, &data)?;
+                        pending.lock().unwrap().remove(&name);
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        UploadQueue {
+            storage,
+            jobs: Mutex::new(Some(jobs)),
+            workers: Mutex::new(workers),
+            pending,
+        }
+    }
+
+    /// Waits for all queued chunks to be written, returning the first error of a worker.
+    pub(crate) fn finish(&self) -> Result<()> {
+        self.jobs.lock().unwrap().take();
+        let mut result = Ok(());
+        for worker in self.workers.lock().unwrap().drain(..) {
+            let written = worker.join().expect("Upload worker panicked");
+            if result.is_ok() {
+                result = written;
+            }
+        }
+        result
+    }
+}
+
+impl fmt::Debug for UploadQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UploadQueue")
+            .field("storage", &self.storage)
+            .field("pending", &self.pending.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl Storage for UploadQueue {
+    fn write_chunk(&self, name: &str, data: &[u8]) -> Result<()> {
+        let jobs = self.jobs.lock().unwrap().clone();
+        let Some(jobs) = jobs else {
+            return self.storage.write_chunk(name, data);
+        };
+        let data = Arc::new(data.to_vec());
+        self.pending.lock().unwrap().insert(name.to_string(), data.clone());
+        if jobs.send((name.to_string(), data)).is_err() {
+            // Every worker stopped at an error
+            self.finish()?;
+            return Err(io::Error::other("Chunk uploads stopped").into());
+        }
+        Ok(())
+    }
+
+    fn read_chunk(&self, name: &str) -> Result<Vec<u8>> {
+        if let Some(data) = self.pending.lock().unwrap().get(name) {
+            return Ok(data.to_vec());
+        }
+        self.storage.read_chunk(name)
+    }
+
+    fn has_chunk(&self, name: &str) -> Result<bool> {
+        if self.pending.lock().unwrap().contains_key(name) {
+            return Ok(true);
+        }
+        self.storage.has_chunk(name)
+    }
+
+    fn chunk_size(&self, name: &str) -> Result<u64> {
+        if let Some(data) = self.pending.lock().unwrap().get(name) {
+            return Ok(data.len() as u64);
+        }
+        self.storage.chunk_size(name)
+    }
+
+    fn write_manifest(&self, data: &[u8]) -> Result<()> {
+        self.storage.write_manifest(data)
+    }
+
+    fn read_manifest(&self) -> Result<Vec<u8>> {
+        self.storage.read_manifest()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalStorage;
+    use crate::Chunker;
+    use std::fs;
+    use std::time::Duration;
+
+    /// Takes a while for every chunk written, and tracks how many are written at once.
+    #[derive(Debug, Clone)]
+    struct SlowStorage {
+        inner: LocalStorage,
+        writing: Arc<Mutex<usize>>,
+        most_writing: Arc<Mutex<usize>>,
+    }
+
+    impl Storage for SlowStorage {
+        fn write_chunk(&self, name: &str, data: &[u8]) -> Result<()> {
+            {
+                let mut writing = self.writing.lock().unwrap();
+                *writing += 1;
+                let mut most = self.most_writing.lock().unwrap();
+                *most = (*most).max(*writing);
+            }
+            std::thread::sleep(Duration::from_millis(20));
+            *self.writing.lock().unwrap() -= 1;
+            if name.starts_with("fail") {
+                return Err(io::Error::other("upload failed").into());
+            }
+            self.inner.write_chunk(name, data)
+        }
+        fn read_chunk(&self, name: &str) -> Result<Vec<u8>> {
+            self.inner.read_chunk(name)
+        }
+        fn write_manifest(&self, data: &[u8]) -> Result<()> {
+            self.inner.write_manifest(data)
+        }
+        fn read_manifest(&self) -> Result<Vec<u8>> {
+            self.inner.read_manifest()
+        }
+    }
+
+    fn slow_storage(dir: &str) -> SlowStorage {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        SlowStorage {
+            inner: LocalStorage::new(dir),
+            writing: Arc::new(Mutex::new(0)),
+            most_writing: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    #[test]
+    fn test_uploads_run_concurrently() {
+        let dir = "./tests/tmp/upload_queue";
+        let storage = slow_storage(dir);
+        let queue = UploadQueue::new(Arc::new(storage.clone()), 4);
+        for i in 0..16 {
+            queue.write_chunk(&format!("{}.chunk", i), &[i as u8]).unwrap();
+            assert!(queue.has_chunk(&format!("{}.chunk", i)).unwrap());
+        }
+        queue.finish().unwrap();
+        for i in 0..16 {
+            assert_eq!(fs::read(format!("{}/{}.chunk", dir, i)).unwrap(), [i as u8]);
+        }
+        let most = *storage.most_writing.lock().unwrap();
+        assert!((2..=4).contains(&most), "{} uploads at once", most);
+    }
+
+    #[test]
+    fn test_queued_chunks_are_readable() {
+        let queue = UploadQueue::new(Arc::new(slow_storage("./tests/tmp/upload_queue_reads")), 1);
+        for i in 0..4 {
+            queue.write_chunk(&format!("{}.chunk", i), &[i as u8; 8]).unwrap();
+        }
+        // The last chunks are still queued behind the slow first one
+        for i in 0..4 {
+            assert_eq!(queue.read_chunk(&format!("{}.chunk", i)).unwrap(), [i as u8; 8]);
+            assert_eq!(queue.chunk_size(&format!("{}.chunk", i)).unwrap(), 8);
+        }
+        queue.finish().unwrap();
+        assert_eq!(queue.read_chunk("3.chunk").unwrap(), [3; 8]);
+    }
+
+    #[test]
+    fn test_failed_upload_is_returned() {
+        let queue = UploadQueue::new(Arc::new(slow_storage("./tests/tmp/upload_queue_failure")), 2);
+        queue.write_chunk("1.chunk", b"chunk").unwrap();
+        queue.write_chunk("fail.chunk", b"chunk").unwrap();
+        assert!(queue.finish().is_err());
+    }
+
+    #[test]
+    fn test_backup_with_concurrent_uploads() {
+        let storage = slow_storage("./tests/tmp/concurrent_uploads");
+        let paths = vec!["./tests/data/A.txt".to_string(), "./tests/data/B100MB.bin".to_string()];
+        let stats = Chunker::builder()
+            .storage(storage.clone())
+            .concurrent_chunk_uploads(4)
+            .build()
+            .add_files(paths.clone(), "")
+            .unwrap();
+        assert!(stats.chunks_written > 2);
+        assert!(*storage.most_writing.lock().unwrap() > 1);
+
+        let chunker = Chunker::builder().storage(storage).build();
+        assert!(chunker.verify_all("").unwrap().is_empty());
+        for path in paths.iter() {
+            let mut restored = vec![];
+            chunker.restore_file_to_writer(path, "", &mut restored).unwrap();
+            assert_eq!(&restored, &fs::read(path).unwrap());
+        }
+    }
+}
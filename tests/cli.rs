@@ -0,0 +1,127 @@
+#![cfg(feature = "cli")]
+
+use assert_cmd::Command;
+use std::fs;
+
+fn fresh_dir(path: &str) -> String {
+    let _ = fs::remove_dir_all(path);
+    fs::create_dir_all(path).unwrap();
+    path.to_string()
+}
+
+fn filechunk() -> Command {
+    Command::cargo_bin("filechunk").unwrap()
+}
+
+/// Backs up a small source tree to `<root>/chunks` and returns the source and chunk directories.
+fn backup(root: &str) -> (String, String) {
+    let source = fresh_dir(&format!("{}/source", root));
+    let chunks = fresh_dir(&format!("{}/chunks", root));
+    fs::create_dir_all(format!("{}/nested", source)).unwrap();
+    fs::copy("./tests/data/A.txt", format!("{}/A.txt", source)).unwrap();
+    fs::copy("./tests/data/CSmall.txt", format!("{}/nested/CSmall.txt", source)).unwrap();
+    filechunk().args(["chunk", &source, &chunks]).assert().success();
+    (source, chunks)
+}
+
+#[test]
+fn test_chunk_and_restore() {
+    let (source, chunks) = backup("./tests/tmp/cli_restore");
+    let restored = fresh_dir("./tests/tmp/cli_restore/restored");
+    filechunk().args(["restore", &chunks, &restored]).assert().success();
+
+    for file in ["A.txt", "nested/CSmall.txt"] {
+        let original = fs::read(format!("{}/{}", source, file)).unwrap();
+        let restored_path = format!("{}/{}/{}", restored, source.trim_start_matches("./"), file);
+        assert_eq!(fs::read(restored_path).unwrap(), original);
+    }
+}
+
+#[test]
+fn test_fastcdc_and_compression_flags() {
+    let source = fresh_dir("./tests/tmp/cli_fastcdc/source");
+    let chunks = fresh_dir("./tests/tmp/cli_fastcdc/chunks");
+    let text: Vec<u8> = (0..10_000).flat_map(|i| format!("Line {}\n", i).into_bytes()).collect();
+    fs::write(format!("{}/lorem.txt", source), &text).unwrap();
+    let sizes = ["--min-chunk", "1024", "--avg-chunk", "4096", "--max-chunk", "8192"];
+    filechunk()
+        .args(["chunk", &source, &chunks, "--cdc", "fastcdc", "--compression", "zstd"])
+        .args(sizes)
+        .assert()
+        .success();
+
+    // No chunk is larger than 8 KiB, and no line repeats another
+    let output = filechunk().args(["list", &chunks]).assert().success().get_output().stdout.clone();
+    let output = String::from_utf8(output).unwrap();
+    let count: u32 = output.trim().rsplit(": ").next().unwrap().trim_end_matches(" chunk(s)").parse().unwrap();
+    assert!(count as usize >= text.len().div_ceil(8192), "{}", output);
+    for entry in fs::read_dir(&chunks).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "chunk") {
+            let bytes = fs::read(&path).unwrap();
+            assert!(bytes.windows(4).any(|magic| magic == [0x28, 0xb5, 0x2f, 0xfd]), "{} is not Zstd", path.display());
+        }
+    }
+    filechunk().args(["verify", &chunks]).assert().success();
+
+    let restored = fresh_dir("./tests/tmp/cli_fastcdc/restored");
+    filechunk().args(["restore", &chunks, &restored]).assert().success();
+    let restored_path = format!("{}/{}/lorem.txt", restored, source.trim_start_matches("./"));
+    assert_eq!(fs::read(restored_path).unwrap(), text);
+
+    // Sizes FastCDC cannot work with fail the backup
+    filechunk()
+        .args(["chunk", &source, &chunks, "--cdc", "fastcdc", "--min-chunk", "8192", "--max-chunk", "1024"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_list() {
+    let (source, chunks) = backup("./tests/tmp/cli_list");
+    let output = filechunk().args(["list", &chunks]).assert().success().get_output().stdout.clone();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains(&format!("{}/A.txt", source)));
+    assert!(output.contains(&format!("{}/nested/CSmall.txt", source)));
+}
+
+#[test]
+fn test_verify() {
+    let (_, chunks) = backup("./tests/tmp/cli_verify");
+    filechunk().args(["verify", &chunks]).assert().success();
+
+    let chunk_path = fs::read_dir(&chunks)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "chunk"))
+        .unwrap();
+    let mut chunk_bytes = fs::read(&chunk_path).unwrap();
+    let last = chunk_bytes.len() - 1;
+    chunk_bytes[last] ^= 0xff;
+    fs::write(&chunk_path, chunk_bytes).unwrap();
+    filechunk().args(["verify", &chunks]).assert().failure();
+}
+
+#[test]
+fn test_gc() {
+    let (_, chunks) = backup("./tests/tmp/cli_gc");
+    let stray = format!("{}/42.chunk", chunks);
+    fs::write(&stray, b"stray").unwrap();
+    filechunk().args(["gc", &chunks]).assert().success();
+    assert!(fs::metadata(&stray).is_err());
+    filechunk().args(["verify", &chunks]).assert().success();
+}
+
+#[test]
+fn test_fingerprint() {
+    let output = filechunk().args(["fingerprint", "./tests/data/CSmall.txt"]).assert().success().get_output().stdout.clone();
+    let expected = file_chunk::RabinFingerprint::compute_for_slice(&fs::read("./tests/data/CSmall.txt").unwrap());
+    assert_eq!(String::from_utf8(output).unwrap().trim(), expected.to_string());
+    filechunk().args(["fingerprint", "./tests/data/missing.bin"]).assert().failure();
+}
+
+#[test]
+fn test_missing_manifest_fails() {
+    let empty = fresh_dir("./tests/tmp/cli_missing_manifest");
+    filechunk().args(["list", &empty]).assert().failure();
+}
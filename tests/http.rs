@@ -0,0 +1,38 @@
+#![cfg(feature = "reqwest")]
+
+use file_chunk::Chunker;
+use std::fs;
+
+fn fresh_dir(path: &str) -> String {
+    let _ = fs::remove_dir_all(path);
+    fs::create_dir_all(path).unwrap();
+    path.to_string()
+}
+
+#[test]
+fn test_add_files_from_url() {
+    let mut server = mockito::Server::new();
+    let body = fs::read("./tests/data/CSmall.txt").unwrap();
+    let mock = server.mock("GET", "/files/CSmall.txt").with_body(&body).create();
+    let url = format!("{}/files/CSmall.txt", server.url());
+
+    let output = fresh_dir("./tests/tmp/http/output");
+    let stats = Chunker::new()
+        .add_files(vec![url.clone(), "./tests/data/A.txt".to_string()], &output)
+        .unwrap();
+    mock.assert();
+    assert_eq!(stats.files_processed, 2);
+    assert_eq!(stats.bytes_read, body.len() as u64 + 27);
+
+    // The URL is the file name
+    let files: Vec<String> = Chunker::new().list_files(&output).unwrap().into_iter().map(|(name, _)| name).collect();
+    assert!(files.contains(&url));
+    let mut restored = vec![];
+    Chunker::new().restore_file_to_writer(&url, &output, &mut restored).unwrap();
+    assert_eq!(restored, body);
+
+    let restored_dir = fresh_dir("./tests/tmp/http/restored");
+    Chunker::new().restore_file(&url, &output, &restored_dir).unwrap();
+    let host = url.trim_start_matches("http://");
+    assert_eq!(fs::read(format!("{}/{}", restored_dir, host)).unwrap(), body);
+}